@@ -0,0 +1,542 @@
+//! The subcommands of the CLI, parsing the arguments is left to main.
+
+use crate::feed_reader::config::{self, Config, Feed, OutputMode};
+use crate::feed_reader::config_editor::ConfigEditor;
+use crate::feed_reader::{
+    self, discovery, opml, pool, schedule, FeedReader, FetchError, FetchOutcome,
+};
+use crate::storage::Storage;
+use crate::transformer::digest::{build_due_digest, rebuild_digest};
+use crate::transformer::entry_to_epub;
+use crate::transformer::images::ImageFetcher;
+use crate::transformer::newspaper::{build_due_edition, next_edition_due, rebuild_edition};
+use crate::transformer::style::Style;
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::{thread, time::Duration};
+
+/// MIN_SLEEP is the least run sleeps between rounds, so a feed that stays
+/// due no matter what can't keep it spinning.
+const MIN_SLEEP: Duration = Duration::from_secs(60);
+
+/// run polls the feeds in `only_feeds`, or every feed if it is empty, and
+/// writes EPUBs for whatever is new. With `once` every selected feed is
+/// fetched right away and run returns afterwards, otherwise it keeps polling
+/// each feed on its own schedule.
+pub fn run(config: Config, only_feeds: &[String], once: bool) -> Result<()> {
+    check_feeds_exist(&config, only_feeds)?;
+    let selected = |feed_name: &str| {
+        only_feeds.is_empty() || only_feeds.iter().any(|only_feed| only_feed == feed_name)
+    };
+
+    let feed_reader_v2 = FeedReader::new(config).context("failed to set up feed reader")?;
+    let image_fetcher = new_image_fetcher(&feed_reader_v2);
+    let image_fetcher = feed_reader_v2.config.images.embed.then_some(&image_fetcher);
+
+    loop {
+        let now = jiff::Timestamp::now();
+        let schedule = feed_reader_v2.schedule()?;
+
+        let due: Vec<&str> = schedule
+            .iter()
+            .filter(|(feed_name, due)| selected(feed_name) && (once || *due <= now))
+            .map(|(feed_name, _)| feed_name.as_str())
+            .collect();
+        pool::fetch_concurrently(&feed_reader_v2, &due, now, |feed_name, result| {
+            write_fetched(&feed_reader_v2, feed_name, result, image_fetcher)
+        });
+
+        for (feed_name, feed) in feed_reader_v2
+            .config
+            .feeds
+            .iter()
+            .filter(|(feed_name, feed)| {
+                selected(feed_name) && feed.output_mode == OutputMode::Digest
+            })
+        {
+            match build_due_digest(
+                &feed_reader_v2.storage(),
+                feed_name,
+                feed,
+                now,
+                &Style::load(
+                    feed_reader_v2.config.feed_stylesheet(feed_name),
+                    feed_reader_v2.config.feed_chapter_template(feed_name),
+                ),
+                image_fetcher,
+            ) {
+                Ok(Some(file_name)) => println!("built digest {}", file_name.display()),
+                Ok(None) => (),
+                Err(err) => eprintln!("failed to build digest for {feed_name}: {err}"),
+            }
+        }
+
+        match build_due_edition(
+            &feed_reader_v2.storage(),
+            &feed_reader_v2.config,
+            now,
+            image_fetcher,
+        ) {
+            Ok(Some(file_name)) => println!("built newspaper {}", file_name.display()),
+            Ok(None) => (),
+            Err(err) => eprintln!("failed to build newspaper: {err}"),
+        }
+
+        if once {
+            return Ok(());
+        }
+
+        // Sleep until the next feed or newspaper edition is due, if there are
+        // no feeds at all we just check back after the global poll interval.
+        let now = jiff::Timestamp::now();
+        let next_feed = feed_reader_v2
+            .schedule()?
+            .into_iter()
+            .find(|(feed_name, _)| selected(feed_name));
+        let mut sleep_for = match next_feed {
+            Some((feed_name, next_due)) => {
+                println!("next feed due is {feed_name} at {next_due}");
+                schedule::sleep_duration(now, next_due)
+            }
+            None => Duration::from_secs(feed_reader_v2.config.poll_interval_secs),
+        };
+        if let Some(edition_due) =
+            next_edition_due(&feed_reader_v2.storage(), &feed_reader_v2.config)?
+        {
+            // An edition with nothing new in it stays due, don't spin on it.
+            if edition_due > now {
+                sleep_for = sleep_for.min(schedule::sleep_duration(now, edition_due));
+            }
+        }
+        thread::sleep(sleep_for.max(MIN_SLEEP));
+    }
+}
+
+/// fetch_from_stdin stores the feed document piped in on stdin as if it had
+/// been fetched for the given feed and writes EPUBs for whatever is new.
+pub fn fetch_from_stdin(config: Config, feed_name: &str) -> Result<()> {
+    check_feeds_exist(&config, &[feed_name.into()])?;
+
+    let feed_reader_v2 = FeedReader::new(config).context("failed to set up feed reader")?;
+    let image_fetcher = new_image_fetcher(&feed_reader_v2);
+    let image_fetcher = feed_reader_v2.config.images.embed.then_some(&image_fetcher);
+
+    let download_dir = &feed_reader_v2.config.feeds[feed_name].download_dir;
+    std::fs::create_dir_all(download_dir)
+        .with_context(|| format!("failed to create download dir {download_dir}"))?;
+
+    let outcome = feed_reader_v2
+        .fetch_from_reader(feed_name, std::io::stdin().lock())
+        .context("failed to read feed from stdin")?;
+    write_fetched(&feed_reader_v2, feed_name, Ok(outcome), image_fetcher);
+    Ok(())
+}
+
+fn new_image_fetcher(feed_reader_v2: &FeedReader) -> ImageFetcher {
+    ImageFetcher::new(
+        feed_reader_v2.agent().clone(),
+        feed_reader_v2.config.images.clone(),
+    )
+}
+
+/// write_fetched writes an EPUB per new or changed entry of a fetched feed,
+/// unless the feed is delivered as a digest or in the newspaper. Entries
+/// whose book failed before are tried again. Errors are logged so one broken
+/// feed doesn't keep the others from being polled.
+fn write_fetched(
+    feed_reader_v2: &FeedReader,
+    feed_name: &str,
+    result: Result<FetchOutcome, FetchError>,
+    image_fetcher: Option<&ImageFetcher>,
+) {
+    let feed = &feed_reader_v2.config.feeds[feed_name];
+    match result {
+        Ok(FetchOutcome::Fetched(_)) => (),
+        Ok(FetchOutcome::NotModified) => {
+            println!("{feed_name} has not been modified since the last fetch");
+        }
+        Ok(FetchOutcome::BackedOff(blocked_until)) => {
+            println!("{feed_name} is backed off until {blocked_until}");
+        }
+        Ok(FetchOutcome::Gone(gone_since)) => {
+            println!(
+                "{feed_name} is gone since {gone_since}, fix its url and run `feed-to-epub revive {feed_name}`"
+            );
+        }
+        Err(err) => {
+            eprintln!("encountered error while fetching feed {}: {err}", feed.url);
+        }
+    };
+
+    if feed.output_mode != OutputMode::Entry {
+        return;
+    }
+    let Ok(Some(feed_stats)) = feed_reader_v2.storage().feed_stats_from_db(&feed.url) else {
+        return;
+    };
+    let entries = match feed_reader_v2
+        .storage()
+        .pending_entries_from_db(feed_stats.id)
+    {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("failed to look up the new entries of {feed_name}: {err}");
+            return;
+        }
+    };
+    if entries.is_empty() {
+        return;
+    }
+
+    let style = Style::load(
+        feed_reader_v2.config.feed_stylesheet(feed_name),
+        feed_reader_v2.config.feed_chapter_template(feed_name),
+    );
+    for entry in entries {
+        let Some(feed_entry_id) = entry.feed_entry_id.as_deref() else {
+            continue;
+        };
+        let series_index = feed_reader_v2
+            .storage()
+            .entry_position_from_db(entry.feed_id, feed_entry_id)
+            .ok()
+            .flatten();
+        match entry_to_epub(
+            feed_name,
+            &feed.download_dir,
+            &entry,
+            series_index,
+            feed_stats.logo.as_deref(),
+            &style,
+            image_fetcher,
+        ) {
+            Ok(..) => {
+                if let Err(err) = feed_reader_v2
+                    .storage()
+                    .book_written_to_db(entry.feed_id, feed_entry_id)
+                {
+                    eprintln!("failed to record the epub of {}: {err}", entry.title);
+                }
+            }
+            Err(err) => eprintln!(
+                "failed to create epub for {}, trying again next time: {err}",
+                entry.title
+            ),
+        }
+    }
+}
+
+/// resolve_feed_url looks up the feed behind `url`, which may be a website
+/// or already a feed. Websites offering several feeds need `pick` to choose
+/// one of them.
+pub fn resolve_feed_url(config_path: &Path, url: &str, pick: Option<usize>) -> Result<String> {
+    let candidates = discovery::discover(&discovery_agent(config_path), url)?;
+
+    let candidate = match (candidates.len(), pick) {
+        (0, _) => anyhow::bail!(
+            "could not find a feed at {url}, pass the URL of the feed itself or use --no-discover"
+        ),
+        (1, None) => &candidates[0],
+        (_, Some(pick)) if (1..=candidates.len()).contains(&pick) => &candidates[pick - 1],
+        (_, Some(pick)) => anyhow::bail!(
+            "cannot pick feed {pick}, {url} only has {} feeds",
+            candidates.len()
+        ),
+        (_, None) => {
+            print_candidates(&candidates);
+            anyhow::bail!("{url} has several feeds, choose one with --pick");
+        }
+    };
+
+    if candidate.url != url {
+        println!("found feed {}", candidate.url);
+    }
+    Ok(candidate.url.clone())
+}
+
+/// discover prints the feeds a website offers.
+pub fn discover(config_path: &Path, url: &str) -> Result<()> {
+    let candidates = discovery::discover(&discovery_agent(config_path), url)?;
+    if candidates.is_empty() {
+        anyhow::bail!("could not find a feed at {url}");
+    }
+    print_candidates(&candidates);
+    Ok(())
+}
+
+fn print_candidates(candidates: &[discovery::Candidate]) {
+    for (i, candidate) in candidates.iter().enumerate() {
+        match &candidate.title {
+            Some(title) => println!("{}: {} ({title})", i + 1, candidate.url),
+            None => println!("{}: {}", i + 1, candidate.url),
+        }
+    }
+}
+
+/// discovery_agent uses the configured timeout if there already is a
+/// config, adding the first feed works without one.
+fn discovery_agent(config_path: &Path) -> ureq::Agent {
+    let timeout = ConfigEditor::open(config_path)
+        .and_then(|editor| editor.config())
+        .map(|config| config.http_request_timeout_secs)
+        .unwrap_or_else(|_| config::default_http_request_timeout_secs());
+    feed_reader::new_agent(timeout)
+}
+
+/// add adds a feed to the config file at `config_path`, creating the file if
+/// it doesn't exist yet.
+pub fn add(config_path: &Path, name: &str, url: &str, download_dir: &str) -> Result<()> {
+    let mut editor = ConfigEditor::open(config_path)?;
+    editor.add_feed(name, url, download_dir, None)?;
+    editor.save(config_path)?;
+    println!("added feed {name}");
+    Ok(())
+}
+
+pub fn remove(config_path: &Path, name: &str) -> Result<()> {
+    let mut editor = ConfigEditor::open(config_path)?;
+    editor.remove_feed(name)?;
+    editor.save(config_path)?;
+    println!("removed feed {name}");
+    Ok(())
+}
+
+pub fn import_opml(config_path: &Path, opml_file: &Path, download_dir: &str) -> Result<()> {
+    let opml = std::fs::read_to_string(opml_file)
+        .with_context(|| format!("failed to read {}", opml_file.display()))?;
+    let outlines = opml::parse(&opml)?;
+
+    let mut editor = ConfigEditor::open(config_path)?;
+    let added = opml::import(&mut editor, &outlines, download_dir)?;
+    editor.save(config_path)?;
+
+    for name in &added {
+        println!("added feed {name}");
+    }
+    println!(
+        "imported {} of {} feeds, the rest were already configured",
+        added.len(),
+        outlines.len()
+    );
+    Ok(())
+}
+
+pub fn export_opml(config: &Config, output: Option<&Path>) -> Result<()> {
+    let opml = opml::export(config);
+    match output {
+        Some(output) => std::fs::write(output, opml)
+            .with_context(|| format!("failed to write {}", output.display()))?,
+        None => print!("{opml}"),
+    }
+    Ok(())
+}
+
+pub fn list(config: &Config) {
+    let feeds = sorted_feeds(config);
+    let width = feeds.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, feed) in feeds {
+        println!("{name:width$}  {}  {}", feed.url, feed.download_dir);
+    }
+}
+
+/// status prints what the database knows about every configured feed.
+pub fn status(config: &Config) -> Result<()> {
+    let storage = open_storage(config)?;
+
+    for (name, feed) in sorted_feeds(config) {
+        println!("{name} ({})", feed.url);
+        let Some(feed_stats) = storage.feed_stats_from_db(&feed.url)? else {
+            println!("  never fetched");
+            continue;
+        };
+
+        let last_fetched = feed_stats
+            .last_fetched
+            .map_or("never".into(), |last_fetched| last_fetched.to_string());
+        println!("  last fetched:  {last_fetched}");
+        println!(
+            "  etag:          {}",
+            feed_stats.etag.as_deref().unwrap_or("none")
+        );
+        println!(
+            "  last modified: {}",
+            feed_stats.last_modified.as_deref().unwrap_or("none")
+        );
+        if let Some(moved_to) = &feed_stats.moved_to {
+            println!("  moved to:      {moved_to}");
+        }
+        if let Some(gone_since) = feed_stats.gone_since {
+            println!("  gone since:    {gone_since}");
+        }
+        match storage.backoff_from_db(feed_stats.id)? {
+            Some(backoff) => println!(
+                "  backoff:       until {} after {} failures: {}",
+                backoff.blocked_until,
+                backoff.failure_count,
+                backoff.last_error.as_deref().unwrap_or("unknown error")
+            ),
+            None => println!("  backoff:       none"),
+        }
+        println!(
+            "  entries:       {}",
+            storage.entry_count_from_db(feed_stats.id)?
+        );
+    }
+
+    Ok(())
+}
+
+/// revive polls a feed that is gone again, for when the feed came back or its
+/// url was fixed.
+pub fn revive(config: &Config, feed_name: &str) -> Result<()> {
+    check_feeds_exist(config, &[feed_name.into()])?;
+    let storage = open_storage(config)?;
+
+    let url = &config.feeds[feed_name].url;
+    match storage.feed_stats_from_db(url)? {
+        Some(mut feed_stats) if feed_stats.gone_since.is_some() => {
+            feed_stats.gone_since = None;
+            storage.feed_stats_to_db(&feed_stats)?;
+            println!("revived {feed_name}");
+        }
+        _ => println!("{feed_name} is not gone"),
+    }
+    Ok(())
+}
+
+/// regenerate rebuilds the EPUBs of stored entries, optionally only for one
+/// feed and a range of dates. Feeds delivered as digests or in the newspaper
+/// get their digests and editions rebuilt instead of a book per entry, those
+/// are filtered by the day they were built.
+pub fn regenerate(
+    config: &Config,
+    only_feed: Option<&str>,
+    since: Option<jiff::civil::Date>,
+    until: Option<jiff::civil::Date>,
+) -> Result<()> {
+    if let Some(only_feed) = only_feed {
+        check_feeds_exist(config, &[only_feed.to_string()])?;
+    }
+
+    let storage = open_storage(config)?;
+    let image_fetcher = ImageFetcher::new(
+        feed_reader::new_agent(config.http_request_timeout_secs),
+        config.images.clone(),
+    );
+    let image_fetcher = config.images.embed.then_some(&image_fetcher);
+
+    let tz = jiff::tz::TimeZone::system();
+    let since = since
+        .map(|date| date.to_zoned(tz.clone()))
+        .transpose()?
+        .map(|zoned| zoned.timestamp());
+    // until is inclusive, so everything before the start of the next day.
+    let until = until
+        .map(|date| date.tomorrow()?.to_zoned(tz.clone()))
+        .transpose()?
+        .map(|zoned| zoned.timestamp());
+    let in_range = |timestamp: jiff::Timestamp| {
+        since.is_none_or(|since| timestamp >= since) && until.is_none_or(|until| timestamp < until)
+    };
+
+    let mut regenerated = 0;
+    for (feed_name, feed) in config.feeds.iter() {
+        if only_feed.is_some_and(|only_feed| only_feed != feed_name)
+            || feed.output_mode == OutputMode::Newspaper
+        {
+            continue;
+        }
+
+        let Some(feed_stats) = storage.feed_stats_from_db(&feed.url)? else {
+            println!("{feed_name} has never been fetched, nothing to regenerate");
+            continue;
+        };
+
+        std::fs::create_dir_all(&feed.download_dir)?;
+        let style = Style::load(
+            config.feed_stylesheet(feed_name),
+            config.feed_chapter_template(feed_name),
+        );
+
+        if feed.output_mode == OutputMode::Digest {
+            for digest in storage.digests_from_db(feed_stats.id)? {
+                if !in_range(digest.created) {
+                    continue;
+                }
+                match rebuild_digest(&storage, feed_name, feed, &digest, &style, image_fetcher) {
+                    Ok(..) => regenerated += 1,
+                    Err(err) => eprintln!("failed to rebuild digest {}: {err}", digest.title),
+                }
+            }
+            continue;
+        }
+
+        // Entries come in the order they were stored, which is what
+        // entry_position_from_db counts.
+        for (position, entry) in (1..).zip(storage.entries_from_db(Some(feed_stats.id))?) {
+            if (since.is_some() || until.is_some()) && !entry.timestamp().is_some_and(&in_range) {
+                continue;
+            }
+
+            match entry_to_epub(
+                feed_name,
+                &feed.download_dir,
+                &entry,
+                Some(position),
+                feed_stats.logo.as_deref(),
+                &style,
+                image_fetcher,
+            ) {
+                Ok(..) => {
+                    regenerated += 1;
+                    if let Some(feed_entry_id) = &entry.feed_entry_id {
+                        storage.book_written_to_db(feed_stats.id, feed_entry_id)?;
+                    }
+                }
+                Err(err) => eprintln!("failed to create epub for {}: {err}", entry.title),
+            }
+        }
+    }
+
+    let newspaper_selected = only_feed
+        .is_none_or(|only_feed| config.feeds[only_feed].output_mode == OutputMode::Newspaper);
+    if newspaper_selected && config.newspaper.is_some() {
+        for edition in storage.editions_from_db()? {
+            if !in_range(edition.created) {
+                continue;
+            }
+            match rebuild_edition(&storage, config, &edition, image_fetcher) {
+                Ok(Some(..)) => regenerated += 1,
+                Ok(None) => (),
+                Err(err) => eprintln!("failed to rebuild newspaper {}: {err}", edition.title),
+            }
+        }
+    }
+
+    println!("regenerated {regenerated} EPUBs");
+    Ok(())
+}
+
+fn open_storage(config: &Config) -> Result<Storage> {
+    let storage = Storage::new(&config.db_file)?;
+    storage.init_database()?;
+    Ok(storage)
+}
+
+fn check_feeds_exist(config: &Config, feeds: &[String]) -> Result<()> {
+    let unknown: Vec<&str> = feeds
+        .iter()
+        .filter(|feed| !config.feeds.contains_key(*feed))
+        .map(String::as_str)
+        .collect();
+    if !unknown.is_empty() {
+        anyhow::bail!("unknown feeds: {}", unknown.join(", "));
+    }
+    Ok(())
+}
+
+fn sorted_feeds(config: &Config) -> Vec<(&String, &Feed)> {
+    let mut feeds: Vec<(&String, &Feed)> = config.feeds.iter().collect();
+    feeds.sort_by_key(|(name, _)| *name);
+    feeds
+}
@@ -0,0 +1,62 @@
+use jiff::{SignedDuration, Timestamp};
+
+/// The first failure blocks a feed for an hour, every following one doubles
+/// that until we hit a week. Small blogs tend to come back eventually and a
+/// week is short enough to notice when they do.
+const BASE_BACKOFF: SignedDuration = SignedDuration::from_hours(1);
+const MAX_BACKOFF: SignedDuration = SignedDuration::from_hours(24 * 7);
+
+/// parse_retry_after understands both forms of the `Retry-After` header,
+/// a number of seconds or an HTTP date.
+pub fn parse_retry_after(value: &str, now: Timestamp) -> Option<Timestamp> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        let secs = i64::try_from(secs).unwrap_or(i64::MAX);
+        return now.saturating_add(SignedDuration::from_secs(secs)).ok();
+    }
+
+    jiff::fmt::rfc2822::DateTimeParser::new()
+        .parse_timestamp(value)
+        .ok()
+}
+
+/// exponential_backoff returns how long a feed should be left alone after
+/// it failed `failure_count` times in a row.
+pub fn exponential_backoff(failure_count: u32) -> SignedDuration {
+    let exponent = failure_count.saturating_sub(1).min(16);
+    BASE_BACKOFF
+        .checked_mul(1 << exponent)
+        .unwrap_or(MAX_BACKOFF)
+        .min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_seconds() {
+        let now: Timestamp = "2025-01-01T00:00:00Z".parse().unwrap();
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some("2025-01-01T00:02:00Z".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn retry_after_http_date() {
+        let now: Timestamp = "2025-01-01T00:00:00Z".parse().unwrap();
+        assert_eq!(
+            parse_retry_after("Wed, 01 Jan 2025 06:00:00 GMT", now),
+            Some("2025-01-01T06:00:00Z".parse().unwrap())
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn exponential_backoff_is_capped() {
+        assert_eq!(exponential_backoff(1), SignedDuration::from_hours(1));
+        assert_eq!(exponential_backoff(3), SignedDuration::from_hours(4));
+        assert_eq!(exponential_backoff(100), MAX_BACKOFF);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::selector::Selector;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::io::Read;
@@ -11,6 +12,16 @@ pub enum Error {
     FileError(#[from] std::io::Error),
     #[error("behave, the poll interval cannot be set below 1h")]
     PollIntervalTooFastError,
+    #[error("behave, the poll interval cannot be set below 1h for feeds: {}", feeds.join(", "))]
+    FeedPollIntervalTooFastError { feeds: Vec<String> },
+    #[error("feeds {} go into the newspaper but there is no [newspaper] section", feeds.join(", "))]
+    NewspaperNotConfiguredError { feeds: Vec<String> },
+    #[error("the newspaper hour has to be between 0 and 23, got {0}")]
+    NewspaperHourInvalidError(i8),
+    #[error("fetch_workers and fetch_workers_per_host have to be at least 1")]
+    FetchWorkersInvalidError,
+    #[error("scraped feeds {} have no [feeds.<name>.scrape] section", feeds.join(", "))]
+    ScrapeNotConfiguredError { feeds: Vec<String> },
 }
 
 #[derive(Clone, Deserialize)]
@@ -22,13 +33,28 @@ pub struct Config {
     pub http_request_timeout_secs: u64,
     #[serde(default = "default_feed_poll_interval_secs")]
     pub poll_interval_secs: u64,
+    // fetch_workers is how many feeds are fetched at the same time, at most
+    // fetch_workers_per_host of them from the same host.
+    #[serde(default = "default_fetch_workers")]
+    pub fetch_workers: usize,
+    #[serde(default = "default_fetch_workers_per_host")]
+    pub fetch_workers_per_host: usize,
+    #[serde(default)]
+    pub images: ImageConfig,
+    pub newspaper: Option<NewspaperConfig>,
+    // stylesheet is the path of a CSS file that replaces the bundled
+    // stylesheet of books.
+    pub stylesheet: Option<String>,
+    // chapter_template is the path of an XHTML template that replaces the
+    // bundled template of the chapter of every entry.
+    pub chapter_template: Option<String>,
 }
 
 fn default_db_file() -> String {
     String::from("./feed-to-rss.db")
 }
 
-fn default_http_request_timeout_secs() -> u64 {
+pub fn default_http_request_timeout_secs() -> u64 {
     15
 }
 
@@ -36,15 +62,215 @@ fn default_feed_poll_interval_secs() -> u64 {
     14400
 }
 
+fn default_fetch_workers() -> usize {
+    4
+}
+
+fn default_fetch_workers_per_host() -> usize {
+    1
+}
+
+/// ImageConfig controls how images referenced by entries are downloaded and
+/// embedded into the EPUB files.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ImageConfig {
+    #[serde(default = "default_embed_images")]
+    pub embed: bool,
+    #[serde(default = "default_max_image_bytes")]
+    pub max_bytes: u64,
+    #[serde(default = "default_max_images_per_entry")]
+    pub max_per_entry: usize,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        ImageConfig {
+            embed: default_embed_images(),
+            max_bytes: default_max_image_bytes(),
+            max_per_entry: default_max_images_per_entry(),
+        }
+    }
+}
+
+fn default_embed_images() -> bool {
+    true
+}
+
+fn default_max_image_bytes() -> u64 {
+    5 * 1024 * 1024
+}
+
+fn default_max_images_per_entry() -> usize {
+    50
+}
+
+/// NewspaperConfig describes the edition that bundles the entries of every
+/// feed with output_mode = "newspaper" into one sectioned book.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct NewspaperConfig {
+    #[serde(default = "default_newspaper_title")]
+    pub title: String,
+    pub output_dir: String,
+    #[serde(default)]
+    pub schedule: DigestSchedule,
+    // hour is the local hour of the day an edition is due.
+    #[serde(default = "default_newspaper_hour")]
+    pub hour: i8,
+}
+
+fn default_newspaper_title() -> String {
+    String::from("Newspaper")
+}
+
+fn default_newspaper_hour() -> i8 {
+    6
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct Feed {
+    // url is the path of the Maildir for type = "maildir".
     pub url: String,
+    #[serde(default, rename = "type")]
+    pub source: SourceType,
+    // conditional_type is only kept around so existing configuration files
+    // keep parsing, we always send every validator we have stored.
+    #[serde(default)]
     pub conditional_type: ConditionalType,
     pub download_dir: String,
+    // poll_interval_secs overrides the global poll interval for this feed.
+    pub poll_interval_secs: Option<u64>,
+    #[serde(default)]
+    pub output_mode: OutputMode,
+    // digest_schedule is only used with output_mode = "digest".
+    #[serde(default)]
+    pub digest_schedule: DigestSchedule,
+    // group is the newspaper section the feed goes into, feeds without one
+    // get a section of their own.
+    pub group: Option<String>,
+    pub auth: Option<Auth>,
+    // headers are sent along with every request for the feed.
+    #[serde(default)]
+    pub headers: HashMap<String, Secret>,
+    // user_agent replaces our own user agent for feeds that block it.
+    pub user_agent: Option<String>,
+    // fetch_full_article replaces the content of new entries with the
+    // article their link points at, for feeds that only carry a summary.
+    #[serde(default)]
+    pub fetch_full_article: bool,
+    // scrape is required for type = "scrape", url is the listing page then.
+    pub scrape: Option<ScrapeConfig>,
+    // stylesheet overrides the global stylesheet for this feed.
+    pub stylesheet: Option<String>,
+    // chapter_template overrides the global chapter template for this feed.
+    pub chapter_template: Option<String>,
+}
+
+/// ScrapeConfig holds the selectors that turn a website without a feed into
+/// one.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ScrapeConfig {
+    // links selects the links to articles on the listing page.
+    pub links: Selector,
+    // title, date and content are selected on every article page. Articles
+    // without a title are named after the page, articles without a date
+    // after when we found them.
+    pub title: Option<Selector>,
+    pub date: Option<Selector>,
+    pub content: Selector,
 }
 
-#[derive(Clone, Deserialize, Debug, PartialEq)]
+/// Auth is how we log in to private feeds.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Auth {
+    Basic { username: String, password: Secret },
+    Bearer(Secret),
+}
+
+/// Secret is a value that is better kept out of the config file, it can be
+/// given inline, as `{ file = "path" }` or as `{ env = "VARIABLE" }`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Secret {
+    Value(String),
+    File { file: String },
+    Env { env: String },
+}
+
+#[derive(Error, Debug)]
+pub enum SecretError {
+    #[error("failed to read secret from {path}: {source}")]
+    FileError {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("failed to read secret from environment variable {name}: {source}")]
+    EnvError {
+        name: String,
+        source: std::env::VarError,
+    },
+}
+
+impl Secret {
+    /// resolve reads the secret, files are read every time so rotated
+    /// secrets are picked up without a restart. Trailing newlines that
+    /// editors like to add are dropped.
+    pub fn resolve(&self) -> Result<String, SecretError> {
+        match self {
+            Secret::Value(value) => Ok(value.clone()),
+            Secret::File { file } => {
+                let to_error = |source| SecretError::FileError {
+                    path: file.clone(),
+                    source,
+                };
+                let path = expanduser::expanduser(file).map_err(to_error)?;
+                let secret = std::fs::read_to_string(path).map_err(to_error)?;
+                Ok(secret.trim_end_matches(['\r', '\n']).into())
+            }
+            Secret::Env { env } => std::env::var(env).map_err(|source| SecretError::EnvError {
+                name: env.clone(),
+                source,
+            }),
+        }
+    }
+}
+
+/// SourceType is what kind of source the entries of a feed are read from.
+#[derive(Clone, Copy, Default, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceType {
+    /// Feed is an RSS, Atom or JSON Feed document.
+    #[default]
+    Feed,
+    /// Maildir is a local Maildir folder newsletters are delivered to.
+    Maildir,
+    /// Scrape is a website without a feed, its articles are picked out with
+    /// CSS selectors.
+    Scrape,
+}
+
+/// OutputMode decides whether every entry becomes its own EPUB or whether
+/// entries are collected into a digest.
+#[derive(Clone, Copy, Default, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    #[default]
+    Entry,
+    Digest,
+    Newspaper,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestSchedule {
+    #[default]
+    Daily,
+    Weekly,
+}
+
+#[derive(Clone, Default, Deserialize, Debug, PartialEq)]
 pub enum ConditionalType {
+    #[default]
     ETag,
     LastModified,
 }
@@ -53,34 +279,122 @@ impl Config {
     pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, Error> {
         let mut toml_contents = String::new();
         reader.read_to_string(&mut toml_contents)?;
-        let config: Config = toml::from_str(&toml_contents)?;
+        let mut config: Config = toml::from_str(&toml_contents)?;
 
         if config.poll_interval_secs < 3600 {
             return Err(Error::PollIntervalTooFastError);
         }
 
-        /* Maybe one day I'll revisit the whole, poll by feed idea properly
-         * For now a global one seems easier to roll.
-        let too_fast_feeds: Vec<String> = config
+        let mut too_fast_feeds: Vec<String> = config
             .feeds
             .iter()
-            .filter_map(|(name, feed)| {
-                if feed.poll_interval_secs < 3600 {
-                    Some(name.clone())
-                } else {
-                    None
-                }
+            .filter_map(|(name, feed)| match feed.poll_interval_secs {
+                Some(poll_interval_secs) if poll_interval_secs < 3600 => Some(name.clone()),
+                _ => None,
             })
             .collect();
 
         if !too_fast_feeds.is_empty() {
-            return Err(Error::PollIntervalTooFastError {
+            too_fast_feeds.sort();
+            return Err(Error::FeedPollIntervalTooFastError {
                 feeds: too_fast_feeds,
             });
         }
-        */
+
+        if config.fetch_workers == 0 || config.fetch_workers_per_host == 0 {
+            return Err(Error::FetchWorkersInvalidError);
+        }
+
+        let mut unconfigured_scrapes: Vec<String> = config
+            .feeds
+            .iter()
+            .filter(|(_, feed)| feed.source == SourceType::Scrape && feed.scrape.is_none())
+            .map(|(name, _)| name.clone())
+            .collect();
+        if !unconfigured_scrapes.is_empty() {
+            unconfigured_scrapes.sort();
+            return Err(Error::ScrapeNotConfiguredError {
+                feeds: unconfigured_scrapes,
+            });
+        }
+
+        match &config.newspaper {
+            Some(newspaper) if !(0..=23).contains(&newspaper.hour) => {
+                return Err(Error::NewspaperHourInvalidError(newspaper.hour));
+            }
+            Some(_) => (),
+            None => {
+                let mut newspaper_feeds: Vec<String> = config
+                    .feeds
+                    .iter()
+                    .filter(|(_, feed)| feed.output_mode == OutputMode::Newspaper)
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                if !newspaper_feeds.is_empty() {
+                    newspaper_feeds.sort();
+                    return Err(Error::NewspaperNotConfiguredError {
+                        feeds: newspaper_feeds,
+                    });
+                }
+            }
+        }
+
+        for feed in config.feeds.values_mut() {
+            feed.download_dir = expand_path(&feed.download_dir)?;
+            if feed.source == SourceType::Maildir {
+                feed.url = expand_path(&feed.url)?;
+            }
+            if let Some(stylesheet) = &feed.stylesheet {
+                feed.stylesheet = Some(expand_path(stylesheet)?);
+            }
+            if let Some(chapter_template) = &feed.chapter_template {
+                feed.chapter_template = Some(expand_path(chapter_template)?);
+            }
+        }
+        if let Some(stylesheet) = &config.stylesheet {
+            config.stylesheet = Some(expand_path(stylesheet)?);
+        }
+        if let Some(chapter_template) = &config.chapter_template {
+            config.chapter_template = Some(expand_path(chapter_template)?);
+        }
+        if let Some(newspaper) = &mut config.newspaper {
+            newspaper.output_dir = expand_path(&newspaper.output_dir)?;
+        }
+
         Ok(config)
     }
+
+    /// feed_poll_interval_secs returns how often the given feed should be
+    /// polled, falling back to the global interval if the feed has none set.
+    pub fn feed_poll_interval_secs(&self, feed_name: &str) -> u64 {
+        self.feeds
+            .get(feed_name)
+            .and_then(|feed| feed.poll_interval_secs)
+            .unwrap_or(self.poll_interval_secs)
+    }
+
+    /// feed_stylesheet returns the path of the stylesheet for the books of
+    /// the given feed, None means the bundled one.
+    pub fn feed_stylesheet(&self, feed_name: &str) -> Option<&str> {
+        self.feeds
+            .get(feed_name)
+            .and_then(|feed| feed.stylesheet.as_deref())
+            .or(self.stylesheet.as_deref())
+    }
+
+    /// feed_chapter_template returns the path of the chapter template for
+    /// the books of the given feed, None means the bundled one.
+    pub fn feed_chapter_template(&self, feed_name: &str) -> Option<&str> {
+        self.feeds
+            .get(feed_name)
+            .and_then(|feed| feed.chapter_template.as_deref())
+            .or(self.chapter_template.as_deref())
+    }
+}
+
+/// expand_path expands a leading `~` to the home directory.
+fn expand_path(path: &str) -> Result<String, Error> {
+    Ok(expanduser::expanduser(path)?.to_string_lossy().into_owned())
 }
 
 #[cfg(test)]
@@ -100,12 +414,33 @@ conditional_type = \"LastModified\"
 
         let config = Config::from_reader(buf.as_bytes()).expect("failed to parse configuration");
         assert_eq!(config.poll_interval_secs, 14400);
+        assert_eq!(config.fetch_workers, 4);
+        assert_eq!(config.fetch_workers_per_host, 1);
+        assert_eq!(config.images, ImageConfig::default());
         assert_eq!(config.feeds["test"].url, "https://example.com/rss");
         assert_eq!(config.feeds["test"].download_dir, "/tmp/test");
         assert_eq!(
             config.feeds["test"].conditional_type,
             ConditionalType::LastModified
         );
+        assert_eq!(config.feeds["test"].output_mode, OutputMode::Entry);
+    }
+
+    #[test]
+    fn config_from_reader_digest() {
+        let buf = String::from(
+            "
+[feeds.test]
+url = \"https://example.com/rss\"
+download_dir = \"/tmp/test\"
+output_mode = \"digest\"
+digest_schedule = \"weekly\"
+        ",
+        );
+
+        let config = Config::from_reader(buf.as_bytes()).expect("failed to parse configuration");
+        assert_eq!(config.feeds["test"].output_mode, OutputMode::Digest);
+        assert_eq!(config.feeds["test"].digest_schedule, DigestSchedule::Weekly);
     }
 
     #[test]
@@ -144,4 +479,267 @@ conditional_type = \"LastModified\"
             ConditionalType::LastModified
         );
     }
+
+    #[test]
+    fn config_from_reader_per_feed_poll_interval() {
+        let buf = String::from(
+            "
+poll_interval_secs = 7200
+
+[feeds.daily]
+url = \"https://example.com/daily\"
+download_dir = \"/tmp/daily\"
+poll_interval_secs = 3600
+
+[feeds.monthly]
+url = \"https://example.com/monthly\"
+download_dir = \"/tmp/monthly\"
+        ",
+        );
+
+        let config = Config::from_reader(buf.as_bytes()).expect("failed to parse configuration");
+        assert_eq!(config.feed_poll_interval_secs("daily"), 3600);
+        assert_eq!(config.feed_poll_interval_secs("monthly"), 7200);
+    }
+
+    #[test]
+    fn config_from_reader_feed_poll_interval_too_fast() {
+        let buf = String::from(
+            "
+[feeds.test]
+url = \"https://example.com/rss\"
+download_dir = \"/tmp/test\"
+poll_interval_secs = 60
+        ",
+        );
+
+        match Config::from_reader(buf.as_bytes()) {
+            Err(Error::FeedPollIntervalTooFastError { feeds }) => assert_eq!(feeds, vec!["test"]),
+            _ => panic!("expected the poll interval of feed test to be rejected"),
+        }
+    }
+
+    #[test]
+    fn config_from_reader_newspaper() {
+        let buf = String::from(
+            "
+[newspaper]
+output_dir = \"/tmp/newspaper\"
+schedule = \"weekly\"
+
+[feeds.test]
+url = \"https://example.com/rss\"
+download_dir = \"/tmp/test\"
+output_mode = \"newspaper\"
+group = \"tech\"
+        ",
+        );
+
+        let config = Config::from_reader(buf.as_bytes()).expect("failed to parse configuration");
+        assert_eq!(
+            config.newspaper,
+            Some(NewspaperConfig {
+                title: "Newspaper".into(),
+                output_dir: "/tmp/newspaper".into(),
+                schedule: DigestSchedule::Weekly,
+                hour: 6,
+            })
+        );
+        assert_eq!(config.feeds["test"].output_mode, OutputMode::Newspaper);
+        assert_eq!(config.feeds["test"].group.as_deref(), Some("tech"));
+    }
+
+    #[test]
+    fn config_from_reader_newspaper_missing() {
+        let buf = String::from(
+            "
+[feeds.test]
+url = \"https://example.com/rss\"
+download_dir = \"/tmp/test\"
+output_mode = \"newspaper\"
+        ",
+        );
+
+        match Config::from_reader(buf.as_bytes()) {
+            Err(Error::NewspaperNotConfiguredError { feeds }) => assert_eq!(feeds, vec!["test"]),
+            _ => panic!("expected the missing newspaper section to be rejected"),
+        }
+    }
+
+    #[test]
+    fn config_from_reader_no_fetch_workers() {
+        let buf = String::from(
+            "
+fetch_workers = 0
+
+[feeds.test]
+url = \"https://example.com/rss\"
+download_dir = \"/tmp/test\"
+        ",
+        );
+
+        assert!(matches!(
+            Config::from_reader(buf.as_bytes()),
+            Err(Error::FetchWorkersInvalidError)
+        ));
+    }
+
+    #[test]
+    fn config_from_reader_auth() {
+        let buf = String::from(
+            "
+[feeds.newsletter]
+url = \"https://example.com/rss\"
+download_dir = \"/tmp/test\"
+auth = { basic = { username = \"me\", password = { file = \"~/.newsletter\" } } }
+user_agent = \"Mozilla/5.0\"
+
+[feeds.wiki]
+url = \"https://wiki.example.com/feed\"
+download_dir = \"/tmp/test\"
+auth = { bearer = { env = \"WIKI_TOKEN\" } }
+headers = { Cookie = \"session=abc\", X-Api-Key = { env = \"WIKI_KEY\" } }
+        ",
+        );
+
+        let config = Config::from_reader(buf.as_bytes()).expect("failed to parse configuration");
+        assert_eq!(
+            config.feeds["newsletter"].auth,
+            Some(Auth::Basic {
+                username: "me".into(),
+                password: Secret::File {
+                    file: "~/.newsletter".into()
+                },
+            })
+        );
+        assert_eq!(
+            config.feeds["newsletter"].user_agent.as_deref(),
+            Some("Mozilla/5.0")
+        );
+        assert_eq!(
+            config.feeds["wiki"].auth,
+            Some(Auth::Bearer(Secret::Env {
+                env: "WIKI_TOKEN".into()
+            }))
+        );
+        assert_eq!(
+            config.feeds["wiki"].headers["Cookie"],
+            Secret::Value("session=abc".into())
+        );
+        assert_eq!(
+            config.feeds["wiki"].headers["X-Api-Key"],
+            Secret::Env {
+                env: "WIKI_KEY".into()
+            }
+        );
+    }
+
+    #[test]
+    fn config_from_reader_scrape() {
+        let buf = String::from(
+            "
+[feeds.site]
+type = \"scrape\"
+url = \"https://example.com/blog/\"
+download_dir = \"/tmp/test\"
+
+[feeds.site.scrape]
+links = \"article h2 > a\"
+content = \".post-body\"
+        ",
+        );
+
+        let config = Config::from_reader(buf.as_bytes()).expect("failed to parse configuration");
+        let scrape = config.feeds["site"]
+            .scrape
+            .as_ref()
+            .expect("no scrape section");
+        assert_eq!(scrape.links.to_string(), "article h2 > a");
+        assert!(scrape.title.is_none());
+
+        let missing = buf.replace("[feeds.site.scrape]", "[feeds.site.elsewhere]");
+        match Config::from_reader(missing.as_bytes()) {
+            Err(Error::ScrapeNotConfiguredError { feeds }) => assert_eq!(feeds, vec!["site"]),
+            _ => panic!("expected the missing scrape section to be rejected"),
+        }
+
+        let invalid = buf.replace(".post-body", "a:hover");
+        assert!(matches!(
+            Config::from_reader(invalid.as_bytes()),
+            Err(Error::TOMLParseError(_))
+        ));
+    }
+
+    #[test]
+    fn config_from_reader_stylesheet() {
+        let buf = String::from(
+            "
+stylesheet = \"~/books/style.css\"
+chapter_template = \"/etc/chapter.xhtml\"
+
+[feeds.plain]
+url = \"https://example.com/rss\"
+download_dir = \"/tmp/test\"
+
+[feeds.fancy]
+url = \"https://example.com/atom.xml\"
+download_dir = \"/tmp/test\"
+stylesheet = \"/etc/fancy.css\"
+chapter_template = \"/etc/fancy.xhtml\"
+        ",
+        );
+
+        let config = Config::from_reader(buf.as_bytes()).expect("failed to parse configuration");
+        let expected = expanduser::expanduser("~/books/style.css").unwrap();
+        assert_eq!(
+            config.feed_stylesheet("plain"),
+            Some(expected.to_str().unwrap())
+        );
+        assert_eq!(config.feed_stylesheet("fancy"), Some("/etc/fancy.css"));
+        assert_eq!(
+            config.feed_chapter_template("plain"),
+            Some("/etc/chapter.xhtml")
+        );
+        assert_eq!(
+            config.feed_chapter_template("fancy"),
+            Some("/etc/fancy.xhtml")
+        );
+    }
+
+    #[test]
+    fn secret_resolve() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"hunter2\n").unwrap();
+        let secret = Secret::File {
+            file: file.path().to_string_lossy().into(),
+        };
+        assert_eq!(secret.resolve().unwrap(), "hunter2");
+
+        let secret = Secret::Env {
+            env: "FEED_TO_EPUB_TEST_SECRET_THAT_IS_NOT_SET".into(),
+        };
+        assert!(matches!(
+            secret.resolve(),
+            Err(SecretError::EnvError { .. })
+        ));
+    }
+
+    #[test]
+    fn config_from_reader_expands_home() {
+        let buf = String::from(
+            "
+[feeds.test]
+url = \"https://example.com/rss\"
+download_dir = \"~/books/test\"
+        ",
+        );
+
+        let config = Config::from_reader(buf.as_bytes()).expect("failed to parse configuration");
+        let expected = expanduser::expanduser("~/books/test").unwrap();
+        assert_eq!(
+            config.feeds["test"].download_dir,
+            expected.to_string_lossy()
+        );
+        assert!(!config.feeds["test"].download_dir.starts_with('~'));
+    }
 }
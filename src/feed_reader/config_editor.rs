@@ -0,0 +1,240 @@
+//! Edits the TOML configuration on behalf of the CLI. Edits go through
+//! toml_edit so the comments and formatting of hand written configuration
+//! files survive, and the result is validated before it is written back.
+
+use super::config::{self, Config};
+use std::io::Write;
+use std::path::Path;
+use thiserror::Error;
+use toml_edit::{value, DocumentMut, Item, Table};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("could not parse config file, invalid TOML: {0}")]
+    TOMLParseError(#[from] toml_edit::TomlError),
+    #[error("could not read or write config file: {0}")]
+    FileError(#[from] std::io::Error),
+    #[error("the edited configuration would be invalid: {0}")]
+    InvalidConfigError(#[from] config::Error),
+    #[error("feeds in the config file is not a table")]
+    FeedsNotATableError,
+    #[error("feed {0} already exists")]
+    FeedExistsError(String),
+    #[error("feed {0} does not exist")]
+    FeedNotFoundError(String),
+}
+
+pub struct ConfigEditor {
+    document: DocumentMut,
+}
+
+impl std::str::FromStr for ConfigEditor {
+    type Err = Error;
+
+    fn from_str(contents: &str) -> Result<Self, Error> {
+        Ok(ConfigEditor {
+            document: contents.parse()?,
+        })
+    }
+}
+
+impl ConfigEditor {
+    /// open reads the config file at `path`, a missing file is treated as an
+    /// empty configuration so `add` can create it.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => contents.parse(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => "".parse(),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// add_feed adds a new `[feeds.<name>]` table.
+    pub fn add_feed(
+        &mut self,
+        name: &str,
+        url: &str,
+        download_dir: &str,
+        group: Option<&str>,
+    ) -> Result<(), Error> {
+        let feeds = self.feeds_mut()?;
+        if feeds.contains_key(name) {
+            return Err(Error::FeedExistsError(name.into()));
+        }
+
+        let mut feed = Table::new();
+        feed.insert("url", value(url));
+        feed.insert("download_dir", value(download_dir));
+        if let Some(group) = group {
+            feed.insert("group", value(group));
+        }
+        feeds.insert(name, Item::Table(feed));
+        Ok(())
+    }
+
+    pub fn has_feed(&self, name: &str) -> bool {
+        self.feeds().is_some_and(|feeds| feeds.contains_key(name))
+    }
+
+    /// has_feed_url tells whether any feed already polls `url`.
+    pub fn has_feed_url(&self, url: &str) -> bool {
+        self.feeds().is_some_and(|feeds| {
+            feeds
+                .iter()
+                .any(|(_, feed)| feed.get("url").and_then(Item::as_str) == Some(url))
+        })
+    }
+
+    pub fn remove_feed(&mut self, name: &str) -> Result<(), Error> {
+        match self.feeds_mut()?.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(Error::FeedNotFoundError(name.into())),
+        }
+    }
+
+    /// config parses the edited document the same way the daemon would.
+    pub fn config(&self) -> Result<Config, Error> {
+        Ok(Config::from_reader(self.document.to_string().as_bytes())?)
+    }
+
+    /// save validates the edited configuration and replaces the file at
+    /// `path` with it. The new contents go to a temporary file next to it
+    /// first so a crash never leaves a half written config behind.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        self.config()?;
+
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        std::fs::create_dir_all(dir)?;
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        file.write_all(self.document.to_string().as_bytes())?;
+        file.as_file().sync_all()?;
+        file.persist(path).map_err(|err| err.error)?;
+        Ok(())
+    }
+
+    fn feeds(&self) -> Option<&Table> {
+        self.document.get("feeds").and_then(Item::as_table)
+    }
+
+    fn feeds_mut(&mut self) -> Result<&mut Table, Error> {
+        let feeds = self.document.entry("feeds").or_insert_with(|| {
+            // Only write the [feeds.<name>] headers, not an empty [feeds] one.
+            let mut feeds = Table::new();
+            feeds.set_implicit(true);
+            Item::Table(feeds)
+        });
+        feeds.as_table_mut().ok_or(Error::FeedsNotATableError)
+    }
+}
+
+impl std::fmt::Display for ConfigEditor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.document)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "# polled every four hours
+poll_interval_secs = 14400
+
+[feeds]
+  # the one everybody reads
+  [feeds.lwn]
+  url = \"https://lwn.net/headlines/rss\"
+  download_dir = \"~/books/lwn\"
+";
+
+    #[test]
+    fn add_feed_keeps_comments() {
+        let mut editor = CONFIG.parse::<ConfigEditor>().unwrap();
+        editor
+            .add_feed(
+                "blog",
+                "https://blog.example/atom.xml",
+                "~/books/blog",
+                None,
+            )
+            .expect("failed to add feed");
+
+        let edited = editor.to_string();
+        assert!(edited.starts_with(CONFIG), "{edited}");
+        assert!(edited.contains("[feeds.blog]"), "{edited}");
+
+        let config = editor.config().unwrap();
+        assert_eq!(config.feeds["blog"].url, "https://blog.example/atom.xml");
+        assert!(
+            edited.contains("download_dir = \"~/books/blog\""),
+            "{edited}"
+        );
+        assert_eq!(config.feeds.len(), 2);
+        assert!(editor.has_feed("blog"));
+        assert!(editor.has_feed_url("https://lwn.net/headlines/rss"));
+        assert!(!editor.has_feed_url("https://other.example"));
+
+        match editor.add_feed("blog", "https://other.example", "/tmp", None) {
+            Err(Error::FeedExistsError(name)) => assert_eq!(name, "blog"),
+            other => panic!("expected the duplicate feed to be refused, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn add_feed_to_empty_config() {
+        let mut editor = "".parse::<ConfigEditor>().unwrap();
+        editor
+            .add_feed(
+                "blog",
+                "https://blog.example/atom.xml",
+                "~/books/blog",
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(
+            editor.to_string(),
+            "[feeds.blog]\nurl = \"https://blog.example/atom.xml\"\ndownload_dir = \"~/books/blog\"\n"
+        );
+    }
+
+    #[test]
+    fn remove_feed() {
+        let mut editor = CONFIG.parse::<ConfigEditor>().unwrap();
+        editor.remove_feed("lwn").expect("failed to remove feed");
+        assert!(editor.config().unwrap().feeds.is_empty());
+        assert!(!editor.to_string().contains("lwn"));
+
+        match editor.remove_feed("lwn") {
+            Err(Error::FeedNotFoundError(name)) => assert_eq!(name, "lwn"),
+            other => panic!("expected the missing feed to be reported, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn save_refuses_invalid_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+
+        let mut editor = "poll_interval_secs = 60".parse::<ConfigEditor>().unwrap();
+        editor
+            .add_feed("blog", "https://blog.example/atom.xml", "/tmp", None)
+            .unwrap();
+        assert!(matches!(
+            editor.save(&path),
+            Err(Error::InvalidConfigError(_))
+        ));
+        assert!(!path.exists());
+
+        let mut editor = ConfigEditor::open(&path).unwrap();
+        editor
+            .add_feed("blog", "https://blog.example/atom.xml", "/tmp", None)
+            .unwrap();
+        editor.save(&path).expect("failed to save config");
+        let config = Config::from_reader(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(config.feeds["blog"].url, "https://blog.example/atom.xml");
+    }
+}
@@ -0,0 +1,268 @@
+//! Finds the feeds of a website when all we have is the URL of its homepage.
+
+use crate::html;
+use std::io::Read;
+use thiserror::Error;
+use url::Url;
+
+/// Pages bigger than this are not worth looking through.
+const MAX_PAGE_BYTES: u64 = 5 * 1024 * 1024;
+
+/// The types `<link rel="alternate">` uses to announce feeds.
+const FEED_TYPES: &[&str] = &[
+    "application/rss+xml",
+    "application/atom+xml",
+    "application/feed+json",
+    "application/rdf+xml",
+];
+
+/// Where feeds usually live on sites that don't announce them, tried in
+/// order relative to the root of the site.
+const COMMON_PATHS: &[&str] = &[
+    "/feed",
+    "/rss",
+    "/atom.xml",
+    "/feed.xml",
+    "/rss.xml",
+    "/index.xml",
+    "/feed.json",
+];
+
+#[derive(Error, Debug)]
+pub enum DiscoveryError {
+    #[error("invalid URL {url}: {source}")]
+    InvalidURLError {
+        url: String,
+        source: url::ParseError,
+    },
+    #[error("failed to execute HTTP request: {0}")]
+    HTTPError(#[from] Box<ureq::Error>),
+    #[error("failed to read response: {0}")]
+    ReadError(#[from] std::io::Error),
+}
+
+/// Candidate is a feed we found for a website.
+#[derive(Debug, PartialEq)]
+pub struct Candidate {
+    pub url: String,
+    pub title: Option<String>,
+}
+
+/// discover returns the feeds behind `url`. That is the URL itself if it
+/// already points at a feed, else every feed the page announces with
+/// `<link rel="alternate">`, else the first of the common feed paths that
+/// answers with a feed.
+pub fn discover(agent: &ureq::Agent, url: &str) -> Result<Vec<Candidate>, DiscoveryError> {
+    let page_url = Url::parse(url).map_err(|source| DiscoveryError::InvalidURLError {
+        url: url.into(),
+        source,
+    })?;
+
+    let (page_url, body) = get(agent, &page_url)?;
+    if let Some(candidate) = feed_candidate(&page_url, &body) {
+        return Ok(vec![candidate]);
+    }
+
+    let candidates = link_candidates(&String::from_utf8_lossy(&body), &page_url);
+    if !candidates.is_empty() {
+        return Ok(candidates);
+    }
+
+    for path in COMMON_PATHS {
+        let Ok(probe_url) = page_url.join(path) else {
+            continue;
+        };
+        // Most of these are going to be 404s, which is fine.
+        if let Ok((probe_url, body)) = get(agent, &probe_url) {
+            if let Some(candidate) = feed_candidate(&probe_url, &body) {
+                return Ok(vec![candidate]);
+            }
+        }
+    }
+
+    Ok(Vec::new())
+}
+
+/// get downloads `url` and returns the URL we ended up at after redirects
+/// together with the body.
+fn get(agent: &ureq::Agent, url: &Url) -> Result<(Url, Vec<u8>), DiscoveryError> {
+    let response = agent.get(url.as_str()).call().map_err(Box::new)?;
+    let final_url = Url::parse(response.get_url()).unwrap_or_else(|_| url.clone());
+
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_PAGE_BYTES)
+        .read_to_end(&mut body)?;
+    Ok((final_url, body))
+}
+
+fn feed_candidate(url: &Url, body: &[u8]) -> Option<Candidate> {
+    let feed = feed_rs::parser::parse(body).ok()?;
+    Some(Candidate {
+        url: url.to_string(),
+        title: feed.title.map(|title| title.content),
+    })
+}
+
+/// link_candidates collects the feeds a page announces in its `<link>`
+/// elements, resolving their URLs against the page or its `<base>`.
+pub fn link_candidates(page: &str, page_url: &Url) -> Vec<Candidate> {
+    let nodes = html::parse_fragment(page);
+
+    let mut base_url = page_url.clone();
+    html::walk_elements(&nodes, &mut |element| {
+        if element.name == "base" {
+            if let Some(base) = element
+                .attr("href")
+                .and_then(|href| page_url.join(href).ok())
+            {
+                base_url = base;
+            }
+        }
+    });
+
+    let mut candidates: Vec<Candidate> = Vec::new();
+    html::walk_elements(&nodes, &mut |element| {
+        if element.name != "link" {
+            return;
+        }
+
+        let is_alternate = element.attr("rel").is_some_and(|rel| {
+            rel.split_ascii_whitespace()
+                .any(|rel| rel.eq_ignore_ascii_case("alternate"))
+        });
+        let is_feed = element.attr("type").is_some_and(|mime_type| {
+            let mime_type = mime_type.split(';').next().unwrap_or_default().trim();
+            FEED_TYPES
+                .iter()
+                .any(|feed_type| feed_type.eq_ignore_ascii_case(mime_type))
+        });
+        if !is_alternate || !is_feed {
+            return;
+        }
+
+        let Some(url) = element
+            .attr("href")
+            .and_then(|href| base_url.join(href.trim()).ok())
+        else {
+            return;
+        };
+        if candidates
+            .iter()
+            .any(|candidate| candidate.url == url.as_str())
+        {
+            return;
+        }
+        candidates.push(Candidate {
+            url: url.into(),
+            title: element
+                .attr("title")
+                .map(str::trim)
+                .filter(|title| !title.is_empty())
+                .map(String::from),
+        });
+    });
+
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{TestResponse, TestServer};
+
+    const RSS: &str = r#"<?xml version="1.0"?>
+<rss version="2.0"><channel><title>Example Blog</title><link>https://example.com</link>
+<item><title>First</title><guid>1</guid></item></channel></rss>"#;
+
+    fn agent() -> ureq::Agent {
+        ureq::AgentBuilder::new().build()
+    }
+
+    #[test]
+    fn discover_link_alternate() {
+        let server = TestServer::start(vec![TestResponse::new(
+            200,
+            r#"<!DOCTYPE html>
+<html><head>
+  <title>Example Blog</title>
+  <link rel="stylesheet" href="/style.css" type="text/css">
+  <link rel="alternate" type="application/rss+xml" title="Posts" href="/posts/index.xml">
+  <link rel="Alternate" type="application/atom+xml; charset=utf-8" href="https://feeds.example.com/atom">
+  <link rel="alternate" type="text/html" hreflang="de" href="/de/">
+  <link rel="alternate" type="application/rss+xml" title="Posts again" href="/posts/index.xml">
+</head><body><p>Hello</p></body></html>"#,
+        )
+        .header("Content-Type", "text/html")]);
+
+        let candidates = discover(&agent(), &server.url("/blog/")).expect("failed to discover");
+        assert_eq!(
+            candidates,
+            vec![
+                Candidate {
+                    url: server.url("/posts/index.xml"),
+                    title: Some("Posts".into()),
+                },
+                Candidate {
+                    url: "https://feeds.example.com/atom".into(),
+                    title: None,
+                },
+            ]
+        );
+        assert_eq!(server.next_request().path, "/blog/");
+    }
+
+    #[test]
+    fn discover_common_paths() {
+        let server = TestServer::start(vec![
+            TestResponse::new(200, "<html><body>No feeds here</body></html>"),
+            TestResponse::new(404, "not found"),
+            TestResponse::new(200, RSS).header("Content-Type", "application/rss+xml"),
+        ]);
+
+        let candidates = discover(&agent(), &server.url("/about")).expect("failed to discover");
+        assert_eq!(
+            candidates,
+            vec![Candidate {
+                url: server.url("/rss"),
+                title: Some("Example Blog".into()),
+            }]
+        );
+        assert_eq!(server.next_request().path, "/about");
+        assert_eq!(server.next_request().path, "/feed");
+        assert_eq!(server.next_request().path, "/rss");
+    }
+
+    #[test]
+    fn discover_feed_url() {
+        let server = TestServer::start(vec![
+            TestResponse::new(200, RSS).header("Content-Type", "application/rss+xml")
+        ]);
+
+        let candidates = discover(&agent(), &server.url("/rss")).expect("failed to discover");
+        assert_eq!(
+            candidates,
+            vec![Candidate {
+                url: server.url("/rss"),
+                title: Some("Example Blog".into()),
+            }]
+        );
+    }
+
+    #[test]
+    fn link_candidates_respect_base() {
+        let page_url = Url::parse("https://example.com/blog/post").unwrap();
+        let candidates = link_candidates(
+            r#"<base href="https://cdn.example.com/site/"><link rel="alternate" type="application/feed+json" href="feed.json">"#,
+            &page_url,
+        );
+        assert_eq!(
+            candidates,
+            vec![Candidate {
+                url: "https://cdn.example.com/site/feed.json".into(),
+                title: None,
+            }]
+        );
+    }
+}
@@ -0,0 +1,387 @@
+//! Just enough MIME (RFC 2045 to 2047) to read the newsletters that end up in
+//! a Maildir, everything we don't understand is kept as raw bytes.
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use std::collections::HashMap;
+
+/// Part is a mail message or one of the parts of a multipart message.
+#[derive(Debug, Default)]
+pub struct Part {
+    /// headers are unfolded but not decoded, names keep their case.
+    pub headers: Vec<(String, String)>,
+    /// body is the content with its transfer encoding undone, empty for
+    /// multipart parts.
+    pub body: Vec<u8>,
+    pub parts: Vec<Part>,
+}
+
+impl Part {
+    /// parse reads a message, it never fails since mail in the wild is too
+    /// broken to be strict about.
+    pub fn parse(raw: &[u8]) -> Part {
+        let (header_bytes, body) = split_headers(raw);
+        let mut part = Part {
+            headers: parse_headers(&String::from_utf8_lossy(header_bytes)),
+            ..Default::default()
+        };
+
+        let (mime_type, params) = part.content_type();
+        match params.get("boundary") {
+            Some(boundary) if mime_type.starts_with("multipart/") => {
+                part.parts = split_multipart(body, boundary)
+                    .into_iter()
+                    .map(Part::parse)
+                    .collect();
+            }
+            _ => {
+                part.body = match part
+                    .header("Content-Transfer-Encoding")
+                    .map(|encoding| encoding.trim().to_ascii_lowercase())
+                    .as_deref()
+                {
+                    Some("base64") => decode_base64(body),
+                    Some("quoted-printable") => decode_quoted_printable(body),
+                    _ => body.to_vec(),
+                };
+            }
+        }
+
+        part
+    }
+
+    /// header returns the raw value of the first header with the given name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// decoded_header returns a header with its encoded words decoded.
+    pub fn decoded_header(&self, name: &str) -> Option<String> {
+        self.header(name).map(decode_header)
+    }
+
+    /// content_type returns the lowercased MIME type and the parameters of
+    /// the part, parts without one are plain text.
+    pub fn content_type(&self) -> (String, HashMap<String, String>) {
+        match self.header("Content-Type") {
+            Some(content_type) => {
+                let (mime_type, params) = parse_parameters(content_type);
+                (mime_type.to_ascii_lowercase(), params)
+            }
+            None => ("text/plain".into(), HashMap::new()),
+        }
+    }
+
+    /// content_id returns the Content-ID without its angle brackets, it is
+    /// what `cid:` URLs refer to.
+    pub fn content_id(&self) -> Option<String> {
+        let content_id = self.header("Content-ID")?.trim();
+        let content_id = content_id
+            .strip_prefix('<')
+            .and_then(|id| id.strip_suffix('>'))
+            .unwrap_or(content_id);
+        Some(content_id.to_string())
+    }
+
+    pub fn is_attachment(&self) -> bool {
+        self.header("Content-Disposition")
+            .is_some_and(|disposition| {
+                parse_parameters(disposition)
+                    .0
+                    .eq_ignore_ascii_case("attachment")
+            })
+    }
+
+    /// text decodes the body using the charset of the part, falling back to
+    /// UTF-8.
+    pub fn text(&self) -> String {
+        let (_, params) = self.content_type();
+        let encoding = params
+            .get("charset")
+            .and_then(|charset| encoding_rs::Encoding::for_label(charset.as_bytes()))
+            .unwrap_or(encoding_rs::UTF_8);
+        encoding.decode(&self.body).0.into_owned()
+    }
+
+    /// walk calls `f` for the part itself and every part nested in it, depth
+    /// first.
+    pub fn walk<'a>(&'a self, f: &mut impl FnMut(&'a Part)) {
+        f(self);
+        for part in &self.parts {
+            part.walk(f);
+        }
+    }
+}
+
+fn split_headers(raw: &[u8]) -> (&[u8], &[u8]) {
+    for (i, window) in raw.windows(2).enumerate() {
+        if window == b"\n\n" {
+            return (&raw[..i], &raw[i + 2..]);
+        }
+        if window == b"\n\r" && raw.get(i + 2) == Some(&b'\n') {
+            return (&raw[..i], &raw[i + 3..]);
+        }
+    }
+    (raw, &[])
+}
+
+fn parse_headers(headers: &str) -> Vec<(String, String)> {
+    let mut parsed: Vec<(String, String)> = Vec::new();
+    for line in headers.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with([' ', '\t']) {
+            // A folded continuation of the previous header.
+            if let Some((_, value)) = parsed.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            parsed.push((name.trim().into(), value.trim().into()));
+        }
+    }
+    parsed
+}
+
+/// parse_parameters splits a header like `text/html; charset="utf-8"` into
+/// its value and its parameters, parameter names are lowercased.
+fn parse_parameters(header: &str) -> (String, HashMap<String, String>) {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in header.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => parts.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    parts.push(current);
+
+    let mut parts = parts.into_iter();
+    let value = parts.next().unwrap_or_default().trim().to_string();
+    let params = parts
+        .filter_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            Some((name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        })
+        .collect();
+    (value, params)
+}
+
+/// split_multipart returns the raw parts between the boundaries, the
+/// preamble and epilogue are dropped.
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{boundary}");
+    let mut parts = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut offset = 0;
+
+    for line in body.split_inclusive(|&b| b == b'\n') {
+        let trimmed = line.trim_ascii_end();
+        if trimmed.starts_with(delimiter.as_bytes()) {
+            if let Some(start) = start {
+                // The line break before the delimiter belongs to it.
+                let end = offset
+                    - if body[..offset].ends_with(b"\r\n") {
+                        2
+                    } else {
+                        usize::from(body[..offset].ends_with(b"\n"))
+                    };
+                parts.push(&body[start..end.max(start)]);
+            }
+            if trimmed[delimiter.len()..].starts_with(b"--") {
+                return parts;
+            }
+            start = Some(offset + line.len());
+        }
+        offset += line.len();
+    }
+
+    // No closing delimiter, keep what we have.
+    if let Some(start) = start {
+        parts.push(&body[start..]);
+    }
+    parts
+}
+
+fn decode_base64(body: &[u8]) -> Vec<u8> {
+    let cleaned: Vec<u8> = body
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    BASE64_STANDARD
+        .decode(&cleaned)
+        .unwrap_or_else(|_| body.to_vec())
+}
+
+fn decode_quoted_printable(body: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(body.len());
+    let mut i = 0;
+    while i < body.len() {
+        if body[i] != b'=' {
+            decoded.push(body[i]);
+            i += 1;
+            continue;
+        }
+
+        match body.get(i + 1..i + 3) {
+            // Soft line breaks only exist to keep lines short.
+            Some([b'\r', b'\n']) => i += 3,
+            Some([b'\n', _]) => i += 2,
+            Some(hex) => match std::str::from_utf8(hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 3;
+                }
+                None => {
+                    decoded.push(b'=');
+                    i += 1;
+                }
+            },
+            None if body.get(i + 1) == Some(&b'\n') => i += 2,
+            None => {
+                decoded.push(b'=');
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// decode_header decodes the `=?charset?B?...?=` and `=?charset?Q?...?=`
+/// encoded words of RFC 2047, whitespace between two of them is dropped.
+pub fn decode_header(value: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = value;
+    let mut after_encoded_word = false;
+
+    while let Some(start) = rest.find("=?") {
+        let Some(word) = decode_encoded_word(&rest[start..]) else {
+            decoded.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            after_encoded_word = false;
+            continue;
+        };
+
+        let between = &rest[..start];
+        if !(after_encoded_word && between.trim().is_empty()) {
+            decoded.push_str(between);
+        }
+        decoded.push_str(&word.0);
+        rest = &rest[start + word.1..];
+        after_encoded_word = true;
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// decode_encoded_word decodes the encoded word `text` starts with and
+/// returns it together with how many bytes of `text` it took up.
+fn decode_encoded_word(text: &str) -> Option<(String, usize)> {
+    let inner = text.strip_prefix("=?")?;
+    let (charset, inner) = inner.split_once('?')?;
+    let (encoding, inner) = inner.split_once('?')?;
+    let end = inner.find("?=")?;
+    let encoded = &inner[..end];
+
+    let bytes = match encoding {
+        "B" | "b" => BASE64_STANDARD.decode(encoded).ok()?,
+        "Q" | "q" => decode_quoted_printable(encoded.replace('_', " ").as_bytes()),
+        _ => return None,
+    };
+    // RFC 2231 allows a language after the charset.
+    let label = charset.split('*').next().unwrap_or(charset);
+    let text_encoding =
+        encoding_rs::Encoding::for_label(label.as_bytes()).unwrap_or(encoding_rs::UTF_8);
+
+    let length = "=?".len() + charset.len() + 1 + encoding.len() + 1 + end + "?=".len();
+    Some((text_encoding.decode(&bytes).0.into_owned(), length))
+}
+
+/// sender_name picks the display name out of an address like
+/// `"Jane Doe" <jane@example.com>`, falling back to the address itself.
+pub fn sender_name(from: &str) -> String {
+    let from = decode_header(from);
+    match from.split_once('<') {
+        Some((name, address)) => {
+            let name = name.trim().trim_matches('"').trim();
+            if name.is_empty() {
+                address.trim_end_matches('>').trim().to_string()
+            } else {
+                name.to_string()
+            }
+        }
+        None => from.trim().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_encoded_words() {
+        assert_eq!(
+            decode_header("=?UTF-8?B?SGVsbG8gd8O2cmxk?= =?ISO-8859-1?Q?caf=E9_au_lait?="),
+            "Hello wörldcafé au lait"
+        );
+        assert_eq!(
+            decode_header("Re: =?utf-8?q?Gr=C3=BC=C3=9Fe?= from me"),
+            "Re: Grüße from me"
+        );
+        assert_eq!(
+            decode_header("plain =? not encoded"),
+            "plain =? not encoded"
+        );
+    }
+
+    #[test]
+    fn sender_names() {
+        assert_eq!(sender_name("\"Jane Doe\" <jane@example.com>"), "Jane Doe");
+        assert_eq!(sender_name("<jane@example.com>"), "jane@example.com");
+        assert_eq!(sender_name("jane@example.com"), "jane@example.com");
+    }
+
+    #[test]
+    fn parse_nested_multipart() {
+        let message = Part::parse(include_bytes!("testdata/newsletter.eml"));
+
+        assert_eq!(
+            message.decoded_header("Subject").as_deref(),
+            Some("Weekly — Issue 42")
+        );
+        assert_eq!(message.parts.len(), 2);
+
+        let mut types = Vec::new();
+        message.walk(&mut |part| types.push(part.content_type().0));
+        assert_eq!(
+            types,
+            vec![
+                "multipart/related",
+                "multipart/alternative",
+                "text/plain",
+                "text/html",
+                "image/png",
+            ]
+        );
+
+        let alternative = &message.parts[0];
+        assert_eq!(
+            alternative.parts[0].text(),
+            "Hello readers,\r\n\r\nthis week in café news."
+        );
+        assert!(alternative.parts[1]
+            .text()
+            .contains(r#"<img src="cid:logo@example.com" alt="Logo">"#));
+
+        let image = &message.parts[1];
+        assert_eq!(image.content_id().as_deref(), Some("logo@example.com"));
+        assert!(image.body.starts_with(b"\x89PNG\r\n\x1a\n"));
+    }
+}
@@ -0,0 +1,279 @@
+//! Reads newsletters delivered to a local Maildir, every mail in `new/`
+//! becomes an entry of the feed and is moved to `cur/` once it is stored.
+
+use super::mail::{self, Part};
+use super::source::Source;
+use super::{FeedReader, FetchError, FetchOutcome};
+use crate::html;
+use crate::storage::{Entry, EntryChange, FeedStats};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use jiff::Timestamp;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// MaildirSource reads the Maildir the feed's url points at.
+pub struct MaildirSource;
+
+impl Source for MaildirSource {
+    fn fetch(
+        &self,
+        reader: &FeedReader,
+        feed_name: &str,
+        mut feed_stats: FeedStats,
+        now: Timestamp,
+    ) -> Result<FetchOutcome, FetchError> {
+        let maildir = Path::new(&reader.config.feeds[feed_name].url);
+
+        let new_mail = match new_mail(maildir) {
+            Ok(new_mail) => new_mail,
+            Err(err) => {
+                reader.record_failure(feed_stats.id, now, None, err.to_string())?;
+                return Err(err);
+            }
+        };
+
+        let mut entries = Vec::new();
+        for path in new_mail {
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let raw = match fs::read(&path) {
+                Ok(raw) => raw,
+                Err(err) => {
+                    eprintln!("failed to read mail {}: {err}", path.display());
+                    continue;
+                }
+            };
+
+            let entry = message_to_entry(feed_stats.id, file_name, &Part::parse(&raw));
+            match reader.storage().entry_to_db(&entry) {
+                Ok(EntryChange::Unchanged) => (),
+                Ok(_) => entries.push(entry),
+                Err(err) => {
+                    // Leave it in new/ so we try again next time.
+                    eprintln!("failed to store mail {}: {err}", path.display());
+                    continue;
+                }
+            }
+
+            // Everything after the colon is flags, which new mail shouldn't
+            // have but some delivery agents set anyway.
+            let unique = file_name.split(':').next().unwrap_or(file_name);
+            let processed = maildir.join("cur").join(format!("{unique}:2,S"));
+            if let Err(err) = fs::rename(&path, &processed) {
+                eprintln!(
+                    "failed to move mail {} to {}: {err}",
+                    path.display(),
+                    processed.display()
+                );
+            }
+        }
+
+        feed_stats.last_fetched = Some(now);
+        reader.storage().feed_stats_to_db(&feed_stats)?;
+        reader.storage().clear_backoff(feed_stats.id)?;
+        Ok(FetchOutcome::Fetched(entries))
+    }
+}
+
+/// new_mail lists the mail in `new/`, oldest first as far as the unique
+/// names of Maildir tell.
+fn new_mail(maildir: &Path) -> Result<Vec<PathBuf>, FetchError> {
+    let new = maildir.join("new");
+    let to_error = |source| FetchError::LocalFeedError {
+        path: new.display().to_string(),
+        source,
+    };
+
+    let mut paths = Vec::new();
+    for dir_entry in fs::read_dir(&new).map_err(to_error)? {
+        let path = dir_entry.map_err(to_error)?.path();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// message_to_entry turns a mail into an entry, the Message-ID identifies it
+/// and the sender is its author.
+fn message_to_entry(feed_id: u64, file_name: &str, message: &Part) -> Entry {
+    let feed_entry_id = message
+        .header("Message-ID")
+        .map(|id| id.trim().trim_start_matches('<').trim_end_matches('>'))
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| file_name.split(':').next().unwrap_or(file_name));
+
+    Entry {
+        feed_id,
+        feed_entry_id: Some(feed_entry_id.into()),
+        title: message.decoded_header("Subject").unwrap_or_default(),
+        updated: None,
+        published: message
+            .header("Date")
+            .and_then(|date| jiff::fmt::rfc2822::parse(date).ok())
+            .map(|date| date.timestamp().to_string()),
+        authors: message
+            .header("From")
+            .map(mail::sender_name)
+            .into_iter()
+            .collect(),
+        summary: "".into(),
+        content: message_html(message),
+        link: None,
+        // Lists of several languages are rare enough to take the first.
+        language: message
+            .header("Content-Language")
+            .and_then(|languages| languages.split(',').next())
+            .map(str::trim)
+            .filter(|language| !language.is_empty())
+            .map(String::from),
+        categories: Vec::new(),
+    }
+}
+
+/// message_html picks the HTML part of a mail and embeds the inline images
+/// it references, mail without HTML has its text turned into paragraphs.
+fn message_html(message: &Part) -> String {
+    let mut html_part = None;
+    let mut text_part = None;
+    let mut inline_images: HashMap<String, (String, &Part)> = HashMap::new();
+
+    message.walk(&mut |part| {
+        let (mime_type, _) = part.content_type();
+        if mime_type.starts_with("image/") {
+            if let Some(content_id) = part.content_id() {
+                inline_images.insert(content_id, (mime_type.clone(), part));
+            }
+        }
+        if part.is_attachment() {
+            return;
+        }
+        match mime_type.as_str() {
+            "text/html" if html_part.is_none() => html_part = Some(part),
+            "text/plain" if text_part.is_none() => text_part = Some(part),
+            _ => (),
+        }
+    });
+
+    match (html_part, text_part) {
+        (Some(part), _) => embed_inline_images(&part.text(), &inline_images),
+        (None, Some(part)) => html::text_to_paragraphs(&part.text()),
+        (None, None) => String::new(),
+    }
+}
+
+/// embed_inline_images replaces the `cid:` URLs of inline images with data
+/// URIs, the EPUB builder turns those into resources of the book.
+fn embed_inline_images(content: &str, inline_images: &HashMap<String, (String, &Part)>) -> String {
+    if inline_images.is_empty() {
+        return content.into();
+    }
+
+    let mut nodes = html::parse_fragment(content);
+    html::walk_elements_mut(&mut nodes, &mut |element| {
+        if element.name != "img" {
+            return;
+        }
+        let Some((mime_type, part)) = element
+            .attr("src")
+            .and_then(|src| src.trim().strip_prefix("cid:"))
+            .and_then(|content_id| inline_images.get(content_id))
+        else {
+            return;
+        };
+        let data_uri = format!(
+            "data:{mime_type};base64,{}",
+            BASE64_STANDARD.encode(&part.body)
+        );
+        element.set_attr("src", &data_uri);
+    });
+    html::to_xhtml(&nodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed_reader::test_reader;
+
+    const NEWSLETTER: &[u8] = include_bytes!("testdata/newsletter.eml");
+
+    #[test]
+    fn fetch_maildir_newsletters() {
+        let maildir = tempfile::tempdir().unwrap();
+        for dir in ["new", "cur", "tmp"] {
+            fs::create_dir(maildir.path().join(dir)).unwrap();
+        }
+        fs::write(maildir.path().join("new/1735889400.M1P2.host"), NEWSLETTER).unwrap();
+
+        let reader = test_reader(&format!(
+            "
+[feeds.newsletter]
+type = \"maildir\"
+url = \"{}\"
+download_dir = \"/tmp/feed-to-epub-test\"
+",
+            maildir.path().display()
+        ));
+        let now: Timestamp = "2025-01-03T12:00:00Z".parse().unwrap();
+
+        let entries = match reader.fetch_feed("newsletter", "/tmp/feed-to-epub-test", now) {
+            Ok(FetchOutcome::Fetched(entries)) => entries,
+            other => panic!("expected the newsletter to be fetched, got {other:?}"),
+        };
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.feed_entry_id.as_deref(), Some("issue-42@example.com"));
+        assert_eq!(entry.title, "Weekly — Issue 42");
+        assert_eq!(entry.authors, ["Weekly Newsletter"]);
+        assert_eq!(entry.published.as_deref(), Some("2025-01-03T07:30:00Z"));
+        assert!(entry.content.contains("this week in caf&#233; news"));
+        assert!(entry.content.contains("need a soft line break"));
+        assert!(entry
+            .content
+            .contains(r#"<img src="data:image/png;base64,iVBORw0KGgo"#));
+
+        assert!(!maildir.path().join("new/1735889400.M1P2.host").exists());
+        assert!(maildir.path().join("cur/1735889400.M1P2.host:2,S").exists());
+
+        // Processed mail is not read again.
+        match reader.fetch_feed("newsletter", "/tmp/feed-to-epub-test", now) {
+            Ok(FetchOutcome::Fetched(entries)) => assert!(entries.is_empty()),
+            other => panic!("expected the newsletter to be fetched, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn fetch_broken_maildir_backs_off() {
+        let reader = test_reader(
+            "
+[feeds.newsletter]
+type = \"maildir\"
+url = \"/does/not/exist\"
+download_dir = \"/tmp/feed-to-epub-test\"
+",
+        );
+        let now: Timestamp = "2025-01-03T12:00:00Z".parse().unwrap();
+
+        assert!(reader
+            .fetch_feed("newsletter", "/tmp/feed-to-epub-test", now)
+            .is_err());
+        assert!(reader.next_due("newsletter").unwrap() > now);
+    }
+
+    #[test]
+    fn plain_text_mail_becomes_paragraphs() {
+        let message = Part::parse(
+            b"From: jane@example.com\nSubject: Hi\n\nFirst line\nstill first <paragraph>\n\nSecond\n",
+        );
+        let entry = message_to_entry(1, "1.M1.host", &message);
+        assert_eq!(entry.feed_entry_id.as_deref(), Some("1.M1.host"));
+        assert_eq!(entry.authors, ["jane@example.com"]);
+        assert_eq!(
+            entry.content,
+            "<p>First line<br />still first &lt;paragraph&gt;</p>\n<p>Second</p>"
+        );
+    }
+}
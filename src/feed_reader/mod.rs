@@ -1,13 +1,24 @@
-use crate::feed_reader::config::{ConditionalType, Config};
-use crate::storage::Storage;
-use feed_rs::model::Feed;
-use jiff::tz::TimeZone;
+use crate::feed_reader::config::{Config, SecretError, SourceType};
+use crate::readability;
+use crate::storage::{Backoff, Entry, EntryChange, FeedStats, Storage};
 use jiff::Timestamp;
+use source::Source;
 use std::fs;
+use std::sync::{Mutex, MutexGuard};
 use thiserror::Error;
-use ureq::Agent;
+use ureq::{Agent, AgentBuilder};
 
+pub mod backoff;
 pub mod config;
+pub mod config_editor;
+pub mod discovery;
+pub mod mail;
+pub mod maildir;
+pub mod opml;
+pub mod pool;
+pub mod schedule;
+pub mod scrape;
+pub mod source;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -24,68 +35,167 @@ pub enum FetchError {
     #[error("storage error: {0}")]
     StorageNewFeedError(#[from] crate::storage::ErrorNewFeedStats),
     #[error("failed to execute HTTP request: {0}")]
-    HTTPError(#[from] ureq::Error),
+    HTTPError(#[from] Box<ureq::Error>),
+    #[error("failed to read credentials: {0}")]
+    SecretError(#[from] SecretError),
+    #[error("{0} is not a valid file URL")]
+    FileURLError(String),
+    #[error("failed to read local feed {path}: {source}")]
+    LocalFeedError {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("gave up following redirects at {url}, last answer was HTTP {status}")]
+    RedirectError { url: String, status: u16 },
+    #[error("failed to scrape listing: {0}")]
+    ScrapeError(#[from] scrape::ScrapeError),
+}
+
+/// FetchOutcome describes what happened to a feed that was fetched
+/// without errors, a 304 is a perfectly normal answer to a conditional
+/// request and should not be treated as a failure.
+#[derive(Debug)]
+pub enum FetchOutcome {
+    /// Fetched only carries the entries that are new or changed since the
+    /// last time they were stored.
+    Fetched(Vec<Entry>),
+    NotModified,
+    /// BackedOff is returned when the feed is not polled because the server
+    /// asked us to slow down or previous fetches failed.
+    BackedOff(Timestamp),
+    /// Gone is returned for feeds that answered with 410 Gone, they are not
+    /// polled again until they are revived.
+    Gone(Timestamp),
+}
+
+/// new_agent builds the HTTP agent everything we download goes through.
+pub fn new_agent(http_request_timeout_secs: u64) -> Agent {
+    agent_builder(http_request_timeout_secs).build()
+}
+
+fn agent_builder(http_request_timeout_secs: u64) -> AgentBuilder {
+    AgentBuilder::new()
+        .user_agent(&format!(
+            "feed-to-epub {VERSION}; +https:/github.com/catouc/feed-to-epub"
+        ))
+        .timeout(std::time::Duration::from_secs(http_request_timeout_secs))
 }
 
 pub struct FeedReader {
     agent: Agent,
-    storage: Storage,
+    /// feed_agent does not follow redirects on its own, we need to see them
+    /// to notice feeds that moved.
+    feed_agent: Agent,
+    /// storage is shared by every worker fetching feeds, rusqlite connections
+    /// cannot be used from several threads at once.
+    storage: Mutex<Storage>,
     pub config: Config,
 }
 
 impl FeedReader {
     pub fn new(config: Config) -> Result<Self, crate::storage::ErrorNew> {
         let storage = Storage::new(&config.db_file)?;
-        storage.init_database()?;
+        Self::new_with_storage(config, storage)
+    }
 
-        let agent = ureq::AgentBuilder::new()
-            .user_agent(&format!(
-                "feed-to-epub {VERSION}; +https:/github.com/catouc/feed-to-epub"
-            ))
-            .timeout(std::time::Duration::from_secs(
-                config.http_request_timeout_secs,
-            ))
+    pub fn new_with_storage(
+        config: Config,
+        storage: Storage,
+    ) -> Result<Self, crate::storage::ErrorNew> {
+        storage.init_database()?;
+        let agent = new_agent(config.http_request_timeout_secs);
+        let feed_agent = agent_builder(config.http_request_timeout_secs)
+            .redirects(0)
             .build();
 
         Ok(FeedReader {
             agent,
-            storage,
+            feed_agent,
+            storage: Mutex::new(storage),
             config,
         })
     }
 
-    pub fn fetch_all(&self, now: Timestamp) -> Vec<Feed> {
+    pub fn fetch_all(&self, now: Timestamp) -> Vec<Entry> {
         self.config
             .feeds
             .iter()
-            .filter_map(|feed_stats| {
+            .flat_map(|feed_stats| {
                 let url = feed_stats.0;
                 match self.fetch_feed(url, &feed_stats.1.download_dir, now) {
-                    Ok(feed) => feed,
+                    Ok(FetchOutcome::Fetched(entries)) => entries,
+                    Ok(_) => Vec::new(),
                     Err(err) => {
                         eprintln!("failed to fetch url {url}: {err}");
-                        None
+                        Vec::new()
                     }
                 }
             })
             .collect()
     }
 
+    /// agent returns the HTTP agent used for feeds so that everything else we
+    /// download goes out with the same user agent and timeouts.
+    pub fn agent(&self) -> &Agent {
+        &self.agent
+    }
+
+    /// storage gives access to the database the fetched entries end up in.
+    /// Other workers wait for the database while the guard is held, so don't
+    /// keep it around across anything slow.
+    pub fn storage(&self) -> MutexGuard<'_, Storage> {
+        self.storage
+            .lock()
+            .expect("a thread panicked while writing to the database")
+    }
+
+    /// next_due returns when the given feed should be fetched next, based on
+    /// its poll interval, the last time it was fetched and any backoff. Feeds
+    /// that are gone are never due.
+    pub fn next_due(&self, feed_name: &str) -> Result<Timestamp, FetchError> {
+        let feed_stats = self
+            .storage()
+            .feed_stats_from_db(&self.config.feeds[feed_name].url)?;
+        let (last_fetched, backoff) = match feed_stats {
+            Some(feed_stats) if feed_stats.gone_since.is_some() => return Ok(Timestamp::MAX),
+            Some(feed_stats) => (
+                feed_stats.last_fetched,
+                self.storage().backoff_from_db(feed_stats.id)?,
+            ),
+            None => (None, None),
+        };
+
+        Ok(schedule::next_due(
+            last_fetched,
+            self.config.feed_poll_interval_secs(feed_name),
+            backoff.as_ref(),
+        ))
+    }
+
+    /// schedule returns every configured feed with the time it is due next,
+    /// ordered so that the feed that is due first comes first.
+    pub fn schedule(&self) -> Result<Vec<(String, Timestamp)>, FetchError> {
+        let mut schedule = self
+            .config
+            .feeds
+            .keys()
+            .map(|feed_name| Ok((feed_name.clone(), self.next_due(feed_name)?)))
+            .collect::<Result<Vec<_>, FetchError>>()?;
+        schedule.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(schedule)
+    }
+
     pub fn fetch_feed(
         &self,
         feed_name: &str,
         download_dir: &str,
         now: Timestamp,
-    ) -> Result<Option<Feed>, FetchError> {
-        let mut feed_stats = match self
-            .storage
-            .feed_stats_from_db(&self.config.feeds[feed_name].url)?
-        {
-            Some(feed_stats) => feed_stats,
-            None => self
-                .storage
-                .new_feed_stats_to_db(&self.config.feeds[feed_name].url)?,
-        };
+    ) -> Result<FetchOutcome, FetchError> {
+        let feed_stats = self.feed_stats(feed_name)?;
+
+        if let Some(gone_since) = feed_stats.gone_since {
+            return Ok(FetchOutcome::Gone(gone_since));
+        }
 
         match fs::create_dir_all(download_dir) {
             Ok(_) => (),
@@ -96,84 +206,665 @@ impl FeedReader {
             }
         };
 
-        if let Some(last_fetched) = feed_stats.last_fetched {
-            let time_diff = now
-                .to_zoned(TimeZone::UTC)
-                .duration_since(&last_fetched.to_zoned(TimeZone::UTC));
-
-            if time_diff.as_hours() < 2 {
+        if let Some(backoff) = self.storage().backoff_from_db(feed_stats.id)? {
+            if backoff.blocked_until > now {
                 println!(
-                    "{feed_name} was already fetched within the last two hours at {time_diff}."
+                    "{feed_name} is backed off until {} after {} failures",
+                    backoff.blocked_until, backoff.failure_count
                 );
-                return Ok(None);
-            };
+                return Ok(FetchOutcome::BackedOff(backoff.blocked_until));
+            }
         };
 
-        let mut request = self.agent.get(&self.config.feeds[feed_name].url);
+        self.source(feed_name)
+            .fetch(self, feed_name, feed_stats, now)
+    }
+
+    /// fetch_from_reader stores the entries of a feed document that did not
+    /// come from the feed's URL, e.g. one piped in by another tool. It does
+    /// not count as a fetch, the feed is still polled on its usual schedule.
+    pub fn fetch_from_reader(
+        &self,
+        feed_name: &str,
+        reader: impl std::io::Read,
+    ) -> Result<FetchOutcome, FetchError> {
+        let feed_stats = self.feed_stats(feed_name)?;
+        let feed = feed_rs::parser::parse(reader)?;
+        Ok(FetchOutcome::Fetched(self.store_entries(
+            feed_name,
+            feed_stats.id,
+            &feed,
+        )))
+    }
+
+    /// source returns where the entries of the given feed come from.
+    fn source(&self, feed_name: &str) -> &'static dyn Source {
+        match self.config.feeds[feed_name].source {
+            SourceType::Feed => &source::FeedSource,
+            SourceType::Maildir => &maildir::MaildirSource,
+            SourceType::Scrape => &scrape::ScrapeSource,
+        }
+    }
+
+    /// feed_stats returns what we know about the given feed, creating it in
+    /// the database if it was never fetched before.
+    fn feed_stats(&self, feed_name: &str) -> Result<FeedStats, FetchError> {
+        let url = &self.config.feeds[feed_name].url;
+        let feed_stats = self.storage().feed_stats_from_db(url)?;
+        match feed_stats {
+            Some(feed_stats) => Ok(feed_stats),
+            None => Ok(self.storage().new_feed_stats_to_db(url)?),
+        }
+    }
+
+    /// store_entries stores every entry of the feed and only hands back the
+    /// ones that are new or changed, everything else already has an EPUB that
+    /// e-readers might have synced.
+    fn store_entries(
+        &self,
+        feed_name: &str,
+        feed_id: u64,
+        feed: &feed_rs::model::Feed,
+    ) -> Vec<Entry> {
+        let fetch_full_article = self.config.feeds[feed_name].fetch_full_article;
+        feed.entries
+            .iter()
+            .filter_map(|e| {
+                let mut entry = match crate::storage::entry_from_feed_entry(feed_id, e) {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        // TODO: we really shouldn't log the error here I think
+                        eprintln!("{err}");
+                        return None;
+                    }
+                };
+                if entry.language.is_none() {
+                    entry.language.clone_from(&feed.language);
+                }
+
+                let mut article_fetched = None;
+                if fetch_full_article {
+                    // The stored content is the article, not what the feed
+                    // sent, so it can't tell us whether the entry changed.
+                    // Entries whose article couldn't be fetched are always
+                    // tried again.
+                    let stored = self
+                        .storage()
+                        .full_article_entry_from_db(feed_id, entry.feed_entry_id.as_deref()?);
+                    match stored {
+                        Ok(Some(stored))
+                            if stored.title == entry.title
+                                && stored.updated == entry.updated
+                                && stored.summary == entry.summary =>
+                        {
+                            return None;
+                        }
+                        Ok(_) => {
+                            article_fetched =
+                                Some(self.replace_with_full_article(feed_name, &mut entry));
+                        }
+                        Err(err) => {
+                            eprintln!("{err}");
+                            return None;
+                        }
+                    }
+                }
 
-        match &self.config.feeds[feed_name].conditional_type {
-            ConditionalType::ETag => {
-                if let Some(etag) = &feed_stats.etag {
-                    request = request.set("ETag", etag);
+                let change = self.storage().entry_to_db(&entry);
+                if let (Ok(_), Some(fetched), Some(feed_entry_id)) =
+                    (&change, article_fetched, &entry.feed_entry_id)
+                {
+                    if let Err(err) =
+                        self.storage()
+                            .article_fetched_to_db(feed_id, feed_entry_id, fetched)
+                    {
+                        eprintln!("{err}");
+                    }
                 }
-            }
-            ConditionalType::LastModified => {
-                // This is essentially only happening on the first time we ever fetch the feed
-                if let Some(last_modified) = &feed_stats.last_modified {
-                    request = request.set("If-Modified-Since", last_modified);
+                match change {
+                    Ok(EntryChange::Unchanged) => None,
+                    Ok(_) => Some(entry),
+                    Err(err) => {
+                        eprintln!("{err}");
+                        None
+                    }
                 }
+            })
+            .collect()
+    }
+
+    /// replace_with_full_article swaps the content of an entry for the
+    /// article its link points at. The summary stays what the feed sent, and
+    /// if the article can't be had the entry keeps the feed's content. It
+    /// returns false if downloading the article failed and is worth another
+    /// try.
+    fn replace_with_full_article(&self, feed_name: &str, entry: &mut Entry) -> bool {
+        let Some(link) = entry.link.as_deref() else {
+            return true;
+        };
+        let Ok(url) = url::Url::parse(link) else {
+            eprintln!("not fetching the full article of {feed_name}, {link} is not a valid URL");
+            return true;
+        };
+
+        match scrape::download_page(self, &self.config.feeds[feed_name], &url, &[]) {
+            Ok(page) => match readability::extract_article(&page) {
+                Some(article) => entry.content = article,
+                None => eprintln!("found no article at {link}, keeping the feed's content"),
+            },
+            Err(err) => {
+                eprintln!(
+                    "failed to fetch the full article at {link}, trying again next time: {err}"
+                );
+                return false;
             }
+        }
+        true
+    }
+
+    /// record_failure bumps the failure counter of a feed and blocks it either
+    /// until the time the server asked for or for an exponentially growing
+    /// amount of time.
+    fn record_failure(
+        &self,
+        feed_id: u64,
+        now: Timestamp,
+        retry_after: Option<Timestamp>,
+        error: String,
+    ) -> Result<Backoff, crate::storage::ErrorDBOperation> {
+        let failure_count = match self.storage().backoff_from_db(feed_id)? {
+            Some(backoff) => backoff.failure_count.saturating_add(1),
+            None => 1,
+        };
+
+        let blocked_until = retry_after.unwrap_or_else(|| {
+            now.saturating_add(backoff::exponential_backoff(failure_count))
+                .expect("signed durations only carry time units")
+        });
+
+        let backoff = Backoff {
+            feed_id,
+            blocked_until,
+            failure_count,
+            last_error: Some(error),
         };
+        self.storage().backoff_to_db(&backoff)?;
+        Ok(backoff)
+    }
+}
+
+/// test_reader sets up a FeedReader for the TOML configuration `config`,
+/// with its database in memory.
+#[cfg(test)]
+fn test_reader(config: &str) -> FeedReader {
+    let config = Config::from_reader(config.as_bytes()).expect("failed to parse configuration");
+    let storage = Storage::new_in_memory().expect("failed to open in memory db");
+    FeedReader::new_with_storage(config, storage).expect("failed to set up feed reader")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{TestResponse, TestServer};
+    use url::Url;
+
+    const ATOM_FEED: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Test</title>
+  <id>urn:test</id>
+  <updated>2025-01-01T00:00:00Z</updated>
+  <entry>
+    <title>First</title>
+    <id>urn:test:1</id>
+    <updated>2025-01-01T00:00:00Z</updated>
+    <content type="html">&lt;p&gt;Hello&lt;/p&gt;</content>
+  </entry>
+</feed>"#;
+
+    /// feed_config is the configuration of a single feed named test.
+    fn feed_config(url: &str) -> String {
+        format!(
+            "
+[feeds.test]
+url = \"{url}\"
+download_dir = \"/tmp/feed-to-epub-test\"
+"
+        )
+    }
+
+    #[test]
+    fn fetch_feed_sends_validators_and_handles_not_modified() {
+        let server = TestServer::start(vec![
+            TestResponse::new(200, ATOM_FEED)
+                .header("ETag", "\"v1\"")
+                .header("Last-Modified", "Wed, 01 Jan 2025 00:00:00 GMT"),
+            TestResponse::new(304, ""),
+        ]);
+        let reader = test_reader(&feed_config(&server.url("/atom.xml")));
+        let first_poll: Timestamp = "2025-01-01T00:00:00Z".parse().unwrap();
+        let second_poll: Timestamp = "2025-01-01T05:00:00Z".parse().unwrap();
+
+        let outcome = reader
+            .fetch_feed("test", "/tmp/feed-to-epub-test", first_poll)
+            .expect("first fetch failed");
+        assert!(matches!(outcome, FetchOutcome::Fetched(_)));
+        let request = server.next_request();
+        assert_eq!(request.path, "/atom.xml");
+        assert_eq!(request.header("If-None-Match"), None);
+        assert_eq!(request.header("If-Modified-Since"), None);
+
+        let outcome = reader
+            .fetch_feed("test", "/tmp/feed-to-epub-test", second_poll)
+            .expect("conditional fetch failed");
+        assert!(matches!(outcome, FetchOutcome::NotModified));
+        let request = server.next_request();
+        assert_eq!(request.header("If-None-Match"), Some("\"v1\""));
+        assert_eq!(
+            request.header("If-Modified-Since"),
+            Some("Wed, 01 Jan 2025 00:00:00 GMT")
+        );
+
+        let feed_stats = reader
+            .storage()
+            .feed_stats_from_db(&server.url("/atom.xml"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(feed_stats.last_fetched, Some(second_poll));
+        assert_eq!(feed_stats.etag.as_deref(), Some("\"v1\""));
+    }
+
+    #[test]
+    fn fetch_feed_backs_off_on_retry_after() {
+        let server = TestServer::start(vec![
+            TestResponse::new(429, "").header("Retry-After", "7200"),
+            TestResponse::new(200, ATOM_FEED),
+        ]);
+        let reader = test_reader(&feed_config(&server.url("/atom.xml")));
+        let first_poll: Timestamp = "2025-01-01T00:00:00Z".parse().unwrap();
+        let blocked_until: Timestamp = "2025-01-01T02:00:00Z".parse().unwrap();
+
+        let outcome = reader
+            .fetch_feed("test", "/tmp/feed-to-epub-test", first_poll)
+            .expect("rate limited fetch failed");
+        assert!(matches!(outcome, FetchOutcome::BackedOff(until) if until == blocked_until));
+        server.next_request();
+
+        // Still blocked, this must not hit the server at all.
+        let outcome = reader
+            .fetch_feed(
+                "test",
+                "/tmp/feed-to-epub-test",
+                "2025-01-01T01:00:00Z".parse().unwrap(),
+            )
+            .expect("blocked fetch failed");
+        assert!(matches!(outcome, FetchOutcome::BackedOff(_)));
+
+        let outcome = reader
+            .fetch_feed(
+                "test",
+                "/tmp/feed-to-epub-test",
+                "2025-01-01T03:00:00Z".parse().unwrap(),
+            )
+            .expect("fetch after backoff failed");
+        assert!(matches!(outcome, FetchOutcome::Fetched(_)));
+
+        let feed_stats = reader
+            .storage()
+            .feed_stats_from_db(&server.url("/atom.xml"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            reader.storage().backoff_from_db(feed_stats.id).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn fetch_feed_only_returns_new_entries() {
+        let server = TestServer::start(vec![
+            TestResponse::new(200, ATOM_FEED),
+            TestResponse::new(200, ATOM_FEED),
+        ]);
+        let reader = test_reader(&feed_config(&server.url("/atom.xml")));
 
-        let response = request.call()?;
-        let feed_data = match response.status() {
-            304 => None,
-            429 => {
-                // TODO: I should add something to maybe a special table of feeds that have
-                // been rate limited to then check every iteration on whether we've gone past
-                // the `Retry-After` header expiry.
-                eprintln!("{feed_name} got a 429 rate limit error");
-                None
+        match reader.fetch_feed(
+            "test",
+            "/tmp/feed-to-epub-test",
+            "2025-01-01T00:00:00Z".parse().unwrap(),
+        ) {
+            Ok(FetchOutcome::Fetched(entries)) => {
+                assert_eq!(entries.len(), 1);
+                assert_eq!(entries[0].title, "First");
             }
-            _ => {
-                if let Some(last_modified_since) = response.header("Last-Modified") {
-                    feed_stats.last_modified = Some(last_modified_since.into());
-                }
+            other => panic!("expected a fetched feed, got {other:?}"),
+        }
 
-                if let Some(etag) = response.header("ETag") {
-                    feed_stats.etag = Some(etag.into());
-                }
+        match reader.fetch_feed(
+            "test",
+            "/tmp/feed-to-epub-test",
+            "2025-01-01T05:00:00Z".parse().unwrap(),
+        ) {
+            Ok(FetchOutcome::Fetched(entries)) => assert!(entries.is_empty()),
+            other => panic!("expected a fetched feed, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn fetch_feed_follows_permanent_redirects() {
+        let server = TestServer::start(vec![
+            TestResponse::new(301, "").header("Location", "/moved.xml"),
+            TestResponse::new(308, "").header("Location", "/final.xml"),
+            TestResponse::new(200, ATOM_FEED),
+            TestResponse::new(200, ATOM_FEED),
+        ]);
+        let reader = test_reader(&feed_config(&server.url("/atom.xml")));
+
+        let outcome = reader
+            .fetch_feed(
+                "test",
+                "/tmp/feed-to-epub-test",
+                "2025-01-01T00:00:00Z".parse().unwrap(),
+            )
+            .expect("redirected fetch failed");
+        assert!(matches!(outcome, FetchOutcome::Fetched(_)));
+        assert_eq!(server.next_request().path, "/atom.xml");
+        assert_eq!(server.next_request().path, "/moved.xml");
+        assert_eq!(server.next_request().path, "/final.xml");
+
+        let feed_stats = reader
+            .storage()
+            .feed_stats_from_db(&server.url("/atom.xml"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(feed_stats.moved_to, Some(server.url("/final.xml")));
+
+        // From now on the feed is fetched from where it moved to.
+        reader
+            .fetch_feed(
+                "test",
+                "/tmp/feed-to-epub-test",
+                "2025-01-01T05:00:00Z".parse().unwrap(),
+            )
+            .expect("fetch after move failed");
+        assert_eq!(server.next_request().path, "/final.xml");
+
+        // Once the config is updated it still finds the same feed.
+        assert_eq!(
+            reader
+                .storage()
+                .feed_stats_from_db(&server.url("/final.xml"))
+                .unwrap()
+                .map(|moved| moved.id),
+            Some(feed_stats.id)
+        );
+    }
+
+    #[test]
+    fn fetch_feed_ignores_temporary_redirects() {
+        let server = TestServer::start(vec![
+            TestResponse::new(301, "").header("Location", "/moved.xml"),
+            TestResponse::new(302, "").header("Location", "/elsewhere.xml"),
+            TestResponse::new(200, ATOM_FEED),
+        ]);
+        let reader = test_reader(&feed_config(&server.url("/atom.xml")));
+
+        reader
+            .fetch_feed(
+                "test",
+                "/tmp/feed-to-epub-test",
+                "2025-01-01T00:00:00Z".parse().unwrap(),
+            )
+            .expect("redirected fetch failed");
+
+        let feed_stats = reader
+            .storage()
+            .feed_stats_from_db(&server.url("/atom.xml"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(feed_stats.moved_to, None);
+    }
+
+    #[test]
+    fn fetch_feed_stops_polling_gone_feeds() {
+        let server = TestServer::start(vec![TestResponse::new(410, "")]);
+        let reader = test_reader(&feed_config(&server.url("/atom.xml")));
+        let gone_since: Timestamp = "2025-01-01T00:00:00Z".parse().unwrap();
 
-                let feed = feed_rs::parser::parse(response.into_reader())?;
-                Some(feed)
+        let outcome = reader
+            .fetch_feed("test", "/tmp/feed-to-epub-test", gone_since)
+            .expect("fetching a gone feed failed");
+        assert!(matches!(outcome, FetchOutcome::Gone(since) if since == gone_since));
+        server.next_request();
+
+        // There is no response left, this must not hit the server.
+        let outcome = reader
+            .fetch_feed(
+                "test",
+                "/tmp/feed-to-epub-test",
+                "2025-02-01T00:00:00Z".parse().unwrap(),
+            )
+            .expect("fetching a gone feed again failed");
+        assert!(matches!(outcome, FetchOutcome::Gone(since) if since == gone_since));
+        assert_eq!(reader.next_due("test").unwrap(), Timestamp::MAX);
+    }
+
+    #[test]
+    fn fetch_feed_backs_off_on_failures() {
+        let server = TestServer::start(vec![
+            TestResponse::new(404, ""),
+            TestResponse::new(200, "not a feed"),
+        ]);
+        let reader = test_reader(&feed_config(&server.url("/atom.xml")));
+        let mut now: Timestamp = "2025-01-01T00:00:00Z".parse().unwrap();
+
+        // The 404, then a document that isn't a feed once the backoff is over.
+        for _ in 0..2 {
+            assert!(reader
+                .fetch_feed("test", "/tmp/feed-to-epub-test", now)
+                .is_err());
+            server.next_request();
+            let next_due = reader.next_due("test").unwrap();
+            assert!(next_due > now);
+            now = next_due;
+        }
+
+        let file_url = Url::from_file_path("/does/not/exist.xml").unwrap();
+        let reader = test_reader(&feed_config(file_url.as_str()));
+        assert!(reader
+            .fetch_feed("test", "/tmp/feed-to-epub-test", now)
+            .is_err());
+        assert!(reader.next_due("test").unwrap() > now);
+    }
+
+    #[test]
+    fn fetch_feed_clears_backoff_of_local_feeds() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("atom.xml");
+        let reader = test_reader(&feed_config(Url::from_file_path(&path).unwrap().as_str()));
+        let now: Timestamp = "2025-01-01T00:00:00Z".parse().unwrap();
+
+        assert!(reader
+            .fetch_feed("test", "/tmp/feed-to-epub-test", now)
+            .is_err());
+        let feed_id = reader
+            .storage()
+            .feed_stats_from_db(&reader.config.feeds["test"].url)
+            .unwrap()
+            .unwrap()
+            .id;
+        assert!(reader.storage().backoff_from_db(feed_id).unwrap().is_some());
+
+        std::fs::write(&path, ATOM_FEED).unwrap();
+        let now = reader.next_due("test").unwrap();
+        assert!(matches!(
+            reader.fetch_feed("test", "/tmp/feed-to-epub-test", now),
+            Ok(FetchOutcome::Fetched(_))
+        ));
+        assert!(reader.storage().backoff_from_db(feed_id).unwrap().is_none());
+    }
+
+    #[test]
+    fn fetch_feed_sends_credentials_and_headers() {
+        let server = TestServer::start(vec![TestResponse::new(200, ATOM_FEED)]);
+        let reader = test_reader(&format!(
+            "
+[feeds.test]
+url = \"{}\"
+download_dir = \"/tmp/feed-to-epub-test\"
+auth = {{ basic = {{ username = \"me\", password = \"hunter2\" }} }}
+headers = {{ Cookie = \"session=abc\" }}
+user_agent = \"Mozilla/5.0\"
+",
+            server.url("/atom.xml")
+        ));
+
+        reader
+            .fetch_feed(
+                "test",
+                "/tmp/feed-to-epub-test",
+                "2025-01-01T00:00:00Z".parse().unwrap(),
+            )
+            .expect("authenticated fetch failed");
+
+        let request = server.next_request();
+        assert_eq!(
+            request.header("Authorization"),
+            Some("Basic bWU6aHVudGVyMg==")
+        );
+        assert_eq!(request.header("Cookie"), Some("session=abc"));
+        assert_eq!(request.header("User-Agent"), Some("Mozilla/5.0"));
+    }
+
+    #[test]
+    fn fetch_feed_reads_local_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.xml"), ATOM_FEED).unwrap();
+        fs::write(
+            dir.path().join("b.xml"),
+            ATOM_FEED
+                .replace("urn:test:1", "urn:test:2")
+                .replace("First", "Second"),
+        )
+        .unwrap();
+
+        let file_url = Url::from_file_path(dir.path().join("a.xml")).unwrap();
+        let reader = test_reader(&feed_config(file_url.as_str()));
+        match reader.fetch_feed(
+            "test",
+            "/tmp/feed-to-epub-test",
+            "2025-01-01T00:00:00Z".parse().unwrap(),
+        ) {
+            Ok(FetchOutcome::Fetched(entries)) => {
+                assert_eq!(entries.len(), 1);
+                assert_eq!(entries[0].title, "First");
+            }
+            other => panic!("expected a fetched feed, got {other:?}"),
+        }
+
+        let dir_url = Url::from_directory_path(dir.path()).unwrap();
+        let reader = test_reader(&feed_config(dir_url.as_str()));
+        match reader.fetch_feed(
+            "test",
+            "/tmp/feed-to-epub-test",
+            "2025-01-01T00:00:00Z".parse().unwrap(),
+        ) {
+            Ok(FetchOutcome::Fetched(entries)) => {
+                let titles: Vec<&str> = entries.iter().map(|e| e.title.as_str()).collect();
+                assert_eq!(titles, vec!["First", "Second"]);
             }
+            other => panic!("expected a fetched feed, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn fetch_from_reader_deduplicates() {
+        let reader = test_reader(&feed_config("https://example.com/atom.xml"));
+
+        match reader.fetch_from_reader("test", ATOM_FEED.as_bytes()) {
+            Ok(FetchOutcome::Fetched(entries)) => assert_eq!(entries.len(), 1),
+            other => panic!("expected a fetched feed, got {other:?}"),
+        }
+        match reader.fetch_from_reader("test", ATOM_FEED.as_bytes()) {
+            Ok(FetchOutcome::Fetched(entries)) => assert!(entries.is_empty()),
+            other => panic!("expected a fetched feed, got {other:?}"),
+        }
+
+        // Piping a feed in doesn't touch the schedule.
+        assert_eq!(reader.next_due("test").unwrap(), Timestamp::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn fetch_feed_replaces_summaries_with_full_articles() {
+        let article = "<html><body><nav><a href=\"/\">Home</a></nav><article>\
+            <p>The whole article, with every paragraph the feed left out, as promised.</p>\
+            <p>And another paragraph, so there is more to read than in the summary.</p>\
+            </article><footer><p>Copyright, all rights reserved, every single one of them.</p></footer></body></html>";
+        let site = TestServer::start(vec![
+            TestResponse::new(503, ""),
+            TestResponse::new(200, article),
+        ]);
+        let rss = format!(
+            r#"<?xml version="1.0"?>
+<rss version="2.0"><channel><title>Test</title>
+<item><title>First</title><guid>1</guid><link>{}</link>
+<pubDate>Wed, 01 Jan 2025 00:00:00 GMT</pubDate>
+<description>Just the first two sentences. Read more on the site.</description></item>
+</channel></rss>"#,
+            site.url("/posts/1")
+        );
+        let server = TestServer::start(vec![
+            TestResponse::new(200, rss.clone()),
+            TestResponse::new(200, rss.clone()),
+            TestResponse::new(200, rss),
+        ]);
+
+        let reader = test_reader(&format!(
+            "
+[feeds.test]
+url = \"{}\"
+download_dir = \"/tmp/feed-to-epub-test\"
+fetch_full_article = true
+",
+            server.url("/rss.xml")
+        ));
+        let now: Timestamp = "2025-01-01T00:00:00Z".parse().unwrap();
+
+        // The site is down, the entry makes do with the feed's content.
+        let entries = match reader.fetch_feed("test", "/tmp/feed-to-epub-test", now) {
+            Ok(FetchOutcome::Fetched(entries)) => entries,
+            other => panic!("expected the feed to be fetched, got {other:?}"),
         };
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].content,
+            "Just the first two sentences. Read more on the site."
+        );
+        assert_eq!(server.next_request().path, "/rss.xml");
+        assert_eq!(site.next_request().path, "/posts/1");
 
-        if let Some(feed) = feed_data {
-            feed.entries
-                .iter()
-                .filter_map(|e| {
-                    match crate::storage::entry_from_feed_entry(feed_stats.id, e) {
-                        Ok(entry) => Some(entry),
-                        Err(err) => {
-                            eprintln!("{err}");
-                            None
-                        } // TODO: we really shouldn't log the error here I think
-                    }
-                })
-                .for_each(|e| match self.storage.new_entry_to_db(&e) {
-                    Ok(_) => (),
-                    Err(err) => eprintln!("{err}"),
-                });
-
-            feed_stats.last_fetched = Some(jiff::Timestamp::now());
-            self.storage.feed_stats_to_db(&feed_stats)?;
-            Ok(Some(feed))
-        } else {
-            // TODO: this is a fucking mess
-            Err(FetchError::EntryConversionError(
-                crate::storage::EntryConversionError::SummaryExtractionError,
-            ))
+        // The next fetch tries the article again.
+        let entries = match reader.fetch_feed("test", "/tmp/feed-to-epub-test", now) {
+            Ok(FetchOutcome::Fetched(entries)) => entries,
+            other => panic!("expected the feed to be fetched, got {other:?}"),
+        };
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].content,
+            "<article><p>The whole article, with every paragraph the feed left out, as promised.</p>\
+            <p>And another paragraph, so there is more to read than in the summary.</p></article>"
+        );
+        assert_eq!(
+            entries[0].summary,
+            "Just the first two sentences. Read more on the site."
+        );
+        assert_eq!(server.next_request().path, "/rss.xml");
+        assert_eq!(site.next_request().path, "/posts/1");
+
+        // The article is only downloaded once.
+        match reader.fetch_feed("test", "/tmp/feed-to-epub-test", now) {
+            Ok(FetchOutcome::Fetched(entries)) => assert!(entries.is_empty()),
+            other => panic!("expected the feed to be fetched, got {other:?}"),
         }
+        assert_eq!(server.next_request().path, "/rss.xml");
     }
 }
@@ -0,0 +1,410 @@
+//! Import and export of feed subscriptions as OPML, the format every other
+//! feed reader speaks.
+
+use super::config::{Config, SourceType};
+use super::config_editor::{self, ConfigEditor};
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("could not parse OPML: {0}")]
+    XMLError(#[from] quick_xml::Error),
+    #[error("could not parse OPML attribute: {0}")]
+    AttributeError(#[from] quick_xml::events::attributes::AttrError),
+    #[error("not an OPML document")]
+    NotOPMLError,
+    #[error("could not add feed: {0}")]
+    ConfigError(#[from] config_editor::Error),
+}
+
+/// Outline is a feed subscription found in an OPML document.
+#[derive(Debug, PartialEq)]
+pub struct Outline {
+    pub title: String,
+    pub xml_url: String,
+    /// category is the innermost outline the feed was nested in, or the
+    /// first category attribute of the feed itself.
+    pub category: Option<String>,
+}
+
+/// parse returns every feed subscription in the OPML document, outlines
+/// without an `xmlUrl` are only used as categories.
+pub fn parse(opml: &str) -> Result<Vec<Outline>, Error> {
+    let mut reader = quick_xml::Reader::from_str(opml);
+    let mut outlines = Vec::new();
+    // One element for every open <outline>, the category it opened if any.
+    let mut open: Vec<Option<String>> = Vec::new();
+    let mut seen_opml = false;
+
+    loop {
+        let (tag, is_empty) = match reader.read_event()? {
+            Event::Eof => break,
+            Event::Start(tag) => (tag, false),
+            Event::Empty(tag) => (tag, true),
+            Event::End(tag) => {
+                if tag.local_name().as_ref() == b"outline" {
+                    open.pop();
+                }
+                continue;
+            }
+            _ => continue,
+        };
+
+        match tag.local_name().as_ref() {
+            b"opml" => seen_opml = true,
+            b"outline" => {
+                let mut attrs: BTreeMap<String, String> = BTreeMap::new();
+                for attr in tag.attributes() {
+                    let attr = attr?;
+                    let value = attr.decode_and_unescape_value(reader.decoder())?;
+                    attrs.insert(
+                        String::from_utf8_lossy(attr.key.local_name().as_ref()).into(),
+                        value.trim().to_string(),
+                    );
+                }
+                let title = attrs
+                    .get("title")
+                    .or(attrs.get("text"))
+                    .filter(|title| !title.is_empty())
+                    .cloned();
+
+                let opened = match attrs.get("xmlUrl").filter(|url| !url.is_empty()) {
+                    Some(xml_url) => {
+                        let category = open.iter().rev().flatten().next().cloned().or_else(|| {
+                            attrs
+                                .get("category")
+                                .and_then(|category| category_name(category))
+                        });
+                        outlines.push(Outline {
+                            title: title.unwrap_or_else(|| xml_url.clone()),
+                            xml_url: xml_url.clone(),
+                            category,
+                        });
+                        None
+                    }
+                    None => title,
+                };
+                if !is_empty {
+                    open.push(opened);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if !seen_opml {
+        return Err(Error::NotOPMLError);
+    }
+    Ok(outlines)
+}
+
+/// category_name picks the first category out of a category attribute like
+/// `/Tech/Linux,/News`, using the last part of its path.
+fn category_name(category: &str) -> Option<String> {
+    category
+        .split(',')
+        .next()?
+        .split('/')
+        .rfind(|part| !part.trim().is_empty())
+        .map(|part| part.trim().to_string())
+}
+
+/// feed_name turns an outline title into a name that is easy to type on the
+/// command line, made of ASCII letters, digits and dashes. Accented Latin
+/// letters lose their accents, any other character separates words.
+pub fn feed_name(title: &str) -> String {
+    let mut name = String::with_capacity(title.len());
+    for c in title.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            name.push(c);
+        } else if let Some(letters) = unaccented(c) {
+            name.push_str(letters);
+        } else if !name.is_empty() && !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+    let name = name.trim_end_matches('-');
+    if name.is_empty() {
+        "feed".into()
+    } else {
+        name.into()
+    }
+}
+
+/// unaccented spells a lowercase accented Latin letter in ASCII.
+fn unaccented(c: char) -> Option<&'static str> {
+    let letters = match c {
+        'à'..='å' | 'ā' | 'ă' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'č' => "c",
+        'ð' | 'ď' | 'đ' => "d",
+        'è'..='ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+        'ğ' => "g",
+        'ì'..='ï' | 'ī' | 'į' | 'ı' => "i",
+        'ł' | 'ľ' | 'ĺ' => "l",
+        'ñ' | 'ń' | 'ň' => "n",
+        'ò'..='ö' | 'ø' | 'ō' | 'ő' => "o",
+        'œ' => "oe",
+        'ř' | 'ŕ' => "r",
+        'ß' => "ss",
+        'ś' | 'š' | 'ş' => "s",
+        'ť' | 'ţ' => "t",
+        'þ' => "th",
+        'ù'..='ü' | 'ū' | 'ů' | 'ű' | 'ų' => "u",
+        'ý' | 'ÿ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    };
+    Some(letters)
+}
+
+/// import adds a feed to the config for every outline whose URL is not
+/// configured yet. `download_dir` may contain `{name}`, which is replaced by
+/// the name of the feed. It returns the names of the added feeds.
+pub fn import(
+    editor: &mut ConfigEditor,
+    outlines: &[Outline],
+    download_dir: &str,
+) -> Result<Vec<String>, Error> {
+    let mut added = Vec::new();
+    for outline in outlines {
+        if editor.has_feed_url(&outline.xml_url) {
+            continue;
+        }
+
+        let base_name = feed_name(&outline.title);
+        let mut name = base_name.clone();
+        let mut suffix = 2;
+        while editor.has_feed(&name) {
+            name = format!("{base_name}-{suffix}");
+            suffix += 1;
+        }
+
+        editor.add_feed(
+            &name,
+            &outline.xml_url,
+            &download_dir.replace("{name}", &name),
+            outline.category.as_deref(),
+        )?;
+        added.push(name);
+    }
+    Ok(added)
+}
+
+/// export writes the configured feeds as an OPML 2.0 document, feeds in a
+/// group are nested in an outline named after it. Maildirs and scraped
+/// websites mean nothing to other readers and are left out.
+pub fn export(config: &Config) -> String {
+    let mut groups: BTreeMap<Option<&str>, Vec<(&str, &str)>> = BTreeMap::new();
+    for (name, feed) in &config.feeds {
+        if feed.source != SourceType::Feed {
+            continue;
+        }
+        groups
+            .entry(feed.group.as_deref())
+            .or_default()
+            .push((name, &feed.url));
+    }
+
+    let mut writer = quick_xml::Writer::new_with_indent(Vec::new(), b' ', 2);
+    let write = |writer: &mut quick_xml::Writer<Vec<u8>>| -> std::io::Result<()> {
+        writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+        writer.write_event(Event::Start(
+            BytesStart::new("opml").with_attributes([("version", "2.0")]),
+        ))?;
+        writer.write_event(Event::Start(BytesStart::new("head")))?;
+        writer
+            .create_element("title")
+            .write_text_content(BytesText::new("feed-to-epub subscriptions"))?;
+        writer.write_event(Event::End(BytesEnd::new("head")))?;
+        writer.write_event(Event::Start(BytesStart::new("body")))?;
+
+        for (group, mut feeds) in groups {
+            feeds.sort();
+            if let Some(group) = group {
+                writer.write_event(Event::Start(
+                    BytesStart::new("outline").with_attributes([("text", group), ("title", group)]),
+                ))?;
+            }
+            for (name, url) in feeds {
+                writer.write_event(Event::Empty(BytesStart::new("outline").with_attributes([
+                    ("type", "rss"),
+                    ("text", name),
+                    ("title", name),
+                    ("xmlUrl", url),
+                ])))?;
+            }
+            if group.is_some() {
+                writer.write_event(Event::End(BytesEnd::new("outline")))?;
+            }
+        }
+
+        writer.write_event(Event::End(BytesEnd::new("body")))?;
+        writer.write_event(Event::End(BytesEnd::new("opml")))?;
+        Ok(())
+    };
+    write(&mut writer).expect("writing to a Vec cannot fail");
+
+    let mut opml = String::from_utf8(writer.into_inner()).expect("we only wrote UTF-8");
+    opml.push('\n');
+    opml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const SUBSCRIPTIONS: &str = include_str!("testdata/subscriptions.opml");
+
+    /// feeds returns name -> (url, group) of every configured feed.
+    fn feeds(config: &Config) -> HashMap<String, (String, Option<String>)> {
+        config
+            .feeds
+            .iter()
+            .map(|(name, feed)| (name.clone(), (feed.url.clone(), feed.group.clone())))
+            .collect()
+    }
+
+    #[test]
+    fn parse_subscriptions() {
+        let outlines = parse(SUBSCRIPTIONS).expect("failed to parse OPML");
+        assert_eq!(
+            outlines,
+            vec![
+                Outline {
+                    title: "LWN.net".into(),
+                    xml_url: "https://lwn.net/headlines/rss".into(),
+                    category: Some("Tech".into()),
+                },
+                Outline {
+                    title: "Julia Evans".into(),
+                    xml_url: "https://jvns.ca/atom.xml".into(),
+                    category: Some("Blogs".into()),
+                },
+                Outline {
+                    title: "Ars Technica".into(),
+                    xml_url: "https://feeds.arstechnica.com/arstechnica/index".into(),
+                    category: Some("Tech".into()),
+                },
+                Outline {
+                    title: "Tom & Jerry's Café".into(),
+                    xml_url: "https://example.com/feed?a=1&b=2".into(),
+                    category: None,
+                },
+                Outline {
+                    title: "Weather".into(),
+                    xml_url: "https://weather.example/rss".into(),
+                    category: Some("Local".into()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_other_documents() {
+        assert!(matches!(
+            parse("<rss><channel></channel></rss>"),
+            Err(Error::NotOPMLError)
+        ));
+    }
+
+    #[test]
+    fn feed_names() {
+        assert_eq!(feed_name("LWN.net"), "lwn-net");
+        assert_eq!(feed_name("  Tom & Jerry's Café!"), "tom-jerry-s-cafe");
+        assert_eq!(feed_name("Süddeutsche Zeitung"), "suddeutsche-zeitung");
+        assert_eq!(feed_name("東京 News"), "news");
+        assert_eq!(feed_name("???"), "feed");
+    }
+
+    #[test]
+    fn import_then_export_round_trip() {
+        let mut editor: ConfigEditor = "".parse().unwrap();
+        let added = import(
+            &mut editor,
+            &parse(SUBSCRIPTIONS).unwrap(),
+            "~/books/{name}",
+        )
+        .expect("failed to import");
+        assert_eq!(
+            added,
+            vec![
+                "lwn-net",
+                "julia-evans",
+                "ars-technica",
+                "tom-jerry-s-cafe",
+                "weather"
+            ]
+        );
+
+        let config = editor.config().expect("imported config is invalid");
+        assert!(editor
+            .to_string()
+            .contains("download_dir = \"~/books/lwn-net\""));
+        assert_eq!(
+            config.feeds["tom-jerry-s-cafe"].url,
+            "https://example.com/feed?a=1&b=2"
+        );
+
+        // Importing the export into a fresh config gives the same feeds.
+        let mut reimported: ConfigEditor = "".parse().unwrap();
+        import(
+            &mut reimported,
+            &parse(&export(&config)).unwrap(),
+            "~/books/{name}",
+        )
+        .unwrap();
+        assert_eq!(feeds(&reimported.config().unwrap()), feeds(&config));
+
+        // Importing it again on top of itself adds nothing.
+        let mut editor = editor;
+        assert!(
+            import(&mut editor, &parse(&export(&config)).unwrap(), "/tmp")
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn export_then_import_round_trip() {
+        let config = Config::from_reader(
+            "
+[feeds.lwn]
+url = \"https://lwn.net/headlines/rss\"
+download_dir = \"/tmp/lwn\"
+group = \"Tech\"
+
+[feeds.blog]
+url = \"https://blog.example/atom.xml?tag=a&b\"
+download_dir = \"/tmp/blog\"
+"
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let opml = export(&config);
+        assert_eq!(
+            opml,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="2.0">
+  <head>
+    <title>feed-to-epub subscriptions</title>
+  </head>
+  <body>
+    <outline type="rss" text="blog" title="blog" xmlUrl="https://blog.example/atom.xml?tag=a&amp;b"/>
+    <outline text="Tech" title="Tech">
+      <outline type="rss" text="lwn" title="lwn" xmlUrl="https://lwn.net/headlines/rss"/>
+    </outline>
+  </body>
+</opml>
+"#
+        );
+
+        let mut editor: ConfigEditor = "".parse().unwrap();
+        import(&mut editor, &parse(&opml).unwrap(), "/tmp/{name}").unwrap();
+        assert_eq!(feeds(&editor.config().unwrap()), feeds(&config));
+    }
+}
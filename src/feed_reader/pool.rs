@@ -0,0 +1,265 @@
+//! Fetches several feeds at once so that a few slow hosts don't hold up
+//! everything else, without hitting any single host with many requests.
+
+use super::{FeedReader, FetchError, FetchOutcome};
+use jiff::Timestamp;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{mpsc, Condvar, Mutex, PoisonError};
+use std::thread;
+use url::Url;
+
+/// Queue holds the feeds that still have to be fetched and how many fetches
+/// are running per host.
+struct Queue<'a> {
+    feeds: VecDeque<(&'a str, String)>,
+    running: HashMap<String, usize>,
+}
+
+struct Pool<'a> {
+    queue: Mutex<Queue<'a>>,
+    host_done: Condvar,
+    per_host: usize,
+}
+
+impl<'a> Pool<'a> {
+    fn new(feeds: VecDeque<(&'a str, String)>, per_host: usize) -> Self {
+        Pool {
+            queue: Mutex::new(Queue {
+                feeds,
+                running: HashMap::new(),
+            }),
+            host_done: Condvar::new(),
+            per_host,
+        }
+    }
+
+    /// next hands out the first feed whose host has a fetch to spare,
+    /// waiting for one to finish if there is none. It returns None once every
+    /// feed has been handed out.
+    fn next(&self) -> Option<(&'a str, String)> {
+        let mut queue = self.queue.lock().expect("a fetch worker panicked");
+        loop {
+            if queue.feeds.is_empty() {
+                return None;
+            }
+
+            let position = queue.feeds.iter().position(|(_, host)| {
+                queue.running.get(host).copied().unwrap_or_default() < self.per_host
+            });
+            if let Some(position) = position {
+                let (feed_name, host) = queue
+                    .feeds
+                    .remove(position)
+                    .expect("position is within the queue");
+                *queue.running.entry(host.clone()).or_default() += 1;
+                return Some((feed_name, host));
+            }
+
+            queue = self.host_done.wait(queue).expect("a fetch worker panicked");
+        }
+    }
+
+    /// done frees up the fetch a worker took for `host`.
+    fn done(&self, host: &str) {
+        // This runs while a panicking worker unwinds, panicking again here
+        // would abort.
+        let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(running) = queue.running.get_mut(host) {
+            *running = running.saturating_sub(1);
+        }
+        self.host_done.notify_all();
+    }
+}
+
+/// Slot is a fetch a worker took from the pool for a host, it is given back
+/// when dropped so that a fetch that panics doesn't block its host forever.
+struct Slot<'p, 'a> {
+    pool: &'p Pool<'a>,
+    host: String,
+}
+
+impl Drop for Slot<'_, '_> {
+    fn drop(&mut self) {
+        self.pool.done(&self.host);
+    }
+}
+
+/// fetch_concurrently fetches the given feeds on up to `fetch_workers`
+/// threads, at most `fetch_workers_per_host` of them from the same host at a
+/// time. `handle` runs on the calling thread for every feed as soon as it is
+/// fetched, so it doesn't need to be thread safe.
+pub fn fetch_concurrently<'a>(
+    reader: &FeedReader,
+    feed_names: &[&'a str],
+    now: Timestamp,
+    mut handle: impl FnMut(&'a str, Result<FetchOutcome, FetchError>),
+) {
+    let feeds = feed_names
+        .iter()
+        .map(|&feed_name| (feed_name, host(&request_url(reader, feed_name))))
+        .collect();
+    let pool = Pool::new(feeds, reader.config.fetch_workers_per_host);
+    let workers = reader.config.fetch_workers.min(feed_names.len());
+
+    thread::scope(|scope| {
+        let (results, fetched) = mpsc::channel();
+        for _ in 0..workers {
+            let results = results.clone();
+            let pool = &pool;
+            scope.spawn(move || {
+                while let Some((feed_name, host)) = pool.next() {
+                    let slot = Slot { pool, host };
+                    let download_dir = &reader.config.feeds[feed_name].download_dir;
+                    let result = reader.fetch_feed(feed_name, download_dir, now);
+                    drop(slot);
+                    if results.send((feed_name, result)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        // Only the workers hold on to a sender now, the loop ends once the
+        // last of them is done.
+        drop(results);
+
+        for (feed_name, result) in fetched {
+            handle(feed_name, result);
+        }
+    });
+}
+
+/// request_url is the URL a feed is fetched from, where it moved to if it
+/// moved for good.
+fn request_url(reader: &FeedReader, feed_name: &str) -> String {
+    let url = &reader.config.feeds[feed_name].url;
+    reader
+        .storage()
+        .feed_stats_from_db(url)
+        .ok()
+        .flatten()
+        .and_then(|feed_stats| feed_stats.moved_to)
+        .unwrap_or_else(|| url.clone())
+}
+
+/// host is what the per host limit goes by, URLs without one are limited on
+/// their own.
+fn host(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(String::from))
+        .unwrap_or_else(|| url.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed_reader::test_reader;
+    use crate::test_server::{TestResponse, TestServer};
+
+    #[test]
+    fn pool_limits_fetches_per_host() {
+        let pool = Pool::new(
+            VecDeque::from([
+                ("a", "one.example.com".into()),
+                ("b", "one.example.com".into()),
+                ("c", "two.example.com".into()),
+            ]),
+            1,
+        );
+
+        assert_eq!(pool.next(), Some(("a", "one.example.com".into())));
+        // b has to wait for a, c's host is free.
+        assert_eq!(pool.next(), Some(("c", "two.example.com".into())));
+        pool.done("one.example.com");
+        assert_eq!(pool.next(), Some(("b", "one.example.com".into())));
+        assert_eq!(pool.next(), None);
+    }
+
+    #[test]
+    fn pool_frees_slots_of_panicking_fetches() {
+        let pool = Pool::new(
+            VecDeque::from([
+                ("a", "one.example.com".into()),
+                ("b", "one.example.com".into()),
+            ]),
+            1,
+        );
+
+        let fetch = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let (_, host) = pool.next().unwrap();
+                    let _slot = Slot { pool: &pool, host };
+                    panic!("the fetch of a panicked");
+                })
+                .join()
+        });
+        assert!(fetch.is_err());
+        // This would wait forever if a still held on to the host.
+        assert_eq!(pool.next(), Some(("b", "one.example.com".into())));
+    }
+
+    #[test]
+    fn request_url_follows_moves() {
+        let reader = test_reader(
+            "
+[feeds.moved]
+url = \"https://old.example.com/atom.xml\"
+download_dir = \"/tmp/feed-to-epub-test\"
+",
+        );
+        assert_eq!(
+            request_url(&reader, "moved"),
+            "https://old.example.com/atom.xml"
+        );
+
+        let mut feed_stats = reader
+            .storage()
+            .new_feed_stats_to_db("https://old.example.com/atom.xml")
+            .unwrap();
+        feed_stats.moved_to = Some("https://new.example.com/atom.xml".into());
+        reader.storage().feed_stats_to_db(&feed_stats).unwrap();
+        assert_eq!(host(&request_url(&reader, "moved")), "new.example.com");
+    }
+
+    #[test]
+    fn fetch_concurrently_fetches_every_feed() {
+        let rss = r#"<?xml version="1.0"?>
+<rss version="2.0"><channel><title>Test</title>
+<item><title>First</title><guid>1</guid><pubDate>Wed, 01 Jan 2025 00:00:00 GMT</pubDate>
+<description>Hello</description></item></channel></rss>"#;
+        let server = TestServer::start(vec![
+            TestResponse::new(200, rss),
+            TestResponse::new(200, rss),
+        ]);
+        let reader = test_reader(&format!(
+            "
+fetch_workers = 2
+
+[feeds.first]
+url = \"{}\"
+download_dir = \"/tmp/feed-to-epub-test\"
+
+[feeds.second]
+url = \"{}\"
+download_dir = \"/tmp/feed-to-epub-test\"
+",
+            server.url("/first.xml"),
+            server.url("/second.xml"),
+        ));
+
+        let mut fetched = Vec::new();
+        fetch_concurrently(
+            &reader,
+            &["first", "second"],
+            "2025-01-01T00:00:00Z".parse().unwrap(),
+            |feed_name, result| match result {
+                Ok(FetchOutcome::Fetched(entries)) => fetched.push((feed_name, entries.len())),
+                other => panic!("expected {feed_name} to be fetched, got {other:?}"),
+            },
+        );
+
+        fetched.sort();
+        assert_eq!(fetched, vec![("first", 1), ("second", 1)]);
+    }
+}
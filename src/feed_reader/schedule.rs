@@ -0,0 +1,176 @@
+use super::config::DigestSchedule;
+use crate::storage::Backoff;
+use jiff::civil::Date;
+use jiff::tz::TimeZone;
+use jiff::{SignedDuration, Span, Timestamp};
+
+/// next_due works out when a feed should be polled next. Feeds we have never
+/// fetched are due right away, everything else is due one poll interval after
+/// the last fetch unless a backoff keeps it blocked for longer.
+pub fn next_due(
+    last_fetched: Option<Timestamp>,
+    poll_interval_secs: u64,
+    backoff: Option<&Backoff>,
+) -> Timestamp {
+    let due = match last_fetched {
+        Some(last_fetched) => {
+            let interval =
+                SignedDuration::from_secs(i64::try_from(poll_interval_secs).unwrap_or(i64::MAX));
+            last_fetched
+                .saturating_add(interval)
+                .expect("signed durations only carry time units")
+        }
+        None => Timestamp::UNIX_EPOCH,
+    };
+
+    match backoff {
+        Some(backoff) => due.max(backoff.blocked_until),
+        None => due,
+    }
+}
+
+/// sleep_duration returns how long to sleep from `now` until `next_due`,
+/// feeds that are already overdue result in no sleep at all.
+pub fn sleep_duration(now: Timestamp, next_due: Timestamp) -> std::time::Duration {
+    let until_due = next_due.duration_since(now);
+    if until_due.is_negative() {
+        return std::time::Duration::ZERO;
+    }
+    until_due.unsigned_abs()
+}
+
+/// digest_due tells whether a new digest should be built today. Digests go
+/// by calendar days rather than exact intervals so that a digest built a bit
+/// later than usual doesn't push every following one back as well.
+pub fn digest_due(last_digest: Option<Date>, today: Date, schedule: DigestSchedule) -> bool {
+    let Some(last_digest) = last_digest else {
+        return true;
+    };
+
+    match last_digest.checked_add(schedule_span(schedule)) {
+        Ok(due) => today >= due,
+        Err(_) => false,
+    }
+}
+
+/// edition_due returns when the next newspaper edition should be built, at
+/// the given local hour one schedule period after the last edition. The
+/// first edition is due right away.
+pub fn edition_due(
+    last_edition: Option<Timestamp>,
+    schedule: DigestSchedule,
+    hour: i8,
+    tz: &TimeZone,
+) -> Timestamp {
+    let Some(last_edition) = last_edition else {
+        return Timestamp::UNIX_EPOCH;
+    };
+
+    last_edition
+        .to_zoned(tz.clone())
+        .date()
+        .checked_add(schedule_span(schedule))
+        .and_then(|due| due.at(hour, 0, 0, 0).to_zoned(tz.clone()))
+        .map(|due| due.timestamp())
+        .unwrap_or(Timestamp::MAX)
+}
+
+fn schedule_span(schedule: DigestSchedule) -> Span {
+    match schedule {
+        DigestSchedule::Daily => Span::new().days(1),
+        DigestSchedule::Weekly => Span::new().days(7),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_due_never_fetched() {
+        assert_eq!(next_due(None, 3600, None), Timestamp::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn next_due_uses_poll_interval() {
+        let last_fetched: Timestamp = "2025-01-01T00:00:00Z".parse().unwrap();
+        assert_eq!(
+            next_due(Some(last_fetched), 7200, None),
+            "2025-01-01T02:00:00Z".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn next_due_respects_backoff() {
+        let last_fetched: Timestamp = "2025-01-01T00:00:00Z".parse().unwrap();
+        let backoff = Backoff {
+            feed_id: 1,
+            blocked_until: "2025-01-02T00:00:00Z".parse().unwrap(),
+            failure_count: 1,
+            last_error: None,
+        };
+        assert_eq!(
+            next_due(Some(last_fetched), 7200, Some(&backoff)),
+            backoff.blocked_until
+        );
+    }
+
+    #[test]
+    fn sleep_duration_overdue() {
+        let now: Timestamp = "2025-01-01T02:00:00Z".parse().unwrap();
+        let due: Timestamp = "2025-01-01T00:00:00Z".parse().unwrap();
+        assert_eq!(sleep_duration(now, due), std::time::Duration::ZERO);
+        assert_eq!(
+            sleep_duration(due, now),
+            std::time::Duration::from_secs(7200)
+        );
+    }
+
+    #[test]
+    fn digest_due_by_calendar_day() {
+        let today = jiff::civil::date(2025, 1, 8);
+        assert!(digest_due(None, today, DigestSchedule::Weekly));
+
+        assert!(!digest_due(Some(today), today, DigestSchedule::Daily));
+        assert!(digest_due(
+            Some(jiff::civil::date(2025, 1, 7)),
+            today,
+            DigestSchedule::Daily
+        ));
+
+        assert!(!digest_due(
+            Some(jiff::civil::date(2025, 1, 2)),
+            today,
+            DigestSchedule::Weekly
+        ));
+        assert!(digest_due(
+            Some(jiff::civil::date(2025, 1, 1)),
+            today,
+            DigestSchedule::Weekly
+        ));
+    }
+
+    #[test]
+    fn edition_due_at_hour() {
+        assert_eq!(
+            edition_due(None, DigestSchedule::Daily, 6, &TimeZone::UTC),
+            Timestamp::UNIX_EPOCH
+        );
+
+        // Built late in the day, the next one is still due in the morning.
+        let last_edition: Timestamp = "2025-01-01T21:30:00Z".parse().unwrap();
+        assert_eq!(
+            edition_due(Some(last_edition), DigestSchedule::Daily, 6, &TimeZone::UTC),
+            "2025-01-02T06:00:00Z".parse().unwrap()
+        );
+        assert_eq!(
+            edition_due(
+                Some(last_edition),
+                DigestSchedule::Weekly,
+                6,
+                &TimeZone::UTC
+            ),
+            "2025-01-08T06:00:00Z".parse().unwrap()
+        );
+    }
+}
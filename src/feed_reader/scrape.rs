@@ -0,0 +1,431 @@
+//! Turns websites without a feed into one. The listing page at the feed's
+//! url links to the articles, every article we haven't seen yet is
+//! downloaded and picked apart with the selectors of the feed's scrape
+//! section.
+
+use super::config::{Feed, ScrapeConfig};
+use super::source::{self, Document, Source};
+use super::{FeedReader, FetchError, FetchOutcome};
+use crate::html::{self, Element, Node};
+use crate::storage::{Entry, EntryChange, FeedStats};
+use jiff::Timestamp;
+use std::io::Read;
+use thiserror::Error;
+use url::Url;
+
+/// Pages bigger than this are not worth looking through.
+const MAX_PAGE_BYTES: u64 = 5 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum ScrapeError {
+    #[error("invalid URL {url}: {source}")]
+    InvalidURLError {
+        url: String,
+        source: url::ParseError,
+    },
+    #[error("failed to execute HTTP request: {0}")]
+    HTTPError(#[from] Box<ureq::Error>),
+    #[error("failed to read page: {0}")]
+    ReadError(#[from] std::io::Error),
+    #[error("nothing matches the content selector {0:?}")]
+    NoContentError(String),
+}
+
+/// ScrapeSource scrapes the website the feed's url points at.
+pub struct ScrapeSource;
+
+impl Source for ScrapeSource {
+    fn fetch(
+        &self,
+        reader: &FeedReader,
+        feed_name: &str,
+        mut feed_stats: FeedStats,
+        now: Timestamp,
+    ) -> Result<FetchOutcome, FetchError> {
+        let feed = &reader.config.feeds[feed_name];
+        let scrape = feed
+            .scrape
+            .as_ref()
+            .expect("the config makes sure scraped feeds have selectors");
+        let listing_url = match &feed_stats.moved_to {
+            Some(moved_to) => moved_to.clone(),
+            None => feed.url.clone(),
+        };
+
+        let response = match source::request_document(
+            reader,
+            feed_name,
+            &listing_url,
+            &mut feed_stats,
+            now,
+        )? {
+            Document::Changed(response) => response,
+            Document::Done(outcome) => return Ok(outcome),
+        };
+        let (listing_url, listing) = match read_listing(*response) {
+            Ok(listing) => listing,
+            Err(err) => {
+                reader.record_failure(feed_stats.id, now, None, err.to_string())?;
+                return Err(err.into());
+            }
+        };
+
+        let headers = source::request_headers(feed)?;
+        let mut entries = Vec::new();
+        // Listings put the newest article first, storing the oldest first
+        // keeps the entries in the order they were published.
+        for link in article_links(&listing, &listing_url, scrape).iter().rev() {
+            if reader
+                .storage()
+                .entry_exists_in_db(feed_stats.id, link.as_str())?
+            {
+                continue;
+            }
+
+            // Credentials are for the site, not for whoever it links to.
+            let headers = match link.host_str() == listing_url.host_str() {
+                true => headers.as_slice(),
+                false => &[],
+            };
+            let entry = match download_page(reader, feed, link, headers) {
+                Ok(page) => article_to_entry(feed_stats.id, link, &page, scrape, now),
+                Err(err) => Err(err),
+            };
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    // The next fetch tries again.
+                    eprintln!("failed to scrape {link}: {err}");
+                    continue;
+                }
+            };
+
+            match reader.storage().entry_to_db(&entry)? {
+                EntryChange::Unchanged => (),
+                _ => entries.push(entry),
+            }
+        }
+
+        feed_stats.last_fetched = Some(now);
+        reader.storage().feed_stats_to_db(&feed_stats)?;
+        reader.storage().clear_backoff(feed_stats.id)?;
+        Ok(FetchOutcome::Fetched(entries))
+    }
+}
+
+/// read_listing reads the listing page together with the URL it ended up
+/// at, which its links are relative to.
+fn read_listing(response: ureq::Response) -> Result<(Url, String), ScrapeError> {
+    let listing_url =
+        Url::parse(response.get_url()).map_err(|source| ScrapeError::InvalidURLError {
+            url: response.get_url().into(),
+            source,
+        })?;
+    Ok((listing_url, read_page(response)?))
+}
+
+fn read_page(response: ureq::Response) -> Result<String, std::io::Error> {
+    let mut page = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_PAGE_BYTES)
+        .read_to_end(&mut page)?;
+    Ok(String::from_utf8_lossy(&page).into_owned())
+}
+
+/// article_links returns the absolute URLs of the articles the listing links
+/// to, in the order they appear and without duplicates.
+fn article_links(listing: &str, listing_url: &Url, scrape: &ScrapeConfig) -> Vec<Url> {
+    let nodes = html::parse_fragment(listing);
+
+    let mut links: Vec<Url> = Vec::new();
+    for element in scrape.links.select(&nodes) {
+        let Some(mut url) = element
+            .attr("href")
+            .and_then(|href| listing_url.join(href.trim()).ok())
+        else {
+            continue;
+        };
+        // Jumping to the comments is still the same article.
+        url.set_fragment(None);
+        if !links.contains(&url) {
+            links.push(url);
+        }
+    }
+    links
+}
+
+/// download_page downloads a page with the feed's user agent and the given
+/// headers.
+pub fn download_page(
+    reader: &FeedReader,
+    feed: &Feed,
+    url: &Url,
+    headers: &[(String, String)],
+) -> Result<String, ScrapeError> {
+    let mut request = reader.agent.get(url.as_str());
+    if let Some(user_agent) = &feed.user_agent {
+        request = request.set("User-Agent", user_agent);
+    }
+    for (name, value) in headers {
+        request = request.set(name, value);
+    }
+
+    let response = request.call().map_err(Box::new)?;
+    Ok(read_page(response)?)
+}
+
+/// article_to_entry picks the title, date and content out of an article
+/// page, the URL of the article identifies the entry.
+fn article_to_entry(
+    feed_id: u64,
+    url: &Url,
+    page: &str,
+    scrape: &ScrapeConfig,
+    now: Timestamp,
+) -> Result<Entry, ScrapeError> {
+    let nodes = html::parse_fragment(page);
+
+    let content: Vec<Node> = scrape
+        .content
+        .select(&nodes)
+        .into_iter()
+        .map(|element| Node::Element(element.clone()))
+        .collect();
+    if content.is_empty() {
+        return Err(ScrapeError::NoContentError(scrape.content.to_string()));
+    }
+
+    let title = scrape
+        .title
+        .as_ref()
+        .and_then(|title| title.select_first(&nodes))
+        .or_else(|| find_element(&nodes, "title"))
+        .map(|element| collapse_whitespace(&element.text_content()))
+        .filter(|title| !title.is_empty())
+        .unwrap_or_else(|| url.to_string());
+
+    let published = scrape
+        .date
+        .as_ref()
+        .and_then(|date| date.select_first(&nodes))
+        .and_then(parse_date)
+        .unwrap_or(now);
+
+    Ok(Entry {
+        feed_id,
+        feed_entry_id: Some(url.to_string()),
+        title,
+        updated: None,
+        published: Some(published.to_string()),
+        authors: Vec::new(),
+        summary: "".into(),
+        content: html::to_xhtml(&content),
+        link: Some(url.to_string()),
+        language: find_element(&nodes, "html")
+            .and_then(|html| html.attr("lang"))
+            .map(str::trim)
+            .filter(|lang| !lang.is_empty())
+            .map(String::from),
+        categories: Vec::new(),
+    })
+}
+
+fn find_element<'a>(nodes: &'a [Node], name: &str) -> Option<&'a Element> {
+    let mut found = None;
+    html::walk_elements(nodes, &mut |element| {
+        if found.is_none() && element.name == name {
+            found = Some(element);
+        }
+    });
+    found
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// parse_date reads the date of an article from a `<time datetime>`, a
+/// `<meta content>` or the text of the element.
+fn parse_date(element: &Element) -> Option<Timestamp> {
+    element
+        .attr("datetime")
+        .and_then(parse_date_text)
+        .or_else(|| element.attr("content").and_then(parse_date_text))
+        .or_else(|| parse_date_text(&element.text_content()))
+}
+
+/// parse_date_text understands RFC 3339 and RFC 2822 dates, dates without an
+/// offset are taken to be UTC and dates without a time midnight.
+fn parse_date_text(date: &str) -> Option<Timestamp> {
+    let date = date.trim();
+    if let Ok(timestamp) = date.parse::<Timestamp>() {
+        return Some(timestamp);
+    }
+    if let Ok(zoned) = jiff::fmt::rfc2822::parse(date) {
+        return Some(zoned.timestamp());
+    }
+
+    let datetime = date
+        .parse::<jiff::civil::DateTime>()
+        .or_else(|_| {
+            date.parse::<jiff::civil::Date>()
+                .map(|date| date.at(0, 0, 0, 0))
+        })
+        .ok()?;
+    datetime
+        .to_zoned(jiff::tz::TimeZone::UTC)
+        .ok()
+        .map(|zoned| zoned.timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed_reader::config::Config;
+    use crate::feed_reader::test_reader;
+    use crate::test_server::{TestResponse, TestServer};
+
+    const LISTING: &str = include_str!("testdata/scrape/listing.html");
+    const FIRST_ARTICLE: &str = include_str!("testdata/scrape/first.html");
+    const SECOND_ARTICLE: &str = include_str!("testdata/scrape/second.html");
+
+    fn scrape_config() -> ScrapeConfig {
+        let config = Config::from_reader(
+            "
+[feeds.site]
+type = \"scrape\"
+url = \"https://example.com/blog/\"
+download_dir = \"/tmp/feed-to-epub-test\"
+
+[feeds.site.scrape]
+links = \".post-list article h2 > a\"
+title = \"article header h1\"
+date = \"article header time\"
+content = \"article .entry-content\"
+"
+            .as_bytes(),
+        )
+        .expect("failed to parse configuration");
+        config.feeds["site"].scrape.clone().unwrap()
+    }
+
+    #[test]
+    fn article_links_from_listing() {
+        let listing_url = Url::parse("https://example.com/blog/").unwrap();
+        let links = article_links(LISTING, &listing_url, &scrape_config());
+        assert_eq!(
+            links.iter().map(Url::as_str).collect::<Vec<_>>(),
+            [
+                "https://example.com/blog/2025/second-post",
+                "https://example.com/blog/2025/first-post",
+            ]
+        );
+    }
+
+    #[test]
+    fn article_to_entry_picks_parts() {
+        let url = Url::parse("https://example.com/blog/2025/first-post").unwrap();
+        let now: Timestamp = "2025-03-01T00:00:00Z".parse().unwrap();
+        let entry = article_to_entry(1, &url, FIRST_ARTICLE, &scrape_config(), now)
+            .expect("failed to scrape article");
+
+        assert_eq!(entry.title, "The first post");
+        assert_eq!(entry.published.as_deref(), Some("2025-01-02T09:30:00Z"));
+        assert_eq!(entry.link.as_deref(), Some(url.as_str()));
+        assert!(entry.content.starts_with(r#"<div class="entry-content">"#));
+        assert!(entry.content.contains("<p>Hello from the first post.</p>"));
+        assert!(!entry.content.contains("Share this"));
+
+        // A date without a time, and no title element.
+        let mut scrape = scrape_config();
+        scrape.title = None;
+        let entry = article_to_entry(1, &url, SECOND_ARTICLE, &scrape, now)
+            .expect("failed to scrape article");
+        assert_eq!(entry.title, "Second post | Example Blog");
+        assert_eq!(entry.published.as_deref(), Some("2025-02-14T00:00:00Z"));
+
+        assert!(matches!(
+            article_to_entry(1, &url, LISTING, &scrape, now),
+            Err(ScrapeError::NoContentError(_))
+        ));
+    }
+
+    #[test]
+    fn fetch_scraped_feed() {
+        let server = TestServer::start(vec![
+            TestResponse::new(200, LISTING),
+            TestResponse::new(200, FIRST_ARTICLE),
+            TestResponse::new(200, SECOND_ARTICLE),
+            TestResponse::new(200, LISTING),
+        ]);
+        let reader = test_reader(&format!(
+            "
+[feeds.site]
+type = \"scrape\"
+url = \"{}\"
+download_dir = \"/tmp/feed-to-epub-test\"
+headers = {{ X-Token = \"secret\" }}
+
+[feeds.site.scrape]
+links = \".post-list article h2 > a\"
+title = \"article header h1\"
+content = \"article .entry-content\"
+",
+            server.url("/blog/")
+        ));
+        let now: Timestamp = "2025-03-01T00:00:00Z".parse().unwrap();
+
+        let entries = match reader.fetch_feed("site", "/tmp/feed-to-epub-test", now) {
+            Ok(FetchOutcome::Fetched(entries)) => entries,
+            other => panic!("expected the site to be scraped, got {other:?}"),
+        };
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.title.as_str())
+                .collect::<Vec<_>>(),
+            ["The first post", "The second post"]
+        );
+        assert_eq!(server.next_request().path, "/blog/");
+        let article = server.next_request();
+        assert_eq!(article.path, "/blog/2025/first-post");
+        assert_eq!(article.header("X-Token"), Some("secret"));
+        assert_eq!(server.next_request().path, "/blog/2025/second-post");
+
+        // Known articles are not downloaded again.
+        match reader.fetch_feed("site", "/tmp/feed-to-epub-test", now) {
+            Ok(FetchOutcome::Fetched(entries)) => assert!(entries.is_empty()),
+            other => panic!("expected the site to be scraped, got {other:?}"),
+        }
+        assert_eq!(server.next_request().path, "/blog/");
+    }
+
+    #[test]
+    fn fetch_unreadable_listing_backs_off() {
+        // The body is not chunked, so reading it fails.
+        let server = TestServer::start(vec![
+            TestResponse::new(200, LISTING).header("Transfer-Encoding", "chunked")
+        ]);
+        let reader = test_reader(&format!(
+            "
+[feeds.site]
+type = \"scrape\"
+url = \"{}\"
+download_dir = \"/tmp/feed-to-epub-test\"
+
+[feeds.site.scrape]
+links = \"a\"
+content = \"article\"
+",
+            server.url("/blog/")
+        ));
+        let now: Timestamp = "2025-03-01T00:00:00Z".parse().unwrap();
+
+        assert!(reader
+            .fetch_feed("site", "/tmp/feed-to-epub-test", now)
+            .is_err());
+        server.next_request();
+        assert!(reader.next_due("site").unwrap() > now);
+    }
+}
@@ -0,0 +1,316 @@
+//! Where the entries of a feed come from. Most feeds are RSS, Atom or JSON
+//! Feed documents, but anything that can be turned into entries can be a
+//! source.
+
+use super::config::{Auth, Feed, SecretError};
+use super::{backoff, FeedReader, FetchError, FetchOutcome};
+use crate::storage::FeedStats;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use jiff::Timestamp;
+use std::fs;
+use ureq::Agent;
+use url::Url;
+
+/// MAX_REDIRECTS is how many redirects we follow for a single fetch, the same
+/// limit ureq uses for everything else.
+const MAX_REDIRECTS: u8 = 5;
+
+/// Source is something the FeedReader can fetch entries from.
+pub trait Source {
+    /// fetch stores whatever is new at the source and returns the entries
+    /// that are new or changed. It is only called for feeds that are neither
+    /// gone nor backed off, keeping `feed_stats` up to date is up to it.
+    fn fetch(
+        &self,
+        reader: &FeedReader,
+        feed_name: &str,
+        feed_stats: FeedStats,
+        now: Timestamp,
+    ) -> Result<FetchOutcome, FetchError>;
+}
+
+/// FeedSource fetches RSS, Atom and JSON Feed documents over HTTP or from
+/// `file://` URLs.
+pub struct FeedSource;
+
+impl Source for FeedSource {
+    fn fetch(
+        &self,
+        reader: &FeedReader,
+        feed_name: &str,
+        mut feed_stats: FeedStats,
+        now: Timestamp,
+    ) -> Result<FetchOutcome, FetchError> {
+        let url = match &feed_stats.moved_to {
+            Some(moved_to) => moved_to.clone(),
+            None => reader.config.feeds[feed_name].url.clone(),
+        };
+
+        if url.starts_with("file://") {
+            let feeds = match read_local_feeds(&url) {
+                Ok(feeds) => feeds,
+                Err(err) => {
+                    reader.record_failure(feed_stats.id, now, None, err.to_string())?;
+                    return Err(err);
+                }
+            };
+            let mut entries = Vec::new();
+            for feed in feeds {
+                entries.extend(reader.store_entries(feed_name, feed_stats.id, &feed));
+                feed_stats.logo = feed_logo(&url, &feed).or(feed_stats.logo);
+            }
+            feed_stats.last_fetched = Some(now);
+            reader.storage().feed_stats_to_db(&feed_stats)?;
+            reader.storage().clear_backoff(feed_stats.id)?;
+            return Ok(FetchOutcome::Fetched(entries));
+        }
+
+        let response = match request_document(reader, feed_name, &url, &mut feed_stats, now)? {
+            Document::Changed(response) => response,
+            Document::Done(outcome) => return Ok(outcome),
+        };
+        let feed = match feed_rs::parser::parse(response.into_reader()) {
+            Ok(feed) => feed,
+            Err(err) => {
+                reader.record_failure(feed_stats.id, now, None, err.to_string())?;
+                return Err(err.into());
+            }
+        };
+        let entries = reader.store_entries(feed_name, feed_stats.id, &feed);
+
+        feed_stats.logo = feed_logo(&url, &feed).or(feed_stats.logo);
+        feed_stats.last_fetched = Some(now);
+        reader.storage().feed_stats_to_db(&feed_stats)?;
+        reader.storage().clear_backoff(feed_stats.id)?;
+        Ok(FetchOutcome::Fetched(entries))
+    }
+}
+
+/// feed_logo is the absolute URL of the feed's logo, or of its icon for
+/// feeds without one.
+fn feed_logo(url: &str, feed: &feed_rs::model::Feed) -> Option<String> {
+    let logo = feed.logo.as_ref().or(feed.icon.as_ref())?;
+    let logo = Url::parse(url).ok()?.join(logo.uri.trim()).ok()?;
+    Some(logo.into())
+}
+
+/// Document is what came of requesting the document behind a feed.
+pub enum Document {
+    /// Changed is a fresh copy of the document, reading it and saving
+    /// `feed_stats` is up to the caller.
+    Changed(Box<ureq::Response>),
+    /// Done means there is nothing to read, `feed_stats` and the backoff
+    /// are already taken care of.
+    Done(FetchOutcome),
+}
+
+/// request_document requests `url` with the feed's credentials and the
+/// validators we have stored. Gone feeds, rate limits, failures and moves are
+/// recorded in `feed_stats` and the database the way every source that
+/// fetches over HTTP wants them.
+pub fn request_document(
+    reader: &FeedReader,
+    feed_name: &str,
+    url: &str,
+    feed_stats: &mut FeedStats,
+    now: Timestamp,
+) -> Result<Document, FetchError> {
+    let headers = request_headers(&reader.config.feeds[feed_name])?;
+    let (response, moved_to) = match call_following_redirects(
+        &reader.feed_agent,
+        url,
+        feed_stats,
+        &reader.config.feeds[feed_name],
+        &headers,
+    )
+    .map_err(|err| *err)
+    {
+        Ok(response) => response,
+        Err(ureq::Error::Status(410, _)) => {
+            eprintln!("{feed_name} is gone, it won't be polled anymore");
+            feed_stats.gone_since = Some(now);
+            feed_stats.last_fetched = Some(now);
+            reader.storage().feed_stats_to_db(feed_stats)?;
+            reader.storage().clear_backoff(feed_stats.id)?;
+            return Ok(Document::Done(FetchOutcome::Gone(now)));
+        }
+        Err(ureq::Error::Status(status @ (429 | 503), response)) => {
+            let retry_after = response
+                .header("Retry-After")
+                .and_then(|value| backoff::parse_retry_after(value, now));
+            let backoff =
+                reader.record_failure(feed_stats.id, now, retry_after, format!("HTTP {status}"))?;
+            eprintln!(
+                "{feed_name} got a {status}, backing off until {}",
+                backoff.blocked_until
+            );
+            return Ok(Document::Done(FetchOutcome::BackedOff(
+                backoff.blocked_until,
+            )));
+        }
+        // Everything else, like a 404, is unlikely to fix itself right away
+        // either, backing off keeps us from polling it in a loop.
+        Err(err) => {
+            reader.record_failure(feed_stats.id, now, None, err.to_string())?;
+            return Err(Box::new(err).into());
+        }
+    };
+
+    if let Some(moved_to) = moved_to {
+        if feed_stats.moved_to.as_ref() != Some(&moved_to) {
+            println!(
+                "{feed_name} moved permanently to {moved_to}, consider updating its url in the config"
+            );
+        }
+        feed_stats.moved_to = (moved_to != feed_stats.url).then_some(moved_to);
+    }
+
+    match response.status() {
+        304 => {
+            feed_stats.last_fetched = Some(now);
+            reader.storage().feed_stats_to_db(feed_stats)?;
+            reader.storage().clear_backoff(feed_stats.id)?;
+            Ok(Document::Done(FetchOutcome::NotModified))
+        }
+        status @ 300..=399 => {
+            let err = FetchError::RedirectError {
+                url: response.get_url().into(),
+                status,
+            };
+            reader.record_failure(feed_stats.id, now, None, err.to_string())?;
+            Err(err)
+        }
+        _ => {
+            if let Some(last_modified_since) = response.header("Last-Modified") {
+                feed_stats.last_modified = Some(last_modified_since.into());
+            }
+
+            if let Some(etag) = response.header("ETag") {
+                feed_stats.etag = Some(etag.into());
+            }
+
+            Ok(Document::Changed(Box::new(response)))
+        }
+    }
+}
+
+/// call_following_redirects requests the feed and follows redirects by
+/// hand, so that we learn whether the feed moved for good. It returns the
+/// final response together with the URL the feed moved to if every
+/// redirect on the way was permanent. A response that still is a redirect
+/// means we gave up following them. The feed's `headers` only go to the
+/// host of the feed, credentials must not leak to wherever it redirects.
+fn call_following_redirects(
+    agent: &Agent,
+    url: &str,
+    feed_stats: &FeedStats,
+    feed: &Feed,
+    headers: &[(String, String)],
+) -> Result<(ureq::Response, Option<String>), Box<ureq::Error>> {
+    let host = |url: &str| Url::parse(url).ok()?.host_str().map(String::from);
+    let feed_host = host(url);
+    let mut url = url.to_string();
+    let mut redirects = 0;
+    let mut permanent = true;
+
+    loop {
+        let mut request = agent.get(&url);
+
+        if let Some(user_agent) = &feed.user_agent {
+            request = request.set("User-Agent", user_agent);
+        }
+
+        if host(&url) == feed_host {
+            for (name, value) in headers {
+                request = request.set(name, value);
+            }
+        }
+
+        // Servers are free to support only one of the validators, sending
+        // both lets them pick whichever they understand.
+        if let Some(etag) = &feed_stats.etag {
+            request = request.set("If-None-Match", etag);
+        }
+
+        if let Some(last_modified) = &feed_stats.last_modified {
+            request = request.set("If-Modified-Since", last_modified);
+        }
+
+        let response = request.call().map_err(Box::new)?;
+        let location = match response.status() {
+            301 | 302 | 303 | 307 | 308 if redirects < MAX_REDIRECTS => response
+                .header("Location")
+                .and_then(|location| Url::parse(response.get_url()).ok()?.join(location).ok()),
+            _ => None,
+        };
+
+        let Some(location) = location else {
+            let moved_to = (redirects > 0 && permanent).then_some(url);
+            return Ok((response, moved_to));
+        };
+        permanent &= matches!(response.status(), 301 | 308);
+        redirects += 1;
+        url = location.into();
+    }
+}
+
+/// read_local_feeds parses the feed a `file://` URL points at, a directory
+/// stands for every file in it.
+fn read_local_feeds(url: &str) -> Result<Vec<feed_rs::model::Feed>, FetchError> {
+    let path = Url::parse(url)
+        .ok()
+        .and_then(|url| url.to_file_path().ok())
+        .ok_or_else(|| FetchError::FileURLError(url.into()))?;
+    let to_error = |path: &std::path::Path| {
+        let path = path.display().to_string();
+        move |source| FetchError::LocalFeedError { path, source }
+    };
+
+    let mut paths = Vec::new();
+    if path.is_dir() {
+        for dir_entry in fs::read_dir(&path).map_err(to_error(&path))? {
+            let file = dir_entry.map_err(to_error(&path))?.path();
+            if file.is_file() {
+                paths.push(file);
+            }
+        }
+        // Oldest first if the files are named by date, like most tools do.
+        paths.sort();
+    } else {
+        paths.push(path);
+    }
+
+    paths
+        .iter()
+        .map(|path| {
+            let file = fs::File::open(path).map_err(to_error(path))?;
+            Ok(feed_rs::parser::parse(std::io::BufReader::new(file))?)
+        })
+        .collect()
+}
+
+/// request_headers resolves the headers a feed wants sent along, including
+/// its credentials.
+pub fn request_headers(feed: &Feed) -> Result<Vec<(String, String)>, SecretError> {
+    let mut headers = feed
+        .headers
+        .iter()
+        .map(|(name, value)| Ok((name.clone(), value.resolve()?)))
+        .collect::<Result<Vec<_>, SecretError>>()?;
+
+    match &feed.auth {
+        Some(Auth::Basic { username, password }) => {
+            let credentials = BASE64_STANDARD.encode(format!("{username}:{}", password.resolve()?));
+            headers.push(("Authorization".into(), format!("Basic {credentials}")));
+        }
+        Some(Auth::Bearer(token)) => {
+            headers.push((
+                "Authorization".into(),
+                format!("Bearer {}", token.resolve()?),
+            ));
+        }
+        None => (),
+    }
+
+    Ok(headers)
+}
@@ -0,0 +1,741 @@
+//! A small and forgiving HTML parser. Feeds are full of HTML that no XML
+//! parser would accept, so this follows the parts of the HTML5 parsing rules
+//! that matter for article content: void elements, implicitly closed
+//! paragraphs and list items, raw text elements and entity references.
+//! It does not try to be a complete HTML5 tree builder.
+
+/// Node is a single node of a parsed HTML fragment. Comments, doctypes and
+/// processing instructions are dropped while parsing.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Element {
+    pub name: String,
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+impl Element {
+    pub fn new(name: &str) -> Self {
+        Element {
+            name: name.into(),
+            attrs: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn set_attr(&mut self, name: &str, value: &str) {
+        match self.attrs.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value.into(),
+            None => self.attrs.push((name.into(), value.into())),
+        }
+    }
+
+    pub fn remove_attr(&mut self, name: &str) {
+        self.attrs.retain(|(n, _)| n != name);
+    }
+
+    /// text_content concatenates all text below this element.
+    pub fn text_content(&self) -> String {
+        text_content(&self.children)
+    }
+}
+
+/// text_content concatenates all text of a fragment.
+pub fn text_content(nodes: &[Node]) -> String {
+    let mut text = String::new();
+    collect_text(nodes, &mut text);
+    text
+}
+
+fn collect_text(nodes: &[Node], text: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(t) => text.push_str(t),
+            Node::Element(e) => collect_text(&e.children, text),
+        }
+    }
+}
+
+/// walk_elements calls `f` for every element in the tree, parents before
+/// their children.
+pub fn walk_elements<'a>(nodes: &'a [Node], f: &mut impl FnMut(&'a Element)) {
+    for node in nodes {
+        if let Node::Element(element) = node {
+            f(element);
+            walk_elements(&element.children, f);
+        }
+    }
+}
+
+/// walk_elements_mut is the mutable counterpart of `walk_elements`.
+pub fn walk_elements_mut(nodes: &mut [Node], f: &mut impl FnMut(&mut Element)) {
+    for node in nodes {
+        if let Node::Element(element) = node {
+            f(element);
+            walk_elements_mut(&mut element.children, f);
+        }
+    }
+}
+
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements whose content is not markup, everything up to the matching end
+/// tag is text.
+const RAW_TEXT_ELEMENTS: &[&str] = &[
+    "script", "style", "iframe", "textarea", "title", "noembed", "noframes", "xmp",
+];
+
+/// Elements that implicitly close an open `<p>`.
+const CLOSES_P: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "details",
+    "div",
+    "dl",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "ul",
+];
+
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+/// Elements that stop the search for an element to implicitly close.
+const SCOPE_BOUNDARIES: &[&str] = &[
+    "button", "caption", "html", "object", "table", "td", "template", "th",
+];
+
+pub fn is_void_element(name: &str) -> bool {
+    VOID_ELEMENTS.contains(&name)
+}
+
+/// parse_fragment parses HTML into a list of nodes, it never fails and
+/// closes whatever was left open at the end.
+pub fn parse_fragment(html: &str) -> Vec<Node> {
+    let mut builder = TreeBuilder::default();
+    for token in Tokenizer::new(html) {
+        builder.process(token);
+    }
+    builder.finish()
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Text(String),
+    StartTag {
+        name: String,
+        attrs: Vec<(String, String)>,
+    },
+    EndTag {
+        name: String,
+    },
+}
+
+struct Tokenizer<'a> {
+    input: &'a str,
+    pos: usize,
+    raw_text_end: Option<String>,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(input: &'a str) -> Self {
+        Tokenizer {
+            input,
+            pos: 0,
+            raw_text_end: None,
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn starts_with_ignore_case(&self, prefix: &str) -> bool {
+        self.rest()
+            .get(..prefix.len())
+            .is_some_and(|s| s.eq_ignore_ascii_case(prefix))
+    }
+
+    /// skip_past moves behind the next occurrence of `needle` or to the end
+    /// of the input if there is none.
+    fn skip_past(&mut self, needle: &str) -> &'a str {
+        let rest = self.rest();
+        match rest.find(needle) {
+            Some(i) => {
+                self.pos += i + needle.len();
+                &rest[..i]
+            }
+            None => {
+                self.pos = self.input.len();
+                rest
+            }
+        }
+    }
+
+    fn raw_text(&mut self, name: &str) -> Token {
+        let rest = self.rest();
+        let lower = rest.to_ascii_lowercase();
+        let end_tag = format!("</{name}");
+        let end = lower.find(&end_tag).unwrap_or(rest.len());
+        self.pos += end;
+        let text = &rest[..end];
+        if name == "textarea" || name == "title" {
+            Token::Text(decode_entities(text))
+        } else {
+            Token::Text(text.into())
+        }
+    }
+
+    fn tag_name(&mut self) -> String {
+        let rest = self.rest();
+        let end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>')
+            .unwrap_or(rest.len());
+        self.pos += end;
+        rest[..end].to_ascii_lowercase()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn attributes(&mut self) -> Vec<(String, String)> {
+        let mut attrs: Vec<(String, String)> = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.is_empty() {
+                return attrs;
+            }
+            if let Some(after) = rest.strip_prefix('>') {
+                self.pos = self.input.len() - after.len();
+                return attrs;
+            }
+            if rest.starts_with('/') {
+                self.pos += 1;
+                continue;
+            }
+
+            // The first character is always part of the name, even if it's
+            // a `=`, which is what browsers do too.
+            let first_len = rest.chars().next().map_or(1, char::len_utf8);
+            let end = rest[first_len..]
+                .find(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>' || c == '=')
+                .map_or(rest.len(), |i| i + first_len);
+            let name = rest[..end].to_ascii_lowercase();
+            self.pos += end;
+
+            self.skip_whitespace();
+            let value = if self.rest().starts_with('=') {
+                self.pos += 1;
+                self.skip_whitespace();
+                self.attribute_value()
+            } else {
+                String::new()
+            };
+
+            if !attrs.iter().any(|(n, _)| n == &name) {
+                attrs.push((name, value));
+            }
+        }
+    }
+
+    fn attribute_value(&mut self) -> String {
+        let rest = self.rest();
+        match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                self.pos += 1;
+                let raw = self.skip_past(&quote.to_string());
+                decode_entities(raw)
+            }
+            _ => {
+                let end = rest
+                    .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                    .unwrap_or(rest.len());
+                self.pos += end;
+                decode_entities(&rest[..end])
+            }
+        }
+    }
+}
+
+impl Iterator for Tokenizer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        loop {
+            if let Some(name) = self.raw_text_end.take() {
+                let token = self.raw_text(&name);
+                if token != Token::Text(String::new()) {
+                    return Some(token);
+                }
+            }
+
+            let rest = self.rest();
+            if rest.is_empty() {
+                return None;
+            }
+
+            if !rest.starts_with('<') {
+                let end = rest.find('<').unwrap_or(rest.len());
+                self.pos += end;
+                return Some(Token::Text(decode_entities(&rest[..end])));
+            }
+
+            if rest.starts_with("<!--") {
+                self.pos += 4;
+                self.skip_past("-->");
+                continue;
+            }
+
+            if self.starts_with_ignore_case("<![CDATA[") {
+                self.pos += 9;
+                let text = self.skip_past("]]>");
+                return Some(Token::Text(text.into()));
+            }
+
+            if rest.starts_with("<!") || rest.starts_with("<?") {
+                self.skip_past(">");
+                continue;
+            }
+
+            let mut chars = rest.chars().skip(1);
+            match (chars.next(), chars.next()) {
+                (Some('/'), Some(c)) if c.is_ascii_alphabetic() => {
+                    self.pos += 2;
+                    let name = self.tag_name();
+                    self.skip_past(">");
+                    return Some(Token::EndTag { name });
+                }
+                (Some('/'), _) => {
+                    // `</>` and `</ foo>` are dropped like comments.
+                    self.skip_past(">");
+                    continue;
+                }
+                (Some(c), _) if c.is_ascii_alphabetic() => {
+                    self.pos += 1;
+                    let name = self.tag_name();
+                    let attrs = self.attributes();
+                    if RAW_TEXT_ELEMENTS.contains(&name.as_str()) {
+                        self.raw_text_end = Some(name.clone());
+                    }
+                    return Some(Token::StartTag { name, attrs });
+                }
+                _ => {
+                    // A `<` that does not start a tag is just text.
+                    self.pos += 1;
+                    return Some(Token::Text("<".into()));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct TreeBuilder {
+    root: Vec<Node>,
+    open: Vec<Element>,
+}
+
+impl TreeBuilder {
+    fn append(&mut self, node: Node) {
+        let children = match self.open.last_mut() {
+            Some(parent) => &mut parent.children,
+            None => &mut self.root,
+        };
+
+        // Merge adjacent text so entity and `<` splits don't fragment it.
+        if let (Node::Text(text), Some(Node::Text(last))) = (&node, children.last_mut()) {
+            last.push_str(text);
+            return;
+        }
+        children.push(node);
+    }
+
+    fn close_top(&mut self) {
+        if let Some(element) = self.open.pop() {
+            self.append(Node::Element(element));
+        }
+    }
+
+    /// position_in_scope finds the innermost open element named one of
+    /// `names` without crossing one of the `boundaries`.
+    fn position_in_scope(&self, names: &[&str], boundaries: &[&str]) -> Option<usize> {
+        for (i, element) in self.open.iter().enumerate().rev() {
+            if names.contains(&element.name.as_str()) {
+                return Some(i);
+            }
+            if boundaries.contains(&element.name.as_str()) {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn close_to(&mut self, position: usize) {
+        while self.open.len() > position {
+            self.close_top();
+        }
+    }
+
+    fn close_in_scope(&mut self, names: &[&str], boundaries: &[&str]) {
+        if let Some(position) = self.position_in_scope(names, boundaries) {
+            self.close_to(position);
+        }
+    }
+
+    fn process(&mut self, token: Token) {
+        match token {
+            Token::Text(text) => self.append(Node::Text(text)),
+            Token::StartTag { name, attrs } => self.start_tag(name, attrs),
+            Token::EndTag { name } => self.end_tag(name),
+        }
+    }
+
+    fn start_tag(&mut self, name: String, attrs: Vec<(String, String)>) {
+        let tag = name.as_str();
+
+        if CLOSES_P.contains(&tag) {
+            self.close_in_scope(&["p"], SCOPE_BOUNDARIES);
+        }
+
+        match tag {
+            "li" => self.close_in_scope(&["li"], &[SCOPE_BOUNDARIES, &["ul", "ol"]].concat()),
+            "dd" | "dt" => {
+                self.close_in_scope(&["dd", "dt"], &[SCOPE_BOUNDARIES, &["dl"]].concat())
+            }
+            "tr" => self.close_in_scope(&["tr"], &["table"]),
+            "td" | "th" => self.close_in_scope(&["td", "th"], &["tr", "table"]),
+            "thead" | "tbody" | "tfoot" => {
+                self.close_in_scope(&["thead", "tbody", "tfoot"], &["table"])
+            }
+            "option" => self.close_in_scope(&["option"], SCOPE_BOUNDARIES),
+            "a" => self.close_in_scope(&["a"], SCOPE_BOUNDARIES),
+            _ if HEADINGS.contains(&tag)
+                && self
+                    .open
+                    .last()
+                    .is_some_and(|e| HEADINGS.contains(&e.name.as_str())) =>
+            {
+                self.close_top();
+            }
+            _ => (),
+        }
+
+        let element = Element {
+            name,
+            attrs,
+            children: Vec::new(),
+        };
+        if is_void_element(&element.name) {
+            self.append(Node::Element(element));
+        } else {
+            self.open.push(element);
+        }
+    }
+
+    fn end_tag(&mut self, name: String) {
+        if name == "br" {
+            // Browsers turn a stray `</br>` into a line break.
+            self.append(Node::Element(Element::new("br")));
+            return;
+        }
+
+        if let Some(position) = self.open.iter().rposition(|e| e.name == name) {
+            self.close_to(position);
+        }
+    }
+
+    fn finish(mut self) -> Vec<Node> {
+        self.close_to(0);
+        self.root
+    }
+}
+
+/// Windows-1252 code points that HTML maps numeric references in the C1
+/// control range to, `&#146;` is an apostrophe in the wild.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{8D}', '\u{017D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{9D}', '\u{017E}', '\u{0178}',
+];
+
+/// Entities that are commonly written without the trailing semicolon.
+const LEGACY_ENTITIES: &[(&str, char)] = &[
+    ("amp", '&'),
+    ("lt", '<'),
+    ("gt", '>'),
+    ("quot", '"'),
+    ("nbsp", '\u{A0}'),
+    ("copy", '\u{A9}'),
+    ("reg", '\u{AE}'),
+];
+
+/// decode_entities resolves named and numeric character references, a `&`
+/// that does not start a known reference is kept as is.
+pub fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        match decode_reference(&rest[1..]) {
+            Some((value, consumed)) => {
+                decoded.push_str(&value);
+                rest = &rest[1 + consumed..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// decode_reference decodes the reference following a `&` and returns the
+/// decoded text and the number of bytes it used up.
+fn decode_reference(after_amp: &str) -> Option<(String, usize)> {
+    if let Some(numeric) = after_amp.strip_prefix('#') {
+        let (digits, radix, prefix_len) = match numeric.strip_prefix(['x', 'X']) {
+            Some(hex) => (hex, 16, 2),
+            None => (numeric, 10, 1),
+        };
+        let len = digits
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(digits.len());
+        if len == 0 {
+            return None;
+        }
+
+        let code_point = u32::from_str_radix(&digits[..len], radix).unwrap_or(u32::MAX);
+        let c = match code_point {
+            0x80..=0x9F => WINDOWS_1252[(code_point - 0x80) as usize],
+            _ => char::from_u32(code_point)
+                .filter(|c| *c != '\0')
+                .unwrap_or('\u{FFFD}'),
+        };
+        let semicolon = usize::from(digits[len..].starts_with(';'));
+        return Some((c.to_string(), prefix_len + len + semicolon));
+    }
+
+    let len = after_amp
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(after_amp.len());
+    let name = &after_amp[..len];
+    if len == 0 {
+        return None;
+    }
+
+    if after_amp[len..].starts_with(';') {
+        if let Ok(i) = html_escape::NAMED_ENTITIES
+            .binary_search_by(|(entity, _)| (*entity).cmp(name.as_bytes()))
+        {
+            return Some((html_escape::NAMED_ENTITIES[i].1.into(), len + 1));
+        }
+    }
+
+    LEGACY_ENTITIES
+        .iter()
+        .find(|(entity, _)| *entity == name)
+        .map(|(_, c)| (c.to_string(), len))
+}
+
+/// to_xhtml serializes nodes as well-formed XHTML. Everything outside of
+/// ASCII is written as a numeric character reference so the result does not
+/// depend on any entity definitions, characters XML does not allow at all
+/// are dropped.
+pub fn to_xhtml(nodes: &[Node]) -> String {
+    let mut xhtml = String::new();
+    write_nodes(nodes, &mut xhtml);
+    xhtml
+}
+
+/// escape makes text safe to put into XHTML, as text or as attribute value.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    escape_into(text, true, &mut escaped);
+    escaped
+}
+
+/// text_to_paragraphs turns plain text into XHTML, blank lines separate
+/// paragraphs and single line breaks are kept as `<br />`.
+pub fn text_to_paragraphs(text: &str) -> String {
+    let text = text.replace("\r\n", "\n");
+    let paragraphs: Vec<Node> = text
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|paragraph| !paragraph.is_empty())
+        .map(|paragraph| {
+            let mut p = Element::new("p");
+            for (i, line) in paragraph.lines().enumerate() {
+                if i > 0 {
+                    p.children.push(Node::Element(Element::new("br")));
+                }
+                p.children.push(Node::Text(line.trim().into()));
+            }
+            Node::Element(p)
+        })
+        .collect();
+
+    paragraphs
+        .iter()
+        .map(|paragraph| to_xhtml(std::slice::from_ref(paragraph)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn write_nodes(nodes: &[Node], out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => escape_into(text, false, out),
+            Node::Element(element) => write_element(element, out),
+        }
+    }
+}
+
+fn write_element(element: &Element, out: &mut String) {
+    if !is_xml_name(&element.name) {
+        write_nodes(&element.children, out);
+        return;
+    }
+
+    out.push('<');
+    out.push_str(&element.name);
+    for (name, value) in &element.attrs {
+        if !is_xml_name(name) {
+            continue;
+        }
+        out.push(' ');
+        out.push_str(name);
+        out.push_str("=\"");
+        escape_into(value, true, out);
+        out.push('"');
+    }
+
+    if is_void_element(&element.name) {
+        out.push_str(" />");
+        return;
+    }
+
+    out.push('>');
+    write_nodes(&element.children, out);
+    out.push_str("</");
+    out.push_str(&element.name);
+    out.push('>');
+}
+
+fn escape_into(text: &str, attribute: bool, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' if attribute => out.push_str("&quot;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if (c as u32) < 0x20 || is_xml_forbidden(c) => (),
+            c if c.is_ascii() => out.push(c),
+            c => out.push_str(&format!("&#{};", c as u32)),
+        }
+    }
+}
+
+fn is_xml_forbidden(c: char) -> bool {
+    matches!(c, '\u{7F}'..='\u{9F}' | '\u{FFFE}' | '\u{FFFF}')
+}
+
+/// is_xml_name accepts the subset of XML names that make sense for HTML
+/// elements and attributes, notably without namespace prefixes which we
+/// would have to declare. `xml:lang` is the one exception.
+fn is_xml_name(name: &str) -> bool {
+    if name == "xml:lang" {
+        return true;
+    }
+
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_entities_named_and_numeric() {
+        assert_eq!(
+            decode_entities("a&nbsp;b &mdash; &#8217;&#x2019;&#146; AT&T &bogus; &amp"),
+            "a\u{A0}b \u{2014} \u{2019}\u{2019}\u{2019} AT&T &bogus; &"
+        );
+    }
+
+    #[test]
+    fn parse_fragment_attributes() {
+        let nodes = parse_fragment("<img src=foo.png alt='A &amp; B' width=1 width=2 hidden>");
+        assert_eq!(
+            nodes,
+            vec![Node::Element(Element {
+                name: "img".into(),
+                attrs: vec![
+                    ("src".into(), "foo.png".into()),
+                    ("alt".into(), "A & B".into()),
+                    ("width".into(), "1".into()),
+                    ("hidden".into(), "".into()),
+                ],
+                children: vec![],
+            })]
+        );
+    }
+
+    #[test]
+    fn parse_fragment_closes_paragraphs() {
+        let nodes = parse_fragment("<p>one<p>two<ul><li>a<li>b</ul>");
+        assert_eq!(
+            to_xhtml(&nodes),
+            "<p>one</p><p>two</p><ul><li>a</li><li>b</li></ul>"
+        );
+    }
+}
@@ -1,15 +1,20 @@
 #![allow(clippy::pedantic)]
 
 use crate::feed_reader::config::Config;
-use crate::feed_reader::FeedReader;
-use crate::transformer::entry_to_epub;
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
 use expanduser::expanduser;
-use std::{fs::File, thread, time::Duration};
+use std::fs::File;
+use std::path::{Path, PathBuf};
 
+pub mod commands;
 pub mod feed_reader;
+pub mod html;
+pub mod readability;
+pub mod selector;
 pub mod storage;
+#[cfg(test)]
+mod test_server;
 pub mod transformer;
 
 #[derive(Parser, Debug)]
@@ -17,40 +22,132 @@ pub mod transformer;
 struct Args {
     #[arg(short, long, default_value = "~/.config/rss-to-epub/config.toml")]
     config: String,
+    /// Defaults to run.
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Keep polling every feed on its schedule and write EPUBs for new entries.
+    Run,
+    /// Poll only the given feeds, or all of them if none are given.
+    Fetch {
+        /// Fetch the feeds right away, regardless of their schedule, and exit.
+        #[arg(long)]
+        once: bool,
+        feeds: Vec<String>,
+        /// Read the feed document from stdin instead of fetching it.
+        #[arg(long, value_name = "NAME", conflicts_with_all = ["once", "feeds"])]
+        from_stdin: Option<String>,
+    },
+    /// Add a feed to the config file, the URL may also point at a website
+    /// whose feed is then looked up.
+    Add {
+        name: String,
+        url: String,
+        /// Where the EPUBs of this feed are written to.
+        #[arg(long)]
+        dir: String,
+        /// Which feed to add if the website has several, counting from 1.
+        #[arg(long)]
+        pick: Option<usize>,
+        /// Store the URL as it is instead of looking up the feed.
+        #[arg(long)]
+        no_discover: bool,
+    },
+    /// List the feeds a website offers.
+    Discover { url: String },
+    /// Remove a feed from the config file, its entries stay in the database.
+    Remove { name: String },
+    /// List the configured feeds.
+    List,
+    /// Add the feeds of an OPML file to the config file, feeds that are
+    /// already configured are skipped.
+    ImportOpml {
+        file: PathBuf,
+        /// Where the EPUBs of the imported feeds are written to, {name} is
+        /// replaced by the name of each feed.
+        #[arg(long, default_value = "~/books/{name}")]
+        download_dir: String,
+    },
+    /// Write the configured feeds as OPML, to stdout unless --output is given.
+    ExportOpml {
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Show when each feed was last fetched, its backoff and stored entries.
+    Status,
+    /// Poll a feed again that stopped being polled after it answered with
+    /// 410 Gone.
+    Revive { name: String },
+    /// Rebuild EPUBs from the entries stored in the database, without
+    /// fetching anything from the network.
+    Regenerate {
+        /// Only rebuild entries of this feed.
+        #[arg(long)]
+        feed: Option<String>,
+        /// Only rebuild entries published, or digests and editions built, on
+        /// or after this date (YYYY-MM-DD).
+        #[arg(long)]
+        since: Option<jiff::civil::Date>,
+        /// Only rebuild entries published, or digests and editions built, on
+        /// or before this date (YYYY-MM-DD).
+        #[arg(long)]
+        until: Option<jiff::civil::Date>,
+    },
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let config_file = File::open(expanduser(&args.config)?)?;
-    let config = Config::from_reader(config_file).expect("failed to read config file");
-    let feed_reader_v2 = FeedReader::new(config).expect("failed to set up feed reader");
+    let config_path = expanduser(&args.config)?;
 
-    loop {
-        for (feed_name, feed) in feed_reader_v2.config.feeds.iter() {
-            let feed_data = match feed_reader_v2.fetch_feed(
-                feed_name,
-                &feed.download_dir,
-                jiff::Timestamp::now(),
-            ) {
-                Ok(feed_data) => feed_data,
-                Err(err) => {
-                    eprintln!("encountered error while fetching feed {}: {err}", feed.url);
-                    None
-                }
+    match args.command.unwrap_or(Command::Run) {
+        Command::Run => commands::run(load_config(&config_path)?, &[], false),
+        Command::Fetch {
+            from_stdin: Some(feed_name),
+            ..
+        } => commands::fetch_from_stdin(load_config(&config_path)?, &feed_name),
+        Command::Fetch { once, feeds, .. } => {
+            commands::run(load_config(&config_path)?, &feeds, once)
+        }
+        Command::Add {
+            name,
+            url,
+            dir,
+            pick,
+            no_discover,
+        } => {
+            let url = if no_discover {
+                url
+            } else {
+                commands::resolve_feed_url(&config_path, &url, pick)?
             };
-
-            if let Some(feed_data) = feed_data {
-                feed_data.entries.iter().for_each(|entry| {
-                    match entry_to_epub(feed_name, &feed.download_dir, entry) {
-                        Ok(..) => (),
-                        Err(err) => println!("failed to create epub: {err}"),
-                    }
-                });
-            }
+            commands::add(&config_path, &name, &url, &dir)
+        }
+        Command::Discover { url } => commands::discover(&config_path, &url),
+        Command::Remove { name } => commands::remove(&config_path, &name),
+        Command::List => {
+            commands::list(&load_config(&config_path)?);
+            Ok(())
+        }
+        Command::ImportOpml { file, download_dir } => {
+            commands::import_opml(&config_path, &file, &download_dir)
+        }
+        Command::ExportOpml { output } => {
+            commands::export_opml(&load_config(&config_path)?, output.as_deref())
+        }
+        Command::Status => commands::status(&load_config(&config_path)?),
+        Command::Revive { name } => commands::revive(&load_config(&config_path)?, &name),
+        Command::Regenerate { feed, since, until } => {
+            commands::regenerate(&load_config(&config_path)?, feed.as_deref(), since, until)
         }
-
-        thread::sleep(Duration::from_secs(
-            feed_reader_v2.config.poll_interval_secs,
-        ))
     }
 }
+
+fn load_config(path: &Path) -> Result<Config> {
+    let config_file = File::open(path)
+        .with_context(|| format!("failed to open config file {}", path.display()))?;
+    Config::from_reader(config_file)
+        .with_context(|| format!("failed to read config file {}", path.display()))
+}
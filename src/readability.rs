@@ -0,0 +1,324 @@
+//! Finds the article in a web page, for feeds that only carry a summary. This
+//! is a simplified take on Arc90's readability: clutter like navigation,
+//! headers, footers and comments is dropped first, then every paragraph
+//! scores its parent and grandparent by how much text it holds, and the best
+//! scoring element is taken together with siblings that look like part of
+//! the article.
+
+use crate::html::{self, Element, Node};
+use std::collections::HashMap;
+
+/// Elements that never are part of an article.
+const UNLIKELY_ELEMENTS: &[&str] = &[
+    "aside", "button", "dialog", "footer", "form", "header", "iframe", "menu", "nav", "noscript",
+    "script", "style", "svg", "template",
+];
+
+/// Words in class names and ids that mark clutter, unless one of the
+/// LIKELY_HINTS is there as well.
+const UNLIKELY_HINTS: &[&str] = &[
+    "ad-",
+    "advert",
+    "banner",
+    "breadcrumb",
+    "comment",
+    "cookie",
+    "disqus",
+    "footer",
+    "masthead",
+    "menu",
+    "navbar",
+    "newsletter",
+    "pagination",
+    "popup",
+    "promo",
+    "related",
+    "share",
+    "sidebar",
+    "social",
+    "sponsor",
+    "subscribe",
+];
+
+const LIKELY_HINTS: &[&str] = &[
+    "article", "blog", "body", "content", "entry", "main", "post", "story", "text",
+];
+
+/// Roles of landmarks that aren't the article.
+const UNLIKELY_ROLES: &[&str] = &[
+    "banner",
+    "complementary",
+    "contentinfo",
+    "dialog",
+    "navigation",
+];
+
+/// Elements that start a new block, divs without any of them are treated
+/// like paragraphs since plenty of sites write their text that way.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "article",
+    "blockquote",
+    "div",
+    "dl",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "ul",
+];
+
+/// Paragraphs shorter than this are captions, bylines and the like.
+const MIN_PARAGRAPH_CHARS: usize = 25;
+
+/// extract_article returns the main content of a page as XHTML, or None if
+/// nothing on it looks like an article.
+pub fn extract_article(page: &str) -> Option<String> {
+    let nodes = strip_clutter(html::parse_fragment(page));
+
+    let mut candidates: HashMap<Vec<usize>, f64> = HashMap::new();
+    score_paragraphs(&nodes, &mut Vec::new(), &mut Vec::new(), &mut candidates);
+
+    let (top_path, top_score) = candidates
+        .iter()
+        .map(|(path, score)| {
+            let element = element_at(&nodes, path).expect("candidates exist");
+            (path, score * (1.0 - link_density(element)))
+        })
+        .max_by(|(a_path, a), (b_path, b)| {
+            // Ties go to the outermost element so whole articles win over
+            // single sections of them.
+            a.total_cmp(b).then(b_path.len().cmp(&a_path.len()))
+        })?;
+
+    let (parent_path, top_index) = top_path.split_at(top_path.len() - 1);
+    let siblings = match parent_path.is_empty() {
+        true => &nodes,
+        false => &element_at(&nodes, parent_path)?.children,
+    };
+
+    // Articles are sometimes split into several containers next to each
+    // other, like a lead paragraph followed by the body.
+    let threshold = f64::max(10.0, top_score * 0.2);
+    let mut article = Vec::new();
+    for (i, sibling) in siblings.iter().enumerate() {
+        let Node::Element(element) = sibling else {
+            continue;
+        };
+        let mut path = parent_path.to_vec();
+        path.push(i);
+
+        let include = i == top_index[0]
+            || candidates
+                .get(&path)
+                .is_some_and(|score| score * (1.0 - link_density(element)) >= threshold)
+            || (element.name == "p" && {
+                let length = text_length(element);
+                let link_density = link_density(element);
+                (length > 80 && link_density < 0.25)
+                    || (length > 0 && link_density == 0.0 && element.text_content().contains(". "))
+            });
+        if include {
+            article.push(sibling.clone());
+        }
+    }
+
+    Some(html::to_xhtml(&article))
+}
+
+/// strip_clutter drops the elements that are never part of an article.
+fn strip_clutter(nodes: Vec<Node>) -> Vec<Node> {
+    nodes
+        .into_iter()
+        .filter_map(|node| match node {
+            Node::Element(element) if is_unlikely(&element) => None,
+            Node::Element(mut element) => {
+                element.children = strip_clutter(element.children);
+                Some(Node::Element(element))
+            }
+            text => Some(text),
+        })
+        .collect()
+}
+
+fn is_unlikely(element: &Element) -> bool {
+    if UNLIKELY_ELEMENTS.contains(&element.name.as_str()) {
+        return true;
+    }
+    if matches!(element.name.as_str(), "html" | "body" | "article" | "main") {
+        return false;
+    }
+    if element
+        .attr("role")
+        .is_some_and(|role| UNLIKELY_ROLES.contains(&role.trim()))
+    {
+        return true;
+    }
+
+    let hints = hints(element);
+    UNLIKELY_HINTS.iter().any(|hint| hints.contains(hint))
+        && !LIKELY_HINTS.iter().any(|hint| hints.contains(hint))
+}
+
+/// hints are the class names and id of an element, lowercased.
+fn hints(element: &Element) -> String {
+    format!(
+        "{} {}",
+        element.attr("class").unwrap_or_default(),
+        element.attr("id").unwrap_or_default()
+    )
+    .to_ascii_lowercase()
+}
+
+/// score_paragraphs adds the score of every paragraph to its parent and,
+/// halved, to its grandparent. Elements are identified by their path of
+/// child indices from the root.
+fn score_paragraphs<'a>(
+    nodes: &'a [Node],
+    path: &mut Vec<usize>,
+    ancestors: &mut Vec<&'a Element>,
+    candidates: &mut HashMap<Vec<usize>, f64>,
+) {
+    for (i, node) in nodes.iter().enumerate() {
+        let Node::Element(element) = node else {
+            continue;
+        };
+        path.push(i);
+
+        if is_paragraph(element) {
+            let text = element.text_content();
+            let length = text.trim().chars().count();
+            if length >= MIN_PARAGRAPH_CHARS {
+                let score =
+                    1.0 + text.matches(',').count() as f64 + f64::min((length / 100) as f64, 3.0);
+
+                for (generation, divisor) in [(1, 1.0), (2, 2.0)] {
+                    if ancestors.len() < generation {
+                        break;
+                    }
+                    let ancestor = ancestors[ancestors.len() - generation];
+                    let ancestor_path = path[..path.len() - generation].to_vec();
+                    *candidates
+                        .entry(ancestor_path)
+                        .or_insert_with(|| initial_score(ancestor)) += score / divisor;
+                }
+            }
+        }
+
+        ancestors.push(element);
+        score_paragraphs(&element.children, path, ancestors, candidates);
+        ancestors.pop();
+        path.pop();
+    }
+}
+
+fn is_paragraph(element: &Element) -> bool {
+    match element.name.as_str() {
+        "p" | "pre" | "td" => true,
+        "div" => !element.children.iter().any(|child| {
+            matches!(child, Node::Element(child) if BLOCK_ELEMENTS.contains(&child.name.as_str()))
+        }),
+        _ => false,
+    }
+}
+
+/// initial_score favours elements that usually hold articles and punishes
+/// those that hold lists and headings.
+fn initial_score(element: &Element) -> f64 {
+    let by_name = match element.name.as_str() {
+        "article" => 10.0,
+        "div" | "main" | "section" => 5.0,
+        "blockquote" | "pre" | "td" => 3.0,
+        "address" | "dd" | "dl" | "dt" | "form" | "li" | "ol" | "ul" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    };
+
+    let hints = hints(element);
+    let by_hints = if LIKELY_HINTS.iter().any(|hint| hints.contains(hint)) {
+        25.0
+    } else if UNLIKELY_HINTS.iter().any(|hint| hints.contains(hint)) {
+        -25.0
+    } else {
+        0.0
+    };
+
+    by_name + by_hints
+}
+
+fn text_length(element: &Element) -> usize {
+    element.text_content().trim().chars().count()
+}
+
+/// link_density is the share of an element's text that is inside links,
+/// lists of links are navigation rather than article.
+fn link_density(element: &Element) -> f64 {
+    let length = text_length(element);
+    if length == 0 {
+        return 0.0;
+    }
+
+    let mut link_length = 0;
+    html::walk_elements(&element.children, &mut |child| {
+        if child.name == "a" {
+            link_length += text_length(child);
+        }
+    });
+    // Links nested in links would count twice.
+    f64::min(link_length as f64 / length as f64, 1.0)
+}
+
+fn element_at<'a>(nodes: &'a [Node], path: &[usize]) -> Option<&'a Element> {
+    let (first, rest) = path.split_first()?;
+    let Node::Element(element) = nodes.get(*first)? else {
+        return None;
+    };
+    match rest.is_empty() {
+        true => Some(element),
+        false => element_at(&element.children, rest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_article_from_page() {
+        let article = extract_article(include_str!("testdata/readability/article.html"))
+            .expect("found no article");
+
+        assert!(article.contains("<p>The lead paragraph of the story"));
+        assert!(article.contains("<p>The second paragraph, which goes on"));
+        assert!(article.contains(r#"<img src="/images/chart.png" alt="A chart" />"#));
+        for clutter in [
+            "Home",
+            "Subscribe to our newsletter",
+            "Share on",
+            "Great post",
+            "All rights reserved",
+            "Trending now",
+            "analytics",
+        ] {
+            assert!(
+                !article.contains(clutter),
+                "{clutter:?} should have been stripped"
+            );
+        }
+    }
+
+    #[test]
+    fn extract_article_without_paragraphs() {
+        assert_eq!(
+            extract_article("<html><body><ul><li><a href=/>Home</a></li></ul></body></html>"),
+            None
+        );
+    }
+}
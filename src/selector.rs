@@ -0,0 +1,474 @@
+//! A small subset of CSS selectors, enough to point at the parts of a web
+//! page that scraped feeds are made of: type, class, id and attribute
+//! selectors, combined with the descendant and child combinators and
+//! separated by commas. Pseudo classes and sibling combinators are not
+//! supported.
+
+use crate::html::{Element, Node};
+use serde::Deserialize;
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SelectorError {
+    #[error("unexpected {found:?} at position {position} in selector {selector:?}")]
+    UnexpectedCharacterError {
+        selector: String,
+        position: usize,
+        found: char,
+    },
+    #[error("selector {0:?} ends unexpectedly")]
+    UnexpectedEndError(String),
+}
+
+/// Selector is a parsed selector list, it matches an element if any of its
+/// alternatives does.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Selector {
+    text: String,
+    alternatives: Vec<Complex>,
+}
+
+/// Complex is a chain of compound selectors, `combinators[i]` sits between
+/// `compounds[i]` and `compounds[i + 1]`.
+#[derive(Clone, Debug, PartialEq)]
+struct Complex {
+    compounds: Vec<Compound>,
+    combinators: Vec<Combinator>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+/// Compound is a selector for a single element like `a.external[href]`, a
+/// missing name matches any element.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Compound {
+    name: Option<String>,
+    conditions: Vec<Condition>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Condition {
+    Id(String),
+    Class(String),
+    Attribute {
+        name: String,
+        value: Option<(AttributeOperator, String)>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AttributeOperator {
+    /// `=`
+    Equals,
+    /// `~=`, one of the whitespace separated words.
+    Includes,
+    /// `|=`, the value or the value followed by a dash.
+    DashMatch,
+    /// `^=`
+    Prefix,
+    /// `$=`
+    Suffix,
+    /// `*=`
+    Substring,
+}
+
+impl Selector {
+    pub fn parse(selector: &str) -> Result<Selector, SelectorError> {
+        let mut parser = Parser {
+            selector,
+            chars: selector.chars().collect(),
+            position: 0,
+        };
+
+        let mut alternatives = vec![parser.complex()?];
+        while parser.peek() == Some(',') {
+            parser.position += 1;
+            alternatives.push(parser.complex()?);
+        }
+        if parser.peek().is_some() {
+            return Err(parser.unexpected());
+        }
+
+        Ok(Selector {
+            text: selector.into(),
+            alternatives,
+        })
+    }
+
+    /// select returns every element below `nodes` the selector matches, in
+    /// document order.
+    pub fn select<'a>(&self, nodes: &'a [Node]) -> Vec<&'a Element> {
+        let mut selected = Vec::new();
+        self.select_into(nodes, &mut Vec::new(), &mut selected);
+        selected
+    }
+
+    /// select_first returns the first element the selector matches.
+    pub fn select_first<'a>(&self, nodes: &'a [Node]) -> Option<&'a Element> {
+        // Pages are small enough that stopping early isn't worth the code.
+        self.select(nodes).into_iter().next()
+    }
+
+    fn select_into<'a>(
+        &self,
+        nodes: &'a [Node],
+        ancestors: &mut Vec<&'a Element>,
+        selected: &mut Vec<&'a Element>,
+    ) {
+        for node in nodes {
+            let Node::Element(element) = node else {
+                continue;
+            };
+            if self
+                .alternatives
+                .iter()
+                .any(|complex| complex.matches(element, ancestors))
+            {
+                selected.push(element);
+            }
+            ancestors.push(element);
+            self.select_into(&element.children, ancestors, selected);
+            ancestors.pop();
+        }
+    }
+}
+
+impl TryFrom<String> for Selector {
+    type Error = SelectorError;
+
+    fn try_from(selector: String) -> Result<Self, Self::Error> {
+        Selector::parse(&selector)
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Complex {
+    /// matches checks the compounds right to left, `ancestors` are the
+    /// parents of `element` with the root first.
+    fn matches(&self, element: &Element, ancestors: &[&Element]) -> bool {
+        let last = self.compounds.len() - 1;
+        self.compounds[last].matches(element) && self.matches_ancestors(last, ancestors)
+    }
+
+    /// matches_ancestors checks the compounds before `i` against the
+    /// ancestors of the element `compounds[i]` matched.
+    fn matches_ancestors(&self, i: usize, ancestors: &[&Element]) -> bool {
+        if i == 0 {
+            return true;
+        }
+
+        let compound = &self.compounds[i - 1];
+        match self.combinators[i - 1] {
+            Combinator::Child => match ancestors.split_last() {
+                Some((parent, rest)) => {
+                    compound.matches(parent) && self.matches_ancestors(i - 1, rest)
+                }
+                None => false,
+            },
+            Combinator::Descendant => (0..ancestors.len()).rev().any(|k| {
+                compound.matches(ancestors[k]) && self.matches_ancestors(i - 1, &ancestors[..k])
+            }),
+        }
+    }
+}
+
+impl Compound {
+    fn matches(&self, element: &Element) -> bool {
+        if self.name.as_ref().is_some_and(|name| *name != element.name) {
+            return false;
+        }
+
+        self.conditions.iter().all(|condition| match condition {
+            Condition::Id(id) => element.attr("id") == Some(id),
+            Condition::Class(class) => element
+                .attr("class")
+                .is_some_and(|classes| classes.split_ascii_whitespace().any(|c| c == class)),
+            Condition::Attribute { name, value } => {
+                let Some(actual) = element.attr(name) else {
+                    return false;
+                };
+                let Some((operator, expected)) = value else {
+                    return true;
+                };
+                let expected = expected.as_str();
+                match operator {
+                    AttributeOperator::Equals => actual == expected,
+                    AttributeOperator::Includes => {
+                        actual.split_ascii_whitespace().any(|word| word == expected)
+                    }
+                    AttributeOperator::DashMatch => {
+                        actual == expected
+                            || actual
+                                .strip_prefix(expected)
+                                .is_some_and(|rest| rest.starts_with('-'))
+                    }
+                    AttributeOperator::Prefix => {
+                        !expected.is_empty() && actual.starts_with(expected)
+                    }
+                    AttributeOperator::Suffix => !expected.is_empty() && actual.ends_with(expected),
+                    AttributeOperator::Substring => {
+                        !expected.is_empty() && actual.contains(expected)
+                    }
+                }
+            }
+        })
+    }
+}
+
+struct Parser<'a> {
+    selector: &'a str,
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    /// skip_whitespace returns whether there was any.
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.position;
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+        self.position > start
+    }
+
+    fn unexpected(&self) -> SelectorError {
+        match self.peek() {
+            Some(found) => SelectorError::UnexpectedCharacterError {
+                selector: self.selector.into(),
+                position: self.position,
+                found,
+            },
+            None => SelectorError::UnexpectedEndError(self.selector.into()),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), SelectorError> {
+        if self.peek() != Some(expected) {
+            return Err(self.unexpected());
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn complex(&mut self) -> Result<Complex, SelectorError> {
+        self.skip_whitespace();
+        let mut complex = Complex {
+            compounds: vec![self.compound()?],
+            combinators: Vec::new(),
+        };
+
+        loop {
+            let whitespace = self.skip_whitespace();
+            let combinator = match self.peek() {
+                Some('>') => {
+                    self.position += 1;
+                    self.skip_whitespace();
+                    Combinator::Child
+                }
+                None | Some(',') => return Ok(complex),
+                Some(_) if whitespace => Combinator::Descendant,
+                Some(_) => return Err(self.unexpected()),
+            };
+            complex.combinators.push(combinator);
+            complex.compounds.push(self.compound()?);
+        }
+    }
+
+    fn compound(&mut self) -> Result<Compound, SelectorError> {
+        let mut compound = Compound::default();
+        let universal = match self.peek() {
+            Some('*') => {
+                self.position += 1;
+                true
+            }
+            Some(c) if is_name_char(c) => {
+                // Our HTML parser lowercases element names.
+                compound.name = Some(self.name()?.to_ascii_lowercase());
+                false
+            }
+            _ => false,
+        };
+
+        loop {
+            let condition = match self.peek() {
+                Some('#') => {
+                    self.position += 1;
+                    Condition::Id(self.name()?)
+                }
+                Some('.') => {
+                    self.position += 1;
+                    Condition::Class(self.name()?)
+                }
+                Some('[') => {
+                    self.position += 1;
+                    self.attribute()?
+                }
+                _ => break,
+            };
+            compound.conditions.push(condition);
+        }
+
+        if !universal && compound.name.is_none() && compound.conditions.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(compound)
+    }
+
+    fn name(&mut self) -> Result<String, SelectorError> {
+        let start = self.position;
+        while self.peek().is_some_and(is_name_char) {
+            self.position += 1;
+        }
+        if self.position == start {
+            return Err(self.unexpected());
+        }
+        Ok(self.chars[start..self.position].iter().collect())
+    }
+
+    /// attribute parses what follows the `[` of an attribute selector.
+    fn attribute(&mut self) -> Result<Condition, SelectorError> {
+        self.skip_whitespace();
+        // Attribute names are lowercased by our HTML parser as well.
+        let name = self.name()?.to_ascii_lowercase();
+        self.skip_whitespace();
+
+        let operator = match self.peek() {
+            Some(']') => {
+                self.position += 1;
+                return Ok(Condition::Attribute { name, value: None });
+            }
+            Some('=') => AttributeOperator::Equals,
+            Some('~') => AttributeOperator::Includes,
+            Some('|') => AttributeOperator::DashMatch,
+            Some('^') => AttributeOperator::Prefix,
+            Some('$') => AttributeOperator::Suffix,
+            Some('*') => AttributeOperator::Substring,
+            _ => return Err(self.unexpected()),
+        };
+        if operator != AttributeOperator::Equals {
+            self.position += 1;
+        }
+        self.expect('=')?;
+        self.skip_whitespace();
+
+        let value = match self.peek() {
+            Some(quote @ ('"' | '\'')) => {
+                self.position += 1;
+                let start = self.position;
+                while self.peek().is_some_and(|c| c != quote) {
+                    self.position += 1;
+                }
+                let value = self.chars[start..self.position].iter().collect();
+                self.expect(quote)?;
+                value
+            }
+            _ => self.name()?,
+        };
+        self.skip_whitespace();
+        self.expect(']')?;
+
+        Ok(Condition::Attribute {
+            name,
+            value: Some((operator, value)),
+        })
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html;
+
+    const PAGE: &str = r#"
+<main id="posts">
+  <article class="post featured">
+    <h2><a href="/one" data-kind="post-link">One</a></h2>
+    <div class="meta"><a href="https://elsewhere.example/">elsewhere</a></div>
+  </article>
+  <article class="post">
+    <section><h2><a href="/two" lang="en-GB">Two</a></h2></section>
+  </article>
+</main>
+<aside><h2><a href="/about">About</a></h2></aside>"#;
+
+    fn select_hrefs(selector: &str) -> Vec<String> {
+        let nodes = html::parse_fragment(PAGE);
+        let selector = Selector::parse(selector).expect("failed to parse selector");
+        selector
+            .select(&nodes)
+            .into_iter()
+            .map(|element| element.attr("href").unwrap_or_default().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn select_combinators() {
+        assert_eq!(select_hrefs("article h2 a"), ["/one", "/two"]);
+        assert_eq!(select_hrefs("article > h2 > a"), ["/one"]);
+        assert_eq!(
+            select_hrefs("#posts .post a"),
+            ["/one", "https://elsewhere.example/", "/two"]
+        );
+        assert_eq!(
+            select_hrefs("ARTICLE.post.featured h2 a, aside a"),
+            ["/one", "/about"]
+        );
+        assert_eq!(
+            select_hrefs("main * > a"),
+            ["/one", "https://elsewhere.example/", "/two"]
+        );
+    }
+
+    #[test]
+    fn select_attributes() {
+        assert_eq!(select_hrefs("a[data-kind]"), ["/one"]);
+        assert_eq!(
+            select_hrefs("a[href^='https:']"),
+            ["https://elsewhere.example/"]
+        );
+        assert_eq!(select_hrefs(r#"a[href $= "o"]"#), ["/two"]);
+        assert_eq!(select_hrefs("a[href*=bou]"), ["/about"]);
+        assert_eq!(select_hrefs("a[lang|=en]"), ["/two"]);
+        assert_eq!(select_hrefs("a[data-kind~=post-link]"), ["/one"]);
+        assert_eq!(select_hrefs(r#"a[href="/one"]"#), ["/one"]);
+        assert!(select_hrefs("a[href^='']").is_empty());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            Selector::parse("a:hover"),
+            Err(SelectorError::UnexpectedCharacterError {
+                selector: "a:hover".into(),
+                position: 1,
+                found: ':',
+            })
+        );
+        assert_eq!(
+            Selector::parse("a >"),
+            Err(SelectorError::UnexpectedEndError("a >".into()))
+        );
+        assert!(Selector::parse("a[href").is_err());
+        assert!(Selector::parse("a, ").is_err());
+        assert!(Selector::parse("").is_err());
+    }
+}
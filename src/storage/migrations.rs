@@ -0,0 +1,361 @@
+use rusqlite::Transaction;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ErrorMigration {
+    #[error("database query failed: {0}")]
+    DBError(#[from] rusqlite::Error),
+    #[error(
+        "database schema version {found} is newer than version {supported} supported by this binary, refusing to touch it"
+    )]
+    DatabaseTooNew { found: u32, supported: u32 },
+    #[error("migration to schema version {version} failed: {source}")]
+    MigrationFailed {
+        version: u32,
+        source: rusqlite::Error,
+    },
+}
+
+type Migration = fn(&Transaction) -> rusqlite::Result<()>;
+
+/// MIGRATIONS holds every schema change in order, the database is at schema
+/// version N once the first N migrations have been applied. Never change or
+/// reorder an existing migration, only ever append new ones.
+const MIGRATIONS: &[Migration] = &[
+    baseline,
+    feed_backoff,
+    deduplicate_entries,
+    entry_published_and_link,
+    digests,
+    editions,
+    feed_moves,
+    feed_logos,
+    entry_language,
+    entry_categories,
+    entry_lists_as_json,
+    entry_books_pending,
+    entry_article_fetched,
+];
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS.len() as u32
+}
+
+pub fn schema_version(db: &rusqlite::Connection) -> rusqlite::Result<u32> {
+    db.query_row("PRAGMA user_version", (), |r| r.get(0))
+}
+
+/// migrate brings the database up to the latest schema version. All pending
+/// migrations run in a single transaction so a failing step leaves the
+/// database exactly as we found it.
+pub fn migrate(db: &rusqlite::Connection) -> Result<(), ErrorMigration> {
+    let found = schema_version(db)?;
+    let supported = latest_version();
+    if found > supported {
+        return Err(ErrorMigration::DatabaseTooNew { found, supported });
+    }
+
+    let tx = db.unchecked_transaction()?;
+    for (version, migration) in (1..).zip(MIGRATIONS).skip(found as usize) {
+        migration(&tx).map_err(|source| ErrorMigration::MigrationFailed { version, source })?;
+        // PRAGMA does not take bound parameters.
+        tx.execute_batch(&format!("PRAGMA user_version = {version}"))?;
+    }
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// baseline is the schema every database had before migrations existed,
+/// hence the IF NOT EXISTS.
+fn baseline(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS feeds (
+            id INTEGER PRIMARY KEY,
+            feed_url TEXT NOT NULL,
+            last_modified TEXT,
+            last_fetched TEXT,
+            etag TEXT
+        );
+
+        CREATE TABLE IF NOT EXISTS entries (
+            id INTEGER PRIMARY KEY,
+            feed_id INTEGER NOT NULL,
+            feed_entry_id TEXT,
+            title TEXT,
+            updated TEXT,
+            authors TEXT,
+            summary TEXT,
+            content BLOB NOT NULL,
+            FOREIGN KEY(feed_id) REFERENCES feeds(id)
+        );",
+    )
+}
+
+fn feed_backoff(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS feed_backoff (
+            feed_id INTEGER PRIMARY KEY,
+            blocked_until TEXT NOT NULL,
+            failure_count INTEGER NOT NULL,
+            last_error TEXT,
+            FOREIGN KEY(feed_id) REFERENCES feeds(id)
+        );",
+    )
+}
+
+/// deduplicate_entries drops the duplicate rows older versions inserted on
+/// every poll and makes sure it cannot happen again.
+fn deduplicate_entries(tx: &Transaction) -> rusqlite::Result<()> {
+    let has_content_hash: bool = tx.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('entries') WHERE name = 'content_hash'",
+        (),
+        |r| r.get(0),
+    )?;
+    if !has_content_hash {
+        tx.execute("ALTER TABLE entries ADD COLUMN content_hash TEXT", ())?;
+    }
+
+    tx.execute_batch(
+        "DELETE FROM entries WHERE id NOT IN (
+            SELECT MAX(id) FROM entries GROUP BY feed_id, feed_entry_id
+        );
+
+        CREATE UNIQUE INDEX IF NOT EXISTS entries_feed_id_feed_entry_id
+            ON entries (feed_id, feed_entry_id);",
+    )
+}
+
+/// entry_published_and_link stores what we need to build an EPUB without
+/// the original feed at hand.
+fn entry_published_and_link(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE entries ADD COLUMN published TEXT;
+        ALTER TABLE entries ADD COLUMN link TEXT;",
+    )
+}
+
+/// digests records which entries went into which digest so every entry is
+/// only ever delivered in one of them.
+fn digests(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS digests (
+            id INTEGER PRIMARY KEY,
+            feed_id INTEGER NOT NULL,
+            title TEXT NOT NULL,
+            created TEXT NOT NULL,
+            FOREIGN KEY(feed_id) REFERENCES feeds(id)
+        );
+
+        CREATE TABLE IF NOT EXISTS digest_entries (
+            digest_id INTEGER NOT NULL,
+            entry_id INTEGER NOT NULL UNIQUE,
+            FOREIGN KEY(digest_id) REFERENCES digests(id),
+            FOREIGN KEY(entry_id) REFERENCES entries(id)
+        );",
+    )
+}
+
+/// editions is the cross-feed counterpart of digests, tracking which entries
+/// went into which newspaper edition.
+fn editions(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS editions (
+            id INTEGER PRIMARY KEY,
+            title TEXT NOT NULL,
+            created TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS edition_entries (
+            edition_id INTEGER NOT NULL,
+            entry_id INTEGER NOT NULL UNIQUE,
+            FOREIGN KEY(edition_id) REFERENCES editions(id),
+            FOREIGN KEY(entry_id) REFERENCES entries(id)
+        );",
+    )
+}
+
+/// feed_moves remembers where feeds permanently moved to and which ones are
+/// gone for good.
+fn feed_moves(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE feeds ADD COLUMN moved_to TEXT;
+        ALTER TABLE feeds ADD COLUMN gone_since TEXT;",
+    )
+}
+
+/// feed_logos keeps the logo of feeds around for the covers of their books.
+fn feed_logos(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE feeds ADD COLUMN logo TEXT;")
+}
+
+/// entry_language keeps the language of entries for the books built from
+/// them.
+fn entry_language(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE entries ADD COLUMN language TEXT;")
+}
+
+/// entry_categories keeps the categories of entries as subjects of their
+/// books.
+fn entry_categories(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE entries ADD COLUMN categories TEXT;")
+}
+
+/// entry_lists_as_json stores the authors and categories of entries as JSON
+/// arrays instead of joining them with commas, which names like "Doe, Jane"
+/// contain as well. Rows that were joined before are split up the way they
+/// used to be read.
+fn entry_lists_as_json(tx: &Transaction) -> rusqlite::Result<()> {
+    let rows: Vec<(u64, Option<String>, Option<String>)> = tx
+        .prepare(
+            "SELECT id, authors, categories FROM entries
+            WHERE authors IS NOT NULL OR categories IS NOT NULL",
+        )?
+        .query_map((), |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let to_json = |list: Option<String>| {
+        let items: Vec<&str> = list
+            .iter()
+            .flat_map(|list| list.split(','))
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .collect();
+        (!items.is_empty()).then(|| serde_json::to_string(&items).unwrap())
+    };
+    let mut statement =
+        tx.prepare("UPDATE entries SET authors = ?2, categories = ?3 WHERE id = ?1")?;
+    for (id, authors, categories) in rows {
+        statement.execute((id, to_json(authors), to_json(categories)))?;
+    }
+    Ok(())
+}
+
+/// entry_books_pending marks entries whose book has not been written yet,
+/// so a book that failed to build is tried again instead of getting lost.
+/// Everything stored so far already had its chance.
+fn entry_books_pending(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE entries ADD COLUMN book_pending INTEGER NOT NULL DEFAULT 0;")
+}
+
+/// entry_article_fetched remembers whether the full article of an entry
+/// could be fetched. Entries stored before are taken as fetched, rather than
+/// downloading every article again.
+fn entry_article_fetched(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE entries ADD COLUMN article_fetched INTEGER NOT NULL DEFAULT 1;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASELINE_FIXTURE: &str = include_str!("testdata/baseline.sql");
+
+    fn column_names(db: &rusqlite::Connection, table: &str) -> Vec<String> {
+        let mut statement = db
+            .prepare(&format!("SELECT name FROM pragma_table_info('{table}')"))
+            .unwrap();
+        statement
+            .query_map((), |r| r.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn migrate_empty_database() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        migrate(&db).expect("failed to migrate empty database");
+        assert_eq!(schema_version(&db).unwrap(), latest_version());
+
+        // Running it again must be a no-op.
+        migrate(&db).expect("failed to migrate an up to date database");
+        assert_eq!(schema_version(&db).unwrap(), latest_version());
+    }
+
+    #[test]
+    fn migrate_baseline_fixture() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute_batch(BASELINE_FIXTURE).unwrap();
+        assert_eq!(schema_version(&db).unwrap(), 0);
+
+        migrate(&db).expect("failed to migrate baseline database");
+        assert_eq!(schema_version(&db).unwrap(), latest_version());
+
+        assert!(column_names(&db, "entries").contains(&"content_hash".to_string()));
+        assert!(column_names(&db, "feed_backoff").contains(&"blocked_until".to_string()));
+
+        let feed_url: String = db
+            .query_row("SELECT feed_url FROM feeds WHERE id = 1", (), |r| r.get(0))
+            .unwrap();
+        assert_eq!(feed_url, "https://example.com/atom.xml");
+
+        // The fixture stores the same entry three times, we keep the newest row.
+        let entries: Vec<(u64, String)> = db
+            .prepare("SELECT id, feed_entry_id FROM entries ORDER BY id")
+            .unwrap()
+            .query_map((), |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                (3, "urn:entry:1".to_string()),
+                (4, "urn:entry:2".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn migrate_comma_separated_lists() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute_batch(BASELINE_FIXTURE).unwrap();
+        // Up to entry_categories, where lists were still joined by commas.
+        let tx = db.unchecked_transaction().unwrap();
+        for migration in &MIGRATIONS[..10] {
+            migration(&tx).unwrap();
+        }
+        tx.commit().unwrap();
+        db.execute_batch(
+            "PRAGMA user_version = 10;
+            UPDATE entries SET authors = 'Jane Doe, John Roe', categories = 'rust,,books' WHERE id = 3;
+            UPDATE entries SET authors = '' WHERE id = 4;",
+        )
+        .unwrap();
+
+        migrate(&db).expect("failed to migrate comma separated lists");
+        let lists: Vec<(Option<String>, Option<String>)> = db
+            .prepare("SELECT authors, categories FROM entries ORDER BY id")
+            .unwrap()
+            .query_map((), |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            lists,
+            vec![
+                (
+                    Some(r#"["Jane Doe","John Roe"]"#.to_string()),
+                    Some(r#"["rust","books"]"#.to_string())
+                ),
+                (None, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn migrate_refuses_newer_database() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute_batch(&format!("PRAGMA user_version = {}", latest_version() + 1))
+            .unwrap();
+
+        match migrate(&db) {
+            Err(ErrorMigration::DatabaseTooNew { found, supported }) => {
+                assert_eq!(found, latest_version() + 1);
+                assert_eq!(supported, latest_version());
+            }
+            other => panic!("expected the database to be refused, got {other:?}"),
+        }
+    }
+}
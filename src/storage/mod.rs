@@ -0,0 +1,1305 @@
+use crate::html;
+use jiff::Timestamp;
+use rusqlite::OptionalExtension;
+use siphasher::sip128::{Hasher128, SipHasher13};
+use std::hash::Hasher;
+use thiserror::Error;
+
+pub mod migrations;
+
+pub use migrations::ErrorMigration;
+
+#[derive(Error, Debug)]
+pub enum ErrorNew {
+    #[error("invalid timestamp found in database: {0}")]
+    InvalidTimestamp(#[from] jiff::Error),
+    #[error("failed to open database file {db_file}: {source}")]
+    DBFileOpenError {
+        db_file: String,
+        source: rusqlite::Error,
+    },
+    #[error("failed to initialise database: {0}")]
+    DBError(#[from] ErrorDBOperation),
+    #[error("failed to migrate database: {0}")]
+    MigrationError(#[from] ErrorMigration),
+}
+
+pub struct Storage {
+    db: rusqlite::Connection,
+}
+
+impl Storage {
+    pub fn new(db_file: &str) -> Result<Self, ErrorNew> {
+        let db = match rusqlite::Connection::open(db_file) {
+            Ok(db) => db,
+            Err(err) => {
+                return Err(ErrorNew::DBFileOpenError {
+                    db_file: db_file.to_string(),
+                    source: err,
+                })
+            }
+        };
+
+        Ok(Storage { db })
+    }
+
+    /// new_in_memory is largely only ever used in testing
+    /// as a convenience to not have to deal with the life-
+    /// cycle of a file handle.
+    pub fn new_in_memory() -> Result<Self, ErrorNew> {
+        let db = match rusqlite::Connection::open_in_memory() {
+            Ok(db) => db,
+            Err(err) => {
+                return Err(ErrorNew::DBFileOpenError {
+                    db_file: "memory".into(),
+                    source: err,
+                })
+            }
+        };
+        Ok(Storage { db })
+    }
+
+    /// init_database migrates the database to the newest schema version,
+    /// see the migrations module for the individual steps.
+    pub fn init_database(&self) -> Result<(), ErrorMigration> {
+        migrations::migrate(&self.db)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FeedStats {
+    pub id: u64,
+    pub url: String,
+    // last_modified will not be a Timestamp since it's just a String we have to
+    // pass back to the feed endpoint and not parse ourselves.
+    pub last_modified: Option<String>,
+    pub last_fetched: Option<Timestamp>,
+    pub etag: Option<String>,
+    /// moved_to is where the feed permanently redirected to, it is fetched
+    /// from there instead of `url` from then on.
+    pub moved_to: Option<String>,
+    /// gone_since is set once the feed answered with 410 Gone, it is not
+    /// polled anymore until someone revives it.
+    pub gone_since: Option<Timestamp>,
+    /// logo is the URL of the feed's logo or icon, books use it as cover.
+    pub logo: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum ErrorDBOperation {
+    #[error("database query failed: {0}")]
+    DBError(#[from] rusqlite::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum ErrorNewFeedStats {
+    #[error("database insert failed: {0}")]
+    DBInsertError(#[from] rusqlite::Error),
+    #[error("failed to feed from database: {0}")]
+    DBError(#[from] ErrorDBOperation),
+    #[error("somehow cannot find feed we just created, report a bug")]
+    NewFeedNotFoundError,
+}
+
+impl Storage {
+    /// feed_stats_from_db looks up a feed by its URL, or by the URL it moved
+    /// to so that nothing is lost once the config catches up with a move.
+    pub fn feed_stats_from_db(&self, url: &str) -> Result<Option<FeedStats>, ErrorDBOperation> {
+        let mut statement = self
+            .db
+            .prepare(
+                "SELECT id, feed_url, last_modified, last_fetched, etag, moved_to, gone_since, logo
+                FROM feeds WHERE feed_url = ?1 OR moved_to = ?1
+                ORDER BY feed_url = ?1 DESC LIMIT 1;",
+            )
+            .expect("sql query wrong");
+
+        let feed_stats = statement
+            .query_row([url], |r| {
+                let parse_timestamp = |timestamp: Option<String>| {
+                    timestamp.map(|timestamp| {
+                        timestamp
+                            .parse::<Timestamp>()
+                            .expect("we manage our own timestamps, this row is corrupted")
+                    })
+                };
+
+                let feed_stats = FeedStats {
+                    id: r.get(0)?,
+                    url: r.get(1)?,
+                    last_modified: r.get(2)?,
+                    last_fetched: parse_timestamp(r.get(3)?),
+                    etag: r.get(4)?,
+                    moved_to: r.get(5)?,
+                    gone_since: parse_timestamp(r.get(6)?),
+                    logo: r.get(7)?,
+                };
+
+                Ok(feed_stats)
+            })
+            .optional()?;
+
+        Ok(feed_stats)
+    }
+
+    pub fn feed_stats_to_db(&self, feed_stats: &FeedStats) -> Result<(), ErrorDBOperation> {
+        let mut statement = self
+            .db
+            .prepare(
+                "INSERT OR REPLACE INTO feeds
+                (id, feed_url, etag, last_modified, last_fetched, moved_to, gone_since, logo)
+               VALUES ((SELECT id FROM feeds WHERE feed_url = ?1), ?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )
+            .expect("SQL syntax error");
+
+        let _ = statement.execute((
+            &feed_stats.url,
+            &feed_stats.etag,
+            &feed_stats.last_modified,
+            feed_stats.last_fetched.map(|t| t.to_string()),
+            &feed_stats.moved_to,
+            feed_stats.gone_since.map(|t| t.to_string()),
+            &feed_stats.logo,
+        ))?;
+        Ok(())
+    }
+
+    pub fn new_feed_stats_to_db(&self, url: &str) -> Result<FeedStats, ErrorNewFeedStats> {
+        let mut statement = self
+            .db
+            .prepare("INSERT INTO feeds (feed_url) VALUES (?1)")
+            .expect("SQL syntax error");
+
+        statement.execute((url,))?;
+        match self.feed_stats_from_db(url)? {
+            Some(feed_stats) => Ok(feed_stats),
+            None => Err(ErrorNewFeedStats::NewFeedNotFoundError),
+        }
+    }
+}
+
+/// Backoff tracks feeds that we are not allowed to poll for a while, either
+/// because the server told us so through `Retry-After` or because the last
+/// few requests failed.
+#[derive(Debug, PartialEq)]
+pub struct Backoff {
+    pub feed_id: u64,
+    pub blocked_until: Timestamp,
+    pub failure_count: u32,
+    pub last_error: Option<String>,
+}
+
+impl Storage {
+    pub fn backoff_from_db(&self, feed_id: u64) -> Result<Option<Backoff>, ErrorDBOperation> {
+        let mut statement = self
+            .db
+            .prepare(
+                "SELECT blocked_until, failure_count, last_error FROM feed_backoff WHERE feed_id = ?;",
+            )
+            .expect("sql query wrong");
+
+        let backoff = statement
+            .query_row([feed_id], |r| {
+                let blocked_until: String = r.get(0)?;
+                Ok(Backoff {
+                    feed_id,
+                    blocked_until: blocked_until
+                        .parse()
+                        .expect("we manage our own timestamps, this row is corrupted"),
+                    failure_count: r.get(1)?,
+                    last_error: r.get(2)?,
+                })
+            })
+            .optional()?;
+
+        Ok(backoff)
+    }
+
+    pub fn backoff_to_db(&self, backoff: &Backoff) -> Result<(), ErrorDBOperation> {
+        let mut statement = self
+            .db
+            .prepare(
+                "INSERT OR REPLACE INTO feed_backoff (feed_id, blocked_until, failure_count, last_error)
+                VALUES (?1, ?2, ?3, ?4)",
+            )
+            .expect("SQL syntax error");
+
+        statement.execute((
+            backoff.feed_id,
+            backoff.blocked_until.to_string(),
+            backoff.failure_count,
+            &backoff.last_error,
+        ))?;
+
+        Ok(())
+    }
+
+    pub fn clear_backoff(&self, feed_id: u64) -> Result<(), ErrorDBOperation> {
+        self.db
+            .execute("DELETE FROM feed_backoff WHERE feed_id = ?1", (feed_id,))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub feed_id: u64,
+    pub feed_entry_id: Option<String>,
+    pub title: String,
+    pub updated: Option<String>,
+    pub published: Option<String>,
+    pub authors: Vec<String>,
+    pub summary: String,
+    pub content: String,
+    pub link: Option<String>,
+    /// language is the language tag of the entry, or of its feed if the
+    /// entry doesn't have its own.
+    pub language: Option<String>,
+    /// categories are the categories or tags of the entry.
+    pub categories: Vec<String>,
+}
+
+impl Entry {
+    /// timestamp is when the entry was published, falling back to when it
+    /// was last updated for feeds that only provide one of them.
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        self.published
+            .as_deref()
+            .or(self.updated.as_deref())
+            .and_then(|date| date.parse().ok())
+    }
+
+    /// content_hash is a stable hash over everything we render into an EPUB,
+    /// it lets us notice feeds that change an entry without bumping `updated`.
+    pub fn content_hash(&self) -> String {
+        let mut hasher = SipHasher13::new();
+        for field in [&self.title, &self.summary, &self.content] {
+            hasher.write(field.as_bytes());
+            // Separate the fields so moving text between them changes the hash.
+            hasher.write_u8(0);
+        }
+        format!("{:032x}", hasher.finish128().as_u128())
+    }
+}
+
+/// EntryChange describes what storing an entry did to the database, only new
+/// and updated entries need a new EPUB.
+#[derive(Debug, PartialEq)]
+pub enum EntryChange {
+    New,
+    Updated,
+    Unchanged,
+}
+
+#[derive(Error, Debug)]
+pub enum EntryConversionError {
+    #[error("could not get bytes from HTML content")]
+    BodyExtractionError,
+    #[error("could not get bytes from HTML summary")]
+    SummaryExtractionError,
+    #[error("could not get title from entry")]
+    TitleExtractionError,
+}
+
+pub fn entry_from_feed_entry(
+    feed_id: u64,
+    feed_entry: &feed_rs::model::Entry,
+) -> Result<Entry, EntryConversionError> {
+    let content = extract_html_string_from_entry(feed_entry)?;
+
+    let title = match &feed_entry.title {
+        // Atom titles can be HTML, which has no place in a book title.
+        Some(title) if has_mime_type(&title.content_type, "text/html") => {
+            words(&title.content).join(" ")
+        }
+        Some(title) if !title.content.trim().is_empty() => title.content.clone(),
+        _ => String::new(),
+    };
+    let title = match title.is_empty() {
+        true => title_from_content(&content),
+        false => title,
+    };
+
+    let updated = feed_entry.updated.map(|updated| updated.to_rfc3339());
+    let published = feed_entry.published.map(|published| published.to_rfc3339());
+
+    let authors = feed_entry
+        .authors
+        .iter()
+        .map(|author| author.name.trim())
+        .filter(|author| !author.is_empty())
+        .map(String::from)
+        .collect();
+
+    let categories = feed_entry
+        .categories
+        .iter()
+        .map(|category| category.label.as_deref().unwrap_or(&category.term))
+        .map(str::trim)
+        .filter(|category| !category.is_empty())
+        .map(String::from)
+        .collect();
+
+    let mut summary_content: String = "".into();
+    if let Some(summary) = &feed_entry.summary {
+        // This will definitely be somewhat arbitrary with unicode
+        // but we just want to avoid some feeds that stuff their
+        // entire content into the summary field from polluting
+        // the description fied.
+        const MAX_SUMMARY_LENGTH_BYTES: usize = 1000;
+        if summary.content.len() < MAX_SUMMARY_LENGTH_BYTES {
+            summary_content = summary.content.clone();
+        };
+    }
+
+    Ok(Entry {
+        feed_id,
+        feed_entry_id: Some(feed_entry.id.clone()),
+        title,
+        updated,
+        published,
+        authors,
+        summary: summary_content,
+        content,
+        link: feed_entry.links.first().map(|link| link.href.clone()),
+        language: feed_entry.language.clone(),
+        categories,
+    })
+}
+
+/// extract_html_string_from_entry returns the body of an entry as HTML,
+/// falling back to the summary for entries without content or whose content
+/// lives elsewhere. Plain text bodies, like JSON Feed's `content_text` or
+/// Atom's `type="text"`, are turned into paragraphs.
+pub fn extract_html_string_from_entry(
+    entry: &feed_rs::model::Entry,
+) -> Result<String, EntryConversionError> {
+    if let Some(content) = &entry.content {
+        match &content.body {
+            Some(body) => return Ok(body_to_html(body, &content.content_type)),
+            None if entry.summary.is_none() => {
+                return Err(EntryConversionError::BodyExtractionError)
+            }
+            None => (),
+        }
+    }
+
+    match &entry.summary {
+        Some(summary) => Ok(body_to_html(&summary.content, &summary.content_type)),
+        None => Err(EntryConversionError::SummaryExtractionError),
+    }
+}
+
+/// body_to_html turns plain text into paragraphs and leaves everything else
+/// alone.
+fn body_to_html(body: &str, content_type: &impl std::fmt::Display) -> String {
+    // RSS 1.0 descriptions are plain text according to the spec, and
+    // feed-rs treats them as such, but plenty of feeds put HTML in them.
+    if has_mime_type(content_type, "text/plain") && !looks_like_html(body) {
+        html::text_to_paragraphs(body)
+    } else {
+        body.to_string()
+    }
+}
+
+/// has_mime_type compares a content type without its parameters.
+fn has_mime_type(content_type: &impl std::fmt::Display, mime_type: &str) -> bool {
+    content_type
+        .to_string()
+        .split(';')
+        .next()
+        .is_some_and(|essence| essence.trim().eq_ignore_ascii_case(mime_type))
+}
+
+/// Tags that give away HTML in text that claims to be plain.
+const TELLTALE_TAGS: &[&str] = &[
+    "a",
+    "b",
+    "blockquote",
+    "br",
+    "div",
+    "em",
+    "h1",
+    "h2",
+    "h3",
+    "i",
+    "img",
+    "li",
+    "p",
+    "pre",
+    "span",
+    "strong",
+    "ul",
+];
+
+fn looks_like_html(text: &str) -> bool {
+    text.match_indices('<').any(|(i, _)| {
+        let tag = text[i + 1..].trim_start_matches('/');
+        let name_length = tag
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(tag.len());
+        let (name, rest) = tag.split_at(name_length);
+        TELLTALE_TAGS
+            .iter()
+            .any(|telltale| telltale.eq_ignore_ascii_case(name))
+            && rest.starts_with(|c: char| c == '>' || c == '/' || c.is_ascii_whitespace())
+    })
+}
+
+/// title_from_content makes up a title for entries without one, like the
+/// short posts of microblogs, from the start of their text.
+fn title_from_content(content: &str) -> String {
+    const MAX_TITLE_CHARS: usize = 60;
+
+    let mut title = String::new();
+    for word in words(content) {
+        if !title.is_empty() && title.chars().count() + 1 + word.chars().count() > MAX_TITLE_CHARS {
+            title.push('…');
+            return title;
+        }
+        if !title.is_empty() {
+            title.push(' ');
+        }
+        title.push_str(&word);
+    }
+    title
+}
+
+/// words splits the text of an HTML fragment into words.
+fn words(html: &str) -> Vec<String> {
+    html::parse_fragment(html)
+        .iter()
+        .map(|node| match node {
+            html::Node::Text(text) => text.clone(),
+            html::Node::Element(element) => format!(" {} ", element.text_content()),
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .map(String::from)
+        .collect()
+}
+
+const ENTRY_COLUMNS: &str =
+    "feed_id, feed_entry_id, title, updated, published, authors, summary, content, link, language, categories";
+
+fn entry_from_row(r: &rusqlite::Row) -> rusqlite::Result<Entry> {
+    Ok(Entry {
+        feed_id: r.get(0)?,
+        feed_entry_id: r.get(1)?,
+        title: r.get(2)?,
+        updated: r.get(3)?,
+        published: r.get(4)?,
+        authors: list_from_column(r, 5)?,
+        summary: r.get(6)?,
+        content: r.get(7)?,
+        link: r.get(8)?,
+        language: r.get(9)?,
+        categories: list_from_column(r, 10)?,
+    })
+}
+
+/// list_to_column stores a list as a JSON array of strings, so items may
+/// contain any character. Empty lists are stored as NULL.
+fn list_to_column(list: &[String]) -> Option<String> {
+    (!list.is_empty())
+        .then(|| serde_json::to_string(list).expect("a list of strings is always valid JSON"))
+}
+
+fn list_from_column(r: &rusqlite::Row, idx: usize) -> rusqlite::Result<Vec<String>> {
+    let Some(list) = r.get::<_, Option<String>>(idx)? else {
+        return Ok(Vec::new());
+    };
+    serde_json::from_str(&list).map_err(|err| {
+        rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(err))
+    })
+}
+
+impl Storage {
+    pub fn entry_from_db(&self, feed_entry_id: &str) -> Result<Entry, ErrorDBOperation> {
+        let mut statement = self
+            .db
+            .prepare(&format!(
+                "SELECT {ENTRY_COLUMNS} FROM entries WHERE feed_entry_id = ?;"
+            ))
+            .expect("sql query wrong");
+
+        Ok(statement.query_row([feed_entry_id], entry_from_row)?)
+    }
+
+    /// entries_from_db returns the stored entries of a single feed or of all
+    /// feeds, oldest first.
+    pub fn entries_from_db(&self, feed_id: Option<u64>) -> Result<Vec<Entry>, ErrorDBOperation> {
+        let mut statement = self
+            .db
+            .prepare(&format!(
+                "SELECT {ENTRY_COLUMNS} FROM entries WHERE ?1 IS NULL OR feed_id = ?1 ORDER BY id;"
+            ))
+            .expect("sql query wrong");
+
+        let entries = statement
+            .query_map([feed_id], entry_from_row)?
+            .collect::<Result<Vec<Entry>, rusqlite::Error>>()?;
+        Ok(entries)
+    }
+
+    /// full_article_entry_from_db returns the entry the feed stored under the
+    /// given id, if there is one and its full article was fetched.
+    pub fn full_article_entry_from_db(
+        &self,
+        feed_id: u64,
+        feed_entry_id: &str,
+    ) -> Result<Option<Entry>, ErrorDBOperation> {
+        let mut statement = self
+            .db
+            .prepare(&format!(
+                "SELECT {ENTRY_COLUMNS} FROM entries
+                WHERE feed_id = ?1 AND feed_entry_id = ?2 AND article_fetched;"
+            ))
+            .expect("sql query wrong");
+
+        Ok(statement
+            .query_row((feed_id, feed_entry_id), entry_from_row)
+            .optional()?)
+    }
+
+    /// article_fetched_to_db records whether the full article of an entry
+    /// could be fetched, the ones that couldn't are tried again.
+    pub fn article_fetched_to_db(
+        &self,
+        feed_id: u64,
+        feed_entry_id: &str,
+        fetched: bool,
+    ) -> Result<(), ErrorDBOperation> {
+        self.db.execute(
+            "UPDATE entries SET article_fetched = ?3 WHERE feed_id = ?1 AND feed_entry_id = ?2",
+            (feed_id, feed_entry_id, fetched),
+        )?;
+        Ok(())
+    }
+
+    /// entry_position_from_db returns the how manieth entry of its feed the
+    /// entry with the given id is, counting from 1 in the order they were
+    /// first stored.
+    pub fn entry_position_from_db(
+        &self,
+        feed_id: u64,
+        feed_entry_id: &str,
+    ) -> Result<Option<u64>, ErrorDBOperation> {
+        Ok(self
+            .db
+            .query_row(
+                "SELECT COUNT(*) FROM entries WHERE feed_id = ?1 AND id <= (
+                    SELECT id FROM entries WHERE feed_id = ?1 AND feed_entry_id = ?2
+                ) HAVING COUNT(*) > 0",
+                (feed_id, feed_entry_id),
+                |r| r.get(0),
+            )
+            .optional()?)
+    }
+
+    /// pending_entries_from_db returns the entries of the feed that are new or
+    /// updated since their book was last written, oldest first.
+    pub fn pending_entries_from_db(&self, feed_id: u64) -> Result<Vec<Entry>, ErrorDBOperation> {
+        let mut statement = self
+            .db
+            .prepare(&format!(
+                "SELECT {ENTRY_COLUMNS} FROM entries WHERE feed_id = ?1 AND book_pending ORDER BY id;"
+            ))
+            .expect("sql query wrong");
+
+        let entries = statement
+            .query_map([feed_id], entry_from_row)?
+            .collect::<Result<Vec<Entry>, rusqlite::Error>>()?;
+        Ok(entries)
+    }
+
+    /// book_written_to_db records that the book of an entry has been written.
+    pub fn book_written_to_db(
+        &self,
+        feed_id: u64,
+        feed_entry_id: &str,
+    ) -> Result<(), ErrorDBOperation> {
+        self.db.execute(
+            "UPDATE entries SET book_pending = 0 WHERE feed_id = ?1 AND feed_entry_id = ?2",
+            (feed_id, feed_entry_id),
+        )?;
+        Ok(())
+    }
+
+    /// entry_exists_in_db tells whether the feed already has an entry with
+    /// the given id.
+    pub fn entry_exists_in_db(
+        &self,
+        feed_id: u64,
+        feed_entry_id: &str,
+    ) -> Result<bool, ErrorDBOperation> {
+        Ok(self.db.query_row(
+            "SELECT EXISTS (SELECT 1 FROM entries WHERE feed_id = ?1 AND feed_entry_id = ?2)",
+            (feed_id, feed_entry_id),
+            |r| r.get(0),
+        )?)
+    }
+
+    pub fn entry_count_from_db(&self, feed_id: u64) -> Result<u64, ErrorDBOperation> {
+        Ok(self.db.query_row(
+            "SELECT COUNT(*) FROM entries WHERE feed_id = ?1",
+            (feed_id,),
+            |r| r.get(0),
+        )?)
+    }
+
+    /// entry_to_db inserts the entry or updates the one already stored for the
+    /// same feed and entry id, reporting whether anything actually changed.
+    pub fn entry_to_db(&self, feed_entry: &Entry) -> Result<EntryChange, ErrorDBOperation> {
+        let content_hash = feed_entry.content_hash();
+
+        let stored: Option<(Option<String>, Option<String>)> = self
+            .db
+            .query_row(
+                "SELECT updated, content_hash FROM entries WHERE feed_id = ?1 AND feed_entry_id = ?2",
+                (feed_entry.feed_id, &feed_entry.feed_entry_id),
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .optional()?;
+
+        let change = match stored {
+            None => EntryChange::New,
+            Some((updated, hash))
+                if updated == feed_entry.updated && hash.as_deref() == Some(&content_hash) =>
+            {
+                return Ok(EntryChange::Unchanged)
+            }
+            // Rows stored before content hashes existed, their books are up
+            // to date as long as the entry wasn't updated since.
+            Some((updated, None)) if updated == feed_entry.updated => {
+                self.db.execute(
+                    "UPDATE entries SET content_hash = ?3 WHERE feed_id = ?1 AND feed_entry_id = ?2",
+                    (feed_entry.feed_id, &feed_entry.feed_entry_id, &content_hash),
+                )?;
+                return Ok(EntryChange::Unchanged);
+            }
+            Some(_) => EntryChange::Updated,
+        };
+
+        let mut statement = self
+            .db
+            .prepare(
+                "INSERT INTO entries (feed_id, feed_entry_id, title, updated, published, authors, summary, content, link, content_hash, language, categories, book_pending)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, 1)
+                ON CONFLICT (feed_id, feed_entry_id) DO UPDATE SET
+                    title = excluded.title,
+                    updated = excluded.updated,
+                    published = excluded.published,
+                    authors = excluded.authors,
+                    summary = excluded.summary,
+                    content = excluded.content,
+                    link = excluded.link,
+                    content_hash = excluded.content_hash,
+                    language = excluded.language,
+                    categories = excluded.categories,
+                    book_pending = 1",
+            ).expect("SQL syntax error");
+
+        statement.execute((
+            feed_entry.feed_id,
+            &feed_entry.feed_entry_id,
+            &feed_entry.title,
+            &feed_entry.updated,
+            &feed_entry.published,
+            list_to_column(&feed_entry.authors),
+            &feed_entry.summary,
+            &feed_entry.content,
+            &feed_entry.link,
+            &content_hash,
+            &feed_entry.language,
+            list_to_column(&feed_entry.categories),
+        ))?;
+
+        Ok(change)
+    }
+}
+
+/// Digest is a single EPUB that bundles several entries of one feed.
+#[derive(Debug, PartialEq)]
+pub struct Digest {
+    pub id: u64,
+    pub feed_id: u64,
+    pub title: String,
+    pub created: Timestamp,
+}
+
+impl Storage {
+    /// last_digest_from_db returns the most recent digest built for the feed.
+    pub fn last_digest_from_db(&self, feed_id: u64) -> Result<Option<Digest>, ErrorDBOperation> {
+        let mut statement = self
+            .db
+            .prepare(
+                "SELECT id, title, created FROM digests WHERE feed_id = ? ORDER BY created DESC, id DESC LIMIT 1;",
+            )
+            .expect("sql query wrong");
+
+        let digest = statement
+            .query_row([feed_id], |r| {
+                let created: String = r.get(2)?;
+                Ok(Digest {
+                    id: r.get(0)?,
+                    feed_id,
+                    title: r.get(1)?,
+                    created: created
+                        .parse()
+                        .expect("we manage our own timestamps, this row is corrupted"),
+                })
+            })
+            .optional()?;
+
+        Ok(digest)
+    }
+
+    /// digests_from_db returns every digest built for the feed, oldest first.
+    pub fn digests_from_db(&self, feed_id: u64) -> Result<Vec<Digest>, ErrorDBOperation> {
+        let mut statement = self
+            .db
+            .prepare(
+                "SELECT id, title, created FROM digests WHERE feed_id = ? ORDER BY created, id;",
+            )
+            .expect("sql query wrong");
+
+        let digests = statement
+            .query_map([feed_id], |r| {
+                let created: String = r.get(2)?;
+                Ok(Digest {
+                    id: r.get(0)?,
+                    feed_id,
+                    title: r.get(1)?,
+                    created: created
+                        .parse()
+                        .expect("we manage our own timestamps, this row is corrupted"),
+                })
+            })?
+            .collect::<Result<Vec<Digest>, rusqlite::Error>>()?;
+        Ok(digests)
+    }
+
+    /// undigested_entries_from_db returns the entries of the feed that have
+    /// not gone into any digest yet, oldest first, together with their row id
+    /// to hand back to digest_to_db.
+    pub fn undigested_entries_from_db(
+        &self,
+        feed_id: u64,
+    ) -> Result<Vec<(u64, Entry)>, ErrorDBOperation> {
+        self.entries_not_in_from_db(feed_id, "digest_entries")
+    }
+
+    /// digest_entries_from_db returns the entries that went into a digest.
+    pub fn digest_entries_from_db(&self, digest_id: u64) -> Result<Vec<Entry>, ErrorDBOperation> {
+        self.entries_in_from_db("digest_entries", "digest_id", digest_id)
+    }
+
+    fn entries_not_in_from_db(
+        &self,
+        feed_id: u64,
+        table: &str,
+    ) -> Result<Vec<(u64, Entry)>, ErrorDBOperation> {
+        let mut statement = self
+            .db
+            .prepare(&format!(
+                "SELECT {ENTRY_COLUMNS}, id FROM entries
+                WHERE feed_id = ?1 AND id NOT IN (SELECT entry_id FROM {table})
+                ORDER BY id;"
+            ))
+            .expect("sql query wrong");
+
+        let entries = statement
+            .query_map([feed_id], |r| Ok((r.get(11)?, entry_from_row(r)?)))?
+            .collect::<Result<Vec<(u64, Entry)>, rusqlite::Error>>()?;
+        Ok(entries)
+    }
+
+    fn entries_in_from_db(
+        &self,
+        table: &str,
+        column: &str,
+        id: u64,
+    ) -> Result<Vec<Entry>, ErrorDBOperation> {
+        let mut statement = self
+            .db
+            .prepare(&format!(
+                "SELECT {ENTRY_COLUMNS} FROM entries
+                WHERE id IN (SELECT entry_id FROM {table} WHERE {column} = ?1)
+                ORDER BY id;"
+            ))
+            .expect("sql query wrong");
+
+        let entries = statement
+            .query_map([id], entry_from_row)?
+            .collect::<Result<Vec<Entry>, rusqlite::Error>>()?;
+        Ok(entries)
+    }
+
+    /// digest_to_db records a digest and the entries that went into it.
+    pub fn digest_to_db(
+        &self,
+        feed_id: u64,
+        title: &str,
+        created: Timestamp,
+        entry_ids: &[u64],
+    ) -> Result<Digest, ErrorDBOperation> {
+        let tx = self.db.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO digests (feed_id, title, created) VALUES (?1, ?2, ?3)",
+            (feed_id, title, created.to_string()),
+        )?;
+        let id = tx.last_insert_rowid() as u64;
+
+        {
+            let mut statement = tx
+                .prepare("INSERT INTO digest_entries (digest_id, entry_id) VALUES (?1, ?2)")
+                .expect("SQL syntax error");
+            let mut written = tx
+                .prepare("UPDATE entries SET book_pending = 0 WHERE id = ?1")
+                .expect("SQL syntax error");
+            for entry_id in entry_ids {
+                statement.execute((id, entry_id))?;
+                written.execute((entry_id,))?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(Digest {
+            id,
+            feed_id,
+            title: title.into(),
+            created,
+        })
+    }
+}
+
+/// Edition is a newspaper edition combining entries of several feeds.
+#[derive(Debug, PartialEq)]
+pub struct Edition {
+    pub id: u64,
+    pub title: String,
+    pub created: Timestamp,
+}
+
+impl Storage {
+    /// last_edition_from_db returns the most recent newspaper edition.
+    pub fn last_edition_from_db(&self) -> Result<Option<Edition>, ErrorDBOperation> {
+        let mut statement = self
+            .db
+            .prepare(
+                "SELECT id, title, created FROM editions ORDER BY created DESC, id DESC LIMIT 1;",
+            )
+            .expect("sql query wrong");
+
+        let edition = statement
+            .query_row((), |r| {
+                let created: String = r.get(2)?;
+                Ok(Edition {
+                    id: r.get(0)?,
+                    title: r.get(1)?,
+                    created: created
+                        .parse()
+                        .expect("we manage our own timestamps, this row is corrupted"),
+                })
+            })
+            .optional()?;
+
+        Ok(edition)
+    }
+
+    /// editions_from_db returns every newspaper edition, oldest first.
+    pub fn editions_from_db(&self) -> Result<Vec<Edition>, ErrorDBOperation> {
+        let mut statement = self
+            .db
+            .prepare("SELECT id, title, created FROM editions ORDER BY created, id;")
+            .expect("sql query wrong");
+
+        let editions = statement
+            .query_map((), |r| {
+                let created: String = r.get(2)?;
+                Ok(Edition {
+                    id: r.get(0)?,
+                    title: r.get(1)?,
+                    created: created
+                        .parse()
+                        .expect("we manage our own timestamps, this row is corrupted"),
+                })
+            })?
+            .collect::<Result<Vec<Edition>, rusqlite::Error>>()?;
+        Ok(editions)
+    }
+
+    /// undelivered_entries_from_db returns the entries of the feed that have
+    /// not been in any newspaper edition yet, oldest first, together with
+    /// their row id to hand back to edition_to_db.
+    pub fn undelivered_entries_from_db(
+        &self,
+        feed_id: u64,
+    ) -> Result<Vec<(u64, Entry)>, ErrorDBOperation> {
+        self.entries_not_in_from_db(feed_id, "edition_entries")
+    }
+
+    /// edition_entries_from_db returns the entries that went into an edition.
+    pub fn edition_entries_from_db(&self, edition_id: u64) -> Result<Vec<Entry>, ErrorDBOperation> {
+        self.entries_in_from_db("edition_entries", "edition_id", edition_id)
+    }
+
+    /// edition_to_db records an edition and the entries that went into it.
+    pub fn edition_to_db(
+        &self,
+        title: &str,
+        created: Timestamp,
+        entry_ids: &[u64],
+    ) -> Result<Edition, ErrorDBOperation> {
+        let tx = self.db.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO editions (title, created) VALUES (?1, ?2)",
+            (title, created.to_string()),
+        )?;
+        let id = tx.last_insert_rowid() as u64;
+
+        {
+            let mut statement = tx
+                .prepare("INSERT INTO edition_entries (edition_id, entry_id) VALUES (?1, ?2)")
+                .expect("SQL syntax error");
+            let mut written = tx
+                .prepare("UPDATE entries SET book_pending = 0 WHERE id = ?1")
+                .expect("SQL syntax error");
+            for entry_id in entry_ids {
+                statement.execute((id, entry_id))?;
+                written.execute((entry_id,))?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(Edition {
+            id,
+            title: title.into(),
+            created,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feed_to_and_from_db() {
+        let storage = Storage::new_in_memory().expect("failed to open in memory db");
+        storage.init_database().expect("failed to set up test DB");
+        let now = Timestamp::now();
+
+        let feed_stats = FeedStats {
+            id: 1,
+            url: "https://example.com".into(),
+            last_modified: Some("1970-01-01T00:00:00Z".into()),
+            last_fetched: Some(now),
+            etag: Some("foo".into()),
+            moved_to: None,
+            gone_since: None,
+            logo: Some("https://example.com/logo.png".into()),
+        };
+
+        storage
+            .feed_stats_to_db(&feed_stats)
+            .expect("failed to store feed_stats");
+        let db_feed_stats = storage
+            .feed_stats_from_db("https://example.com")
+            .expect("failed to read feed_stats back out of DB");
+
+        if let Some(db_feed_stats) = db_feed_stats {
+            assert_eq!(feed_stats, db_feed_stats);
+        } else {
+            panic!("expected a feed_stats struct back, but got None")
+        };
+
+        let feed_entry = Entry {
+            feed_id: 1,
+            feed_entry_id: Some("foo".into()),
+            title: "bar".into(),
+            updated: Some("baz".into()),
+            published: Some("qux".into()),
+            authors: vec!["Doe, John".into(), "Jane Roe".into()],
+            summary: "some summary".into(),
+            content: "<XML here>".into(),
+            link: Some("https://example.com/foo".into()),
+            language: Some("en-GB".into()),
+            categories: vec!["rust".into(), "books, reading".into()],
+        };
+
+        storage
+            .entry_to_db(&feed_entry)
+            .expect("failed to store feed_entry");
+        let db_feed_entry = storage
+            .entry_from_db("foo")
+            .expect("failed to read feed_entry out of DB");
+
+        assert_eq!(feed_entry, db_feed_entry);
+        assert_eq!(storage.entries_from_db(Some(1)).unwrap(), vec![feed_entry]);
+        assert_eq!(storage.entries_from_db(Some(2)).unwrap(), vec![]);
+        assert_eq!(storage.entry_count_from_db(1).unwrap(), 1);
+    }
+
+    #[test]
+    fn feed_stats_from_db_finds_moved_feeds() {
+        let storage = Storage::new_in_memory().expect("failed to open in memory db");
+        storage.init_database().expect("failed to set up test DB");
+
+        let mut feed_stats = storage
+            .new_feed_stats_to_db("https://old.example.com/feed")
+            .unwrap();
+        feed_stats.moved_to = Some("https://new.example.com/feed".into());
+        feed_stats.gone_since = Some("2025-01-01T00:00:00Z".parse().unwrap());
+        storage.feed_stats_to_db(&feed_stats).unwrap();
+
+        assert_eq!(
+            storage
+                .feed_stats_from_db("https://new.example.com/feed")
+                .unwrap(),
+            Some(feed_stats)
+        );
+        assert_eq!(
+            storage
+                .feed_stats_from_db("https://other.example.com/feed")
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn entry_to_db_deduplicates() {
+        let storage = Storage::new_in_memory().expect("failed to open in memory db");
+        storage.init_database().expect("failed to set up test DB");
+
+        let mut feed_entry = Entry {
+            feed_id: 1,
+            feed_entry_id: Some("foo".into()),
+            title: "bar".into(),
+            updated: Some("2025-01-01T00:00:00+00:00".into()),
+            published: None,
+            authors: Vec::new(),
+            summary: "".into(),
+            content: "<p>first</p>".into(),
+            link: None,
+            language: None,
+            categories: Vec::new(),
+        };
+
+        assert_eq!(storage.entry_to_db(&feed_entry).unwrap(), EntryChange::New);
+        assert_eq!(
+            storage.entry_to_db(&feed_entry).unwrap(),
+            EntryChange::Unchanged
+        );
+
+        feed_entry.content = "<p>fixed a typo</p>".into();
+        assert_eq!(
+            storage.entry_to_db(&feed_entry).unwrap(),
+            EntryChange::Updated
+        );
+
+        feed_entry.updated = Some("2025-01-02T00:00:00+00:00".into());
+        assert_eq!(
+            storage.entry_to_db(&feed_entry).unwrap(),
+            EntryChange::Updated
+        );
+
+        let count: u64 = storage
+            .db
+            .query_row("SELECT COUNT(*) FROM entries", (), |r| r.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(storage.entry_from_db("foo").unwrap(), feed_entry);
+    }
+
+    #[test]
+    fn entry_to_db_keeps_entries_stored_before_content_hashes() {
+        let storage = Storage::new_in_memory().expect("failed to open in memory db");
+        storage
+            .db
+            .execute_batch(include_str!("testdata/baseline.sql"))
+            .unwrap();
+        storage
+            .init_database()
+            .expect("failed to migrate baseline DB");
+
+        let mut feed_entry = storage.entry_from_db("urn:entry:1").unwrap();
+        assert_eq!(
+            storage.entry_to_db(&feed_entry).unwrap(),
+            EntryChange::Unchanged
+        );
+        let content_hash: Option<String> = storage
+            .db
+            .query_row(
+                "SELECT content_hash FROM entries WHERE feed_entry_id = 'urn:entry:1'",
+                (),
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(content_hash, Some(feed_entry.content_hash()));
+
+        // From now on changes are noticed as usual.
+        feed_entry.content = "<p>fixed a typo</p>".into();
+        assert_eq!(
+            storage.entry_to_db(&feed_entry).unwrap(),
+            EntryChange::Updated
+        );
+    }
+
+    #[test]
+    fn entry_position_from_db() {
+        let storage = Storage::new_in_memory().expect("failed to open in memory db");
+        storage.init_database().expect("failed to set up test DB");
+
+        let mut feed_entry = Entry {
+            feed_id: 1,
+            feed_entry_id: Some("first".into()),
+            title: "first".into(),
+            updated: None,
+            published: None,
+            authors: Vec::new(),
+            summary: "".into(),
+            content: "<p>first</p>".into(),
+            link: None,
+            language: None,
+            categories: Vec::new(),
+        };
+        storage.entry_to_db(&feed_entry).unwrap();
+        feed_entry.feed_id = 2;
+        storage.entry_to_db(&feed_entry).unwrap();
+        feed_entry.feed_id = 1;
+        feed_entry.feed_entry_id = Some("second".into());
+        storage.entry_to_db(&feed_entry).unwrap();
+
+        // Updates keep the position of the entry.
+        feed_entry.feed_entry_id = Some("first".into());
+        feed_entry.content = "<p>fixed a typo</p>".into();
+        storage.entry_to_db(&feed_entry).unwrap();
+
+        assert_eq!(storage.entry_position_from_db(1, "first").unwrap(), Some(1));
+        assert_eq!(
+            storage.entry_position_from_db(1, "second").unwrap(),
+            Some(2)
+        );
+        assert_eq!(storage.entry_position_from_db(2, "first").unwrap(), Some(1));
+        assert_eq!(storage.entry_position_from_db(1, "missing").unwrap(), None);
+    }
+
+    #[test]
+    fn backoff_to_and_from_db() {
+        let storage = Storage::new_in_memory().expect("failed to open in memory db");
+        storage.init_database().expect("failed to set up test DB");
+        let feed_stats = storage
+            .new_feed_stats_to_db("https://example.com")
+            .expect("failed to create feed");
+
+        assert_eq!(storage.backoff_from_db(feed_stats.id).unwrap(), None);
+
+        let backoff = Backoff {
+            feed_id: feed_stats.id,
+            blocked_until: "2025-01-01T00:00:00Z".parse().unwrap(),
+            failure_count: 2,
+            last_error: Some("HTTP 503".into()),
+        };
+        storage
+            .backoff_to_db(&backoff)
+            .expect("failed to store backoff");
+        assert_eq!(
+            storage.backoff_from_db(feed_stats.id).unwrap(),
+            Some(backoff)
+        );
+
+        storage
+            .clear_backoff(feed_stats.id)
+            .expect("failed to clear backoff");
+        assert_eq!(storage.backoff_from_db(feed_stats.id).unwrap(), None);
+    }
+
+    #[test]
+    fn digest_to_and_from_db() {
+        let storage = Storage::new_in_memory().expect("failed to open in memory db");
+        storage.init_database().expect("failed to set up test DB");
+        let feed_stats = storage
+            .new_feed_stats_to_db("https://example.com")
+            .expect("failed to create feed");
+
+        for id in ["first", "second"] {
+            let entry = Entry {
+                feed_id: feed_stats.id,
+                feed_entry_id: Some(id.into()),
+                title: id.into(),
+                updated: None,
+                published: None,
+                authors: Vec::new(),
+                summary: "".into(),
+                content: "<p>content</p>".into(),
+                link: None,
+                language: None,
+                categories: Vec::new(),
+            };
+            storage.entry_to_db(&entry).unwrap();
+        }
+
+        assert_eq!(storage.last_digest_from_db(feed_stats.id).unwrap(), None);
+        let undigested = storage.undigested_entries_from_db(feed_stats.id).unwrap();
+        assert_eq!(undigested.len(), 2);
+        assert_eq!(undigested[0].1.title, "first");
+
+        let created: Timestamp = "2025-01-01T08:00:00Z".parse().unwrap();
+        let digest = storage
+            .digest_to_db(
+                feed_stats.id,
+                "example — 2025-01-01",
+                created,
+                &[undigested[0].0],
+            )
+            .expect("failed to store digest");
+        assert_eq!(
+            storage.last_digest_from_db(feed_stats.id).unwrap(),
+            Some(digest)
+        );
+
+        let undigested = storage.undigested_entries_from_db(feed_stats.id).unwrap();
+        assert_eq!(undigested.len(), 1);
+        assert_eq!(undigested[0].1.title, "second");
+
+        // The digest is the book of the entries that went into it.
+        let pending = storage.pending_entries_from_db(feed_stats.id).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].title, "second");
+    }
+
+    #[test]
+    fn pending_entries_from_db() {
+        let storage = Storage::new_in_memory().expect("failed to open in memory db");
+        storage.init_database().expect("failed to set up test DB");
+
+        let mut feed_entry = Entry {
+            feed_id: 1,
+            feed_entry_id: Some("foo".into()),
+            title: "bar".into(),
+            updated: None,
+            published: None,
+            authors: Vec::new(),
+            summary: "".into(),
+            content: "<p>first</p>".into(),
+            link: None,
+            language: None,
+            categories: Vec::new(),
+        };
+        storage.entry_to_db(&feed_entry).unwrap();
+        assert_eq!(storage.pending_entries_from_db(1).unwrap().len(), 1);
+
+        // Until the book is written it stays pending, even if the feed
+        // doesn't change it.
+        storage.entry_to_db(&feed_entry).unwrap();
+        assert_eq!(storage.pending_entries_from_db(1).unwrap().len(), 1);
+        storage.book_written_to_db(1, "foo").unwrap();
+        assert!(storage.pending_entries_from_db(1).unwrap().is_empty());
+
+        feed_entry.content = "<p>fixed a typo</p>".into();
+        storage.entry_to_db(&feed_entry).unwrap();
+        assert_eq!(storage.pending_entries_from_db(1).unwrap(), [feed_entry]);
+    }
+}
@@ -0,0 +1,31 @@
+-- A database as created by feed-to-epub 0.7.0, before schema migrations
+-- existed. Every poll inserted the same entries again.
+CREATE TABLE IF NOT EXISTS feeds (
+    id INTEGER PRIMARY KEY,
+    feed_url TEXT NOT NULL,
+    last_modified TEXT,
+    last_fetched TEXT,
+    etag TEXT
+);
+
+CREATE TABLE IF NOT EXISTS entries (
+    id INTEGER PRIMARY KEY,
+    feed_id INTEGER NOT NULL,
+    feed_entry_id TEXT,
+    title TEXT,
+    updated TEXT,
+    authors TEXT,
+    summary TEXT,
+    content BLOB NOT NULL,
+    FOREIGN KEY(feed_id) REFERENCES feeds(id)
+);
+
+INSERT INTO feeds (id, feed_url, last_modified, last_fetched, etag)
+VALUES (1, 'https://example.com/atom.xml', 'Wed, 01 Jan 2025 00:00:00 GMT', '2025-01-01T00:00:00Z', '"v1"');
+
+INSERT INTO entries (id, feed_id, feed_entry_id, title, updated, authors, summary, content)
+VALUES
+    (1, 1, 'urn:entry:1', 'First', '2025-01-01T00:00:00+00:00', 'Jane Doe', '', '<p>first</p>'),
+    (2, 1, 'urn:entry:1', 'First', '2025-01-01T00:00:00+00:00', 'Jane Doe', '', '<p>first</p>'),
+    (3, 1, 'urn:entry:1', 'First', '2025-01-01T00:00:00+00:00', 'Jane Doe', '', '<p>first</p>'),
+    (4, 1, 'urn:entry:2', 'Second', '2025-01-02T00:00:00+00:00', 'Jane Doe', '', '<p>second</p>');
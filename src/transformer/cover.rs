@@ -0,0 +1,376 @@
+//! Covers for the books we write, e-readers show them in their library and
+//! a book without one is just a blank tile. The logo of the feed or the
+//! first image of the content make the cover if they are big enough, any
+//! other book gets its title set in type.
+
+use super::images::{self, EmbeddedImage, Image, ImageFetcher};
+use crate::html::{self, Element, Node};
+use jiff::Timestamp;
+use std::rc::Rc;
+use url::Url;
+
+/// FONT_FAMILY is what covers are set in. Embedding a font would add more
+/// to every book than the rest of it, so covers make do with the sans serif
+/// the e-reader has.
+const FONT_FAMILY: &str = "\"Open Sans\", \"Helvetica Neue\", Arial, sans-serif";
+
+/// Images smaller than this on either side, like favicons and avatars, look
+/// worse as a cover than a generated one.
+const MIN_COVER_PIXELS: u32 = 200;
+
+/// Generated covers have the 2:3 aspect ratio of most e-reader screens.
+const WIDTH: f64 = 1200.0;
+const HEIGHT: f64 = 1800.0;
+const MARGIN: f64 = 100.0;
+const LINE_HEIGHT: f64 = 1.2;
+
+/// Title sizes from largest to smallest, the largest one the title fits
+/// into is used.
+const TITLE_SIZES: &[f64] = &[140.0, 120.0, 100.0, 86.0, 72.0, 60.0];
+
+/// Background colours of generated covers, books of the same feed share
+/// one so they are easy to tell apart from other feeds.
+const PALETTE: &[&str] = &[
+    "#1d3557", "#6a040f", "#2d6a4f", "#3c096c", "#7f4f24", "#264653", "#5e503f", "#343a40",
+];
+
+/// CoverText is what a generated cover shows.
+pub struct CoverText<'a> {
+    /// kicker is set in small capitals above the title, usually it is the
+    /// name of the feed.
+    pub kicker: &'a str,
+    pub title: &'a str,
+    pub date: Option<Timestamp>,
+}
+
+/// choose_cover picks the cover of a book: the feed's logo, else the first
+/// of `images` big enough to be one, else a generated cover. A cover taken
+/// from `images` keeps its path, the image is only stored once.
+pub fn choose_cover(
+    logo: Option<&str>,
+    images: &[EmbeddedImage],
+    image_fetcher: Option<&ImageFetcher>,
+    text: &CoverText,
+) -> EmbeddedImage {
+    let logo = image_fetcher
+        .zip(logo.and_then(|logo| Url::parse(logo).ok()))
+        .and_then(|(image_fetcher, logo)| image_fetcher.cover_image(&logo))
+        .filter(|image| is_cover_sized(image));
+    if let Some(image) = logo {
+        return EmbeddedImage {
+            path: format!("cover.{}", images::extension(image.mime_type)),
+            image,
+        };
+    }
+
+    if let Some(image) = images.iter().find(|image| is_cover_sized(&image.image)) {
+        return image.clone();
+    }
+
+    EmbeddedImage {
+        path: "cover.svg".into(),
+        image: Rc::new(generate(text)),
+    }
+}
+
+/// is_cover_sized also accepts images we can't tell the size of, like SVG.
+fn is_cover_sized(image: &Image) -> bool {
+    images::image_dimensions(&image.data)
+        .is_none_or(|(width, height)| width.min(height) >= MIN_COVER_PIXELS)
+}
+
+/// Font is a font size and weight of the cover text.
+struct Font {
+    size: f64,
+    bold: bool,
+    letter_spacing: f64,
+}
+
+impl Font {
+    /// width estimates how wide `text` is set in this font. Covers only need
+    /// to keep their text inside the margins and we don't know the font the
+    /// e-reader picks anyway, so a rough guess at the widths of a sans serif
+    /// is all there is.
+    fn width(&self, text: &str) -> f64 {
+        text.chars()
+            .map(|c| {
+                let ems = match c {
+                    ' ' => 0.26,
+                    'i' | 'j' | 'l' | 'I' | '.' | ',' | ':' | ';' | '\'' | '!' | '|' => 0.27,
+                    'f' | 'r' | 't' | '(' | ')' | '[' | ']' | '-' | '"' => 0.36,
+                    'm' | 'w' | 'M' | 'W' | '@' | '—' => 0.86,
+                    c if c.is_uppercase() => 0.66,
+                    _ => 0.57,
+                };
+                let ems = if self.bold { ems * 1.06 } else { ems };
+                ems * self.size + self.letter_spacing
+            })
+            .sum()
+    }
+
+    fn line_height(&self) -> f64 {
+        self.size * LINE_HEIGHT
+    }
+}
+
+/// generate sets the text of a cover in a sans serif, on a background
+/// colour that depends on the kicker.
+pub fn generate(text: &CoverText) -> Image {
+    let kicker_font = Font {
+        size: 44.0,
+        bold: false,
+        letter_spacing: 6.0,
+    };
+    let date_font = Font {
+        size: 44.0,
+        bold: false,
+        letter_spacing: 0.0,
+    };
+
+    let background = PALETTE[text.kicker.bytes().map(usize::from).sum::<usize>() % PALETTE.len()];
+    let mut nodes = vec![
+        Node::Element(svg_element("title", &[], text.title)),
+        Node::Element(svg_element(
+            "style",
+            &[],
+            &format!("text {{ font-family: {FONT_FAMILY}; fill: #ffffff; }}"),
+        )),
+        Node::Element(svg_element(
+            "rect",
+            &[("width", "100%"), ("height", "100%"), ("fill", background)],
+            "",
+        )),
+    ];
+
+    let mut y = MARGIN;
+    let kicker = truncate(
+        wrap(&text.kicker.to_uppercase(), &kicker_font),
+        2,
+        &kicker_font,
+    );
+    for line in &kicker {
+        y += kicker_font.line_height();
+        nodes.push(Node::Element(text_line(line, y, &kicker_font)));
+    }
+    if !kicker.is_empty() {
+        y += 50.0;
+        nodes.push(Node::Element(svg_element(
+            "rect",
+            &[
+                ("x", &MARGIN.to_string()),
+                ("y", &y.to_string()),
+                ("width", "240"),
+                ("height", "10"),
+                ("fill", "#ffffff"),
+                ("fill-opacity", "0.6"),
+            ],
+            "",
+        )));
+    }
+
+    // The title takes whatever room is left between the kicker and the date.
+    let title_top = y + 100.0;
+    let title_bottom = HEIGHT - MARGIN - date_font.line_height() - 100.0;
+    let (title_font, title) = TITLE_SIZES
+        .iter()
+        .map(|&size| {
+            let font = Font {
+                size,
+                bold: true,
+                letter_spacing: 0.0,
+            };
+            let lines = wrap(text.title, &font);
+            (font, lines)
+        })
+        .find(|(font, lines)| lines.len() as f64 * font.line_height() <= title_bottom - title_top)
+        .unwrap_or_else(|| {
+            let font = Font {
+                size: TITLE_SIZES[TITLE_SIZES.len() - 1],
+                bold: true,
+                letter_spacing: 0.0,
+            };
+            let max_lines = ((title_bottom - title_top) / font.line_height()) as usize;
+            let lines = truncate(wrap(text.title, &font), max_lines, &font);
+            (font, lines)
+        });
+    let mut y = title_top;
+    for line in &title {
+        y += title_font.line_height();
+        nodes.push(Node::Element(text_line(line, y, &title_font)));
+    }
+
+    if let Some(date) = text.date {
+        let date = super::format_date(date);
+        nodes.push(Node::Element(text_line(&date, HEIGHT - MARGIN, &date_font)));
+    }
+
+    let mut svg = svg_element(
+        "svg",
+        &[
+            ("xmlns", "http://www.w3.org/2000/svg"),
+            ("width", &WIDTH.to_string()),
+            ("height", &HEIGHT.to_string()),
+            ("viewBox", &format!("0 0 {WIDTH} {HEIGHT}")),
+        ],
+        "",
+    );
+    svg.children = nodes;
+
+    let mut data = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    data.push_str(&html::to_xhtml(&[Node::Element(svg)]));
+    Image {
+        mime_type: "image/svg+xml",
+        data: data.into_bytes(),
+    }
+}
+
+fn svg_element(name: &str, attrs: &[(&str, &str)], text: &str) -> Element {
+    let mut element = Element::new(name);
+    for (name, value) in attrs {
+        element.set_attr(name, value);
+    }
+    if !text.is_empty() {
+        element.children.push(Node::Text(text.into()));
+    }
+    element
+}
+
+fn text_line(line: &str, y: f64, font: &Font) -> Element {
+    svg_element(
+        "text",
+        &[
+            ("x", &MARGIN.to_string()),
+            ("y", &y.to_string()),
+            ("font-size", &font.size.to_string()),
+            ("font-weight", if font.bold { "700" } else { "400" }),
+            ("letter-spacing", &font.letter_spacing.to_string()),
+        ],
+        line,
+    )
+}
+
+/// wrap breaks text into lines that fit between the margins, words too long
+/// for a line of their own, like URLs, are broken anywhere.
+fn wrap(text: &str, font: &Font) -> Vec<String> {
+    let max_width = WIDTH - 2.0 * MARGIN;
+
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let candidate = match line.is_empty() {
+            true => word.to_string(),
+            false => format!("{line} {word}"),
+        };
+        if line.is_empty() || font.width(&candidate) <= max_width {
+            line = candidate;
+        } else {
+            lines.push(std::mem::replace(&mut line, word.to_string()));
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    let mut broken = Vec::new();
+    for line in lines {
+        let mut part = String::new();
+        for c in line.chars() {
+            if !part.is_empty() && font.width(&format!("{part}{c}")) > max_width {
+                broken.push(std::mem::take(&mut part));
+            }
+            part.push(c);
+        }
+        broken.push(part);
+    }
+    broken
+}
+
+/// truncate keeps the first `max_lines` lines, ending the last one with an
+/// ellipsis if anything was cut.
+fn truncate(mut lines: Vec<String>, max_lines: usize, font: &Font) -> Vec<String> {
+    if lines.len() <= max_lines {
+        return lines;
+    }
+
+    lines.truncate(max_lines);
+    if let Some(last) = lines.last_mut() {
+        while !last.is_empty() && font.width(&format!("{last}…")) > WIDTH - 2.0 * MARGIN {
+            last.pop();
+        }
+        *last = format!("{}…", last.trim_end());
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_cover() {
+        let cover = generate(&CoverText {
+            kicker: "lwn",
+            title: "A <very> long title about the state of the Linux kernel & everything around it in 2025",
+            date: Some("2025-01-13T12:00:00Z".parse().unwrap()),
+        });
+        assert_eq!(cover.mime_type, "image/svg+xml");
+
+        let svg = String::from_utf8(cover.data).unwrap();
+        let mut reader = quick_xml::Reader::from_str(&svg);
+        reader.config_mut().check_end_names = true;
+        let mut lines = Vec::new();
+        let mut in_text = false;
+        loop {
+            match reader.read_event() {
+                Ok(quick_xml::events::Event::Eof) => break,
+                Ok(quick_xml::events::Event::Start(tag)) => {
+                    in_text = tag.name().as_ref() == b"text"
+                }
+                Ok(quick_xml::events::Event::Text(text)) if in_text => {
+                    lines.push(text.unescape().unwrap().into_owned())
+                }
+                Ok(_) => (),
+                Err(err) => panic!("not well-formed XML: {err}\n{svg}"),
+            }
+        }
+
+        assert_eq!(lines[0], "LWN");
+        assert_eq!(
+            lines[1..lines.len() - 1].join(" "),
+            "A <very> long title about the state of the Linux kernel & everything around it in 2025"
+        );
+        assert!(lines.len() > 3, "the title should be wrapped: {lines:?}");
+        assert!(lines.last().unwrap().ends_with(" 2025"));
+        // No fonts are embedded, the cover is just the text.
+        assert!(svg.len() < 4096, "{} bytes", svg.len());
+    }
+
+    #[test]
+    fn wrap_and_truncate() {
+        let font = Font {
+            size: 100.0,
+            bold: true,
+            letter_spacing: 0.0,
+        };
+        let lines = wrap(
+            "https://example.com/a/very/long/url/that/does/not/fit/on/one/line",
+            &font,
+        );
+        assert!(lines.len() > 1);
+        assert_eq!(
+            lines.concat(),
+            "https://example.com/a/very/long/url/that/does/not/fit/on/one/line"
+        );
+
+        let lines = truncate(
+            wrap(
+                "one two three four five six seven eight nine ten eleven twelve",
+                &font,
+            ),
+            2,
+            &font,
+        );
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].ends_with('…'));
+    }
+}
@@ -0,0 +1,188 @@
+//! Digests bundle every new entry of a feed into one EPUB per day or week
+//! instead of creating a book per entry.
+
+use super::images::ImageFetcher;
+use super::style::Style;
+use crate::feed_reader::config::Feed;
+use crate::feed_reader::schedule;
+use crate::storage::{Digest, ErrorDBOperation, Storage};
+use jiff::tz::TimeZone;
+use jiff::Timestamp;
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DigestError {
+    #[error("storage error: {0}")]
+    StorageError(#[from] ErrorDBOperation),
+    #[error("could not create download directory: {0}")]
+    DownloadDirError(#[from] std::io::Error),
+    #[error("failed to build digest EPUB: {0}")]
+    EpubError(#[from] super::Error),
+}
+
+/// digest_title names a digest after its feed and the day it was built.
+pub fn digest_title(feed_name: &str, date: jiff::civil::Date) -> String {
+    format!("{feed_name} — {date}")
+}
+
+/// build_due_digest builds the next digest of the feed if its schedule says
+/// one is due and there is anything new since the last one. It returns the
+/// path of the new EPUB, or None if there was nothing to do.
+pub fn build_due_digest(
+    storage: &Storage,
+    feed_name: &str,
+    feed: &Feed,
+    now: Timestamp,
+    style: &Style,
+    image_fetcher: Option<&ImageFetcher>,
+) -> Result<Option<PathBuf>, DigestError> {
+    let Some(feed_stats) = storage.feed_stats_from_db(&feed.url)? else {
+        return Ok(None);
+    };
+
+    let tz = TimeZone::system();
+    let today = now.to_zoned(tz.clone()).date();
+    let last_digest = storage
+        .last_digest_from_db(feed_stats.id)?
+        .map(|digest| digest.created.to_zoned(tz.clone()).date());
+    if !schedule::digest_due(last_digest, today, feed.digest_schedule) {
+        return Ok(None);
+    }
+
+    let (entry_ids, entries): (Vec<u64>, Vec<_>) = storage
+        .undigested_entries_from_db(feed_stats.id)?
+        .into_iter()
+        .unzip();
+    if entries.is_empty() {
+        return Ok(None);
+    }
+
+    std::fs::create_dir_all(&feed.download_dir)?;
+    let title = digest_title(feed_name, today);
+    let file_name = super::digest_to_epub(
+        feed_name,
+        &feed.download_dir,
+        &title,
+        &entries,
+        feed_stats.logo.as_deref(),
+        style,
+        image_fetcher,
+    )?;
+    storage.digest_to_db(feed_stats.id, &title, now, &entry_ids)?;
+
+    Ok(Some(file_name))
+}
+
+/// rebuild_digest writes the EPUB of a digest built before again, with the
+/// entries that went into it and its original title.
+pub fn rebuild_digest(
+    storage: &Storage,
+    feed_name: &str,
+    feed: &Feed,
+    digest: &Digest,
+    style: &Style,
+    image_fetcher: Option<&ImageFetcher>,
+) -> Result<PathBuf, DigestError> {
+    let logo = storage
+        .feed_stats_from_db(&feed.url)?
+        .and_then(|feed_stats| feed_stats.logo);
+    let entries = storage.digest_entries_from_db(digest.id)?;
+
+    std::fs::create_dir_all(&feed.download_dir)?;
+    Ok(super::digest_to_epub(
+        feed_name,
+        &feed.download_dir,
+        &digest.title,
+        &entries,
+        logo.as_deref(),
+        style,
+        image_fetcher,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed_reader::config::Config;
+    use crate::transformer::store_test_entry;
+
+    #[test]
+    fn build_due_digest_bundles_new_entries() {
+        let download_dir = tempfile::tempdir().unwrap();
+        let config = Config::from_reader(
+            format!(
+                "
+[feeds.example]
+url = \"https://example.com/atom.xml\"
+download_dir = \"{}\"
+output_mode = \"digest\"
+",
+                download_dir.path().display()
+            )
+            .as_bytes(),
+        )
+        .expect("failed to parse configuration");
+        let feed = &config.feeds["example"];
+
+        let storage = Storage::new_in_memory().expect("failed to open in memory db");
+        storage.init_database().expect("failed to set up test DB");
+        let feed_stats = storage.new_feed_stats_to_db(&feed.url).unwrap();
+        store_test_entry(&storage, &feed.url, "First");
+        store_test_entry(&storage, &feed.url, "Second");
+
+        let now: Timestamp = "2026-10-16T12:00:00Z".parse().unwrap();
+        let today = now.to_zoned(TimeZone::system()).date();
+        let file_name = build_due_digest(&storage, "example", feed, now, &Style::default(), None)
+            .expect("failed to build digest")
+            .expect("expected a digest to be built");
+        assert_eq!(
+            file_name,
+            download_dir
+                .path()
+                .join(format!("{}.epub", digest_title("example", today)))
+        );
+        assert!(file_name.exists());
+        assert!(storage
+            .undigested_entries_from_db(feed_stats.id)
+            .unwrap()
+            .is_empty());
+
+        // Still the same day, Third has to wait for tomorrow's digest.
+        store_test_entry(&storage, &feed.url, "Third");
+        assert_eq!(
+            build_due_digest(&storage, "example", feed, now, &Style::default(), None).unwrap(),
+            None
+        );
+
+        // The next day only the entry that was left over goes in.
+        let tomorrow = now
+            .saturating_add(jiff::SignedDuration::from_hours(24))
+            .unwrap();
+        assert!(
+            build_due_digest(&storage, "example", feed, tomorrow, &Style::default(), None)
+                .unwrap()
+                .is_some()
+        );
+        let last_digest = storage.last_digest_from_db(feed_stats.id).unwrap().unwrap();
+        let digested = storage.digest_entries_from_db(last_digest.id).unwrap();
+        assert_eq!(digested.len(), 1);
+        assert_eq!(digested[0].title, "Third");
+
+        std::fs::remove_file(&file_name).unwrap();
+        let first_digest = &storage.digests_from_db(feed_stats.id).unwrap()[0];
+        assert_eq!(
+            rebuild_digest(
+                &storage,
+                "example",
+                feed,
+                first_digest,
+                &Style::default(),
+                None
+            )
+            .expect("failed to rebuild digest"),
+            file_name
+        );
+        assert!(file_name.exists());
+    }
+}
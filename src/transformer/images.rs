@@ -0,0 +1,608 @@
+//! Downloads the images an entry references so they can be embedded into the
+//! EPUB, e-readers are offline more often than not.
+
+use crate::feed_reader::config::ImageConfig;
+use crate::html::{Element, Node};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::rc::Rc;
+use thiserror::Error;
+use url::Url;
+
+/// The cache holds on to image bytes across entries, feeds love to repeat
+/// the same author avatar or banner in every post.
+const MAX_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Hosts that only ever serve tracking pixels.
+const TRACKING_HOSTS: &[&str] = &[
+    "feeds.feedburner.com",
+    "feedpress.me",
+    "pixel.wp.com",
+    "stats.wordpress.com",
+    "www.google-analytics.com",
+    "pixel.quantserve.com",
+];
+
+#[derive(Error, Debug)]
+pub enum FetchImageError {
+    #[error("failed to execute HTTP request: {0}")]
+    HTTPError(#[from] Box<ureq::Error>),
+    #[error("failed to read image body: {0}")]
+    ReadError(#[from] std::io::Error),
+    #[error("image is larger than the configured limit of {0} bytes")]
+    TooLarge(u64),
+    #[error("response is not an image we can embed")]
+    NotAnImage,
+    #[error("image is a tracking pixel")]
+    TrackingPixel,
+}
+
+impl FetchImageError {
+    /// is_permanent tells whether trying again later would end the same way,
+    /// timeouts and server errors are worth another try.
+    fn is_permanent(&self) -> bool {
+        match self {
+            FetchImageError::HTTPError(err) => {
+                matches!(**err, ureq::Error::Status(400..=499, _))
+            }
+            FetchImageError::ReadError(_) => false,
+            FetchImageError::TooLarge(_)
+            | FetchImageError::NotAnImage
+            | FetchImageError::TrackingPixel => true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Image {
+    pub mime_type: &'static str,
+    pub data: Vec<u8>,
+}
+
+/// EmbeddedImage is an image that an entry's XHTML now references by its
+/// path inside the EPUB.
+#[derive(Debug, Clone)]
+pub struct EmbeddedImage {
+    pub path: String,
+    pub image: Rc<Image>,
+}
+
+#[derive(Clone)]
+enum CachedImage {
+    Image(Rc<Image>),
+    TrackingPixel,
+    Failed,
+}
+
+#[derive(Default)]
+struct ImageCache {
+    images: HashMap<String, CachedImage>,
+    order: VecDeque<String>,
+    bytes: usize,
+}
+
+impl ImageCache {
+    fn insert(&mut self, url: String, image: CachedImage) {
+        self.bytes += cached_bytes(&url, &image);
+        if let Some(replaced) = self.images.insert(url.clone(), image) {
+            self.bytes -= cached_bytes(&url, &replaced);
+            self.order.retain(|cached| *cached != url);
+        }
+        self.order.push_back(url);
+
+        while self.bytes > MAX_CACHE_BYTES {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(image) = self.images.remove(&oldest) {
+                self.bytes -= cached_bytes(&oldest, &image);
+            }
+        }
+    }
+}
+
+/// cached_bytes is what an image takes up in the cache, for images we don't
+/// have that is at least their URL.
+fn cached_bytes(url: &str, image: &CachedImage) -> usize {
+    match image {
+        CachedImage::Image(image) => url.len() + image.data.len(),
+        CachedImage::TrackingPixel | CachedImage::Failed => url.len(),
+    }
+}
+
+pub struct ImageFetcher {
+    agent: ureq::Agent,
+    config: ImageConfig,
+    cache: RefCell<ImageCache>,
+}
+
+impl ImageFetcher {
+    pub fn new(agent: ureq::Agent, config: ImageConfig) -> Self {
+        ImageFetcher {
+            agent,
+            config,
+            cache: RefCell::new(ImageCache::default()),
+        }
+    }
+
+    /// embed_images downloads the images referenced by `nodes`, points their
+    /// `src` at the copy inside the EPUB and returns the images to add as
+    /// resources. Tracking pixels are removed, images we fail to download
+    /// keep pointing at the web. Image paths start with `path_prefix` so
+    /// several entries can share one EPUB.
+    pub fn embed_images(
+        &self,
+        nodes: &mut Vec<Node>,
+        base_url: Option<&Url>,
+        path_prefix: &str,
+    ) -> Vec<EmbeddedImage> {
+        let mut embedded: Vec<EmbeddedImage> = Vec::new();
+        let mut paths: HashMap<String, String> = HashMap::new();
+        self.embed_into(nodes, base_url, path_prefix, &mut embedded, &mut paths);
+        embedded
+    }
+
+    fn embed_into(
+        &self,
+        nodes: &mut Vec<Node>,
+        base_url: Option<&Url>,
+        path_prefix: &str,
+        embedded: &mut Vec<EmbeddedImage>,
+        paths: &mut HashMap<String, String>,
+    ) {
+        nodes.retain_mut(|node| {
+            let Node::Element(element) = node else {
+                return true;
+            };
+
+            if element.name != "img" {
+                self.embed_into(
+                    &mut element.children,
+                    base_url,
+                    path_prefix,
+                    embedded,
+                    paths,
+                );
+                return true;
+            }
+
+            let source = match image_url(element, base_url) {
+                Some(url) => {
+                    if is_tracking_pixel(element, &url) {
+                        return false;
+                    }
+                    ImageSource::Web(url)
+                }
+                None => match element.attr("src").and_then(data_uri) {
+                    // Mail inlines its images, they need no download but
+                    // still have to become resources of the book.
+                    Some(data_uri) => ImageSource::Inline(data_uri.to_string()),
+                    None => return true,
+                },
+            };
+            let key = match &source {
+                ImageSource::Web(url) => url.to_string(),
+                ImageSource::Inline(data_uri) => data_uri.clone(),
+            };
+
+            let path = match paths.get(&key) {
+                Some(path) => path.clone(),
+                None => {
+                    if embedded.len() >= self.config.max_per_entry {
+                        return true;
+                    }
+
+                    let image = match &source {
+                        ImageSource::Web(url) => self.fetch(url),
+                        ImageSource::Inline(data_uri) => self.decode_data_uri(data_uri),
+                    };
+                    match image {
+                        CachedImage::Image(image) => {
+                            let path = format!(
+                                "{path_prefix}{}.{}",
+                                embedded.len(),
+                                extension(image.mime_type)
+                            );
+                            paths.insert(key, path.clone());
+                            embedded.push(EmbeddedImage {
+                                path: path.clone(),
+                                image,
+                            });
+                            path
+                        }
+                        CachedImage::TrackingPixel => return false,
+                        CachedImage::Failed => return true,
+                    }
+                }
+            };
+
+            element.set_attr("src", &path);
+            // The alternatives would still point at the web.
+            element.remove_attr("srcset");
+            element.remove_attr("sizes");
+            true
+        });
+    }
+
+    /// fetch returns the image behind `url` from the cache or downloads it,
+    /// failures are logged. Permanent failures are cached as well so we
+    /// don't retry them for every entry, others are tried again next time.
+    fn fetch(&self, url: &Url) -> CachedImage {
+        if let Some(image) = self.cache.borrow().images.get(url.as_str()) {
+            return image.clone();
+        }
+
+        let image = match self.download(url) {
+            Ok(image) => CachedImage::Image(Rc::new(image)),
+            Err(FetchImageError::TrackingPixel) => CachedImage::TrackingPixel,
+            Err(err) if !err.is_permanent() => {
+                eprintln!("failed to embed image {url}, trying again next time: {err}");
+                return CachedImage::Failed;
+            }
+            Err(err) => {
+                eprintln!("failed to embed image {url}: {err}");
+                CachedImage::Failed
+            }
+        };
+
+        self.cache
+            .borrow_mut()
+            .insert(url.to_string(), image.clone());
+        image
+    }
+
+    /// decode_data_uri turns a base64 `data:` URI into an image, with the
+    /// same limits as images we download.
+    fn decode_data_uri(&self, data_uri: &str) -> CachedImage {
+        let Some((meta, data)) = data_uri
+            .strip_prefix("data:")
+            .and_then(|rest| rest.split_once(','))
+        else {
+            return CachedImage::Failed;
+        };
+        if !meta.ends_with(";base64") {
+            return CachedImage::Failed;
+        }
+        let Ok(data) = BASE64_STANDARD.decode(data.trim()) else {
+            return CachedImage::Failed;
+        };
+        if data.len() as u64 > self.config.max_bytes {
+            return CachedImage::Failed;
+        }
+
+        // Lazy loading placeholders are 1x1 pixels as well.
+        if image_dimensions(&data).is_some_and(|(w, h)| w <= 1 && h <= 1) {
+            return CachedImage::TrackingPixel;
+        }
+
+        match sniff_mime_type(&data) {
+            Some(mime_type) => CachedImage::Image(Rc::new(Image { mime_type, data })),
+            None => CachedImage::Failed,
+        }
+    }
+
+    /// cover_image downloads the image behind `url` for the cover of a book,
+    /// going through the cache since every book of a feed shares its logo.
+    pub fn cover_image(&self, url: &Url) -> Option<Rc<Image>> {
+        match self.fetch(url) {
+            CachedImage::Image(image) => Some(image),
+            CachedImage::TrackingPixel | CachedImage::Failed => None,
+        }
+    }
+
+    pub fn download(&self, url: &Url) -> Result<Image, FetchImageError> {
+        let response = self.agent.get(url.as_str()).call().map_err(Box::new)?;
+
+        if let Some(length) = response
+            .header("Content-Length")
+            .and_then(|l| l.parse::<u64>().ok())
+        {
+            if length > self.config.max_bytes {
+                return Err(FetchImageError::TooLarge(self.config.max_bytes));
+            }
+        }
+
+        let mut data = Vec::new();
+        response
+            .into_reader()
+            .take(self.config.max_bytes + 1)
+            .read_to_end(&mut data)?;
+        if data.len() as u64 > self.config.max_bytes {
+            return Err(FetchImageError::TooLarge(self.config.max_bytes));
+        }
+
+        // Servers lie about content types all the time, so only the magic
+        // bytes count. That also keeps out SVG, which may carry scripts.
+        let mime_type = sniff_mime_type(&data).ok_or(FetchImageError::NotAnImage)?;
+
+        if image_dimensions(&data).is_some_and(|(w, h)| w <= 1 && h <= 1) {
+            return Err(FetchImageError::TrackingPixel);
+        }
+
+        Ok(Image { mime_type, data })
+    }
+}
+
+/// ImageSource is where the bytes of an `<img>` come from.
+enum ImageSource {
+    Web(Url),
+    Inline(String),
+}
+
+/// data_uri returns `src` if it is a `data:` URI.
+fn data_uri(src: &str) -> Option<&str> {
+    let src = src.trim();
+    src.starts_with("data:").then_some(src)
+}
+
+/// image_url picks the URL of an image, lazy loading plugins like to put a
+/// placeholder into `src` and the real image somewhere else.
+fn image_url(element: &Element, base_url: Option<&Url>) -> Option<Url> {
+    let src = ["data-src", "data-lazy-src", "src"]
+        .iter()
+        .filter_map(|attr| element.attr(attr))
+        .map(str::trim)
+        .find(|src| !src.is_empty() && !src.starts_with("data:"))?;
+
+    let url = match base_url {
+        Some(base_url) => base_url.join(src).ok()?,
+        None => Url::parse(src).ok()?,
+    };
+
+    match url.scheme() {
+        "http" | "https" => Some(url),
+        _ => None,
+    }
+}
+
+fn is_tracking_host(url: &Url) -> bool {
+    url.host_str()
+        .is_some_and(|host| TRACKING_HOSTS.contains(&host))
+}
+
+fn is_tracking_pixel(element: &Element, url: &Url) -> bool {
+    let tiny = |attr| {
+        element
+            .attr(attr)
+            .and_then(|v| v.trim_end_matches("px").trim().parse::<u32>().ok())
+            .is_some_and(|v| v <= 1)
+    };
+
+    (tiny("width") && tiny("height")) || is_tracking_host(url)
+}
+
+/// sniff_mime_type recognizes the raster image types EPUB readers are
+/// required to support by their magic bytes.
+fn sniff_mime_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+pub fn extension(mime_type: &str) -> &'static str {
+    match mime_type {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        _ => "bin",
+    }
+}
+
+/// image_dimensions reads width and height from PNG, GIF and JPEG headers,
+/// which is enough to spot the 1x1 pixels trackers use and images too small
+/// for a cover.
+pub fn image_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    match sniff_mime_type(data)? {
+        "image/png" if data.len() >= 24 => Some((
+            u32::from_be_bytes(data[16..20].try_into().ok()?),
+            u32::from_be_bytes(data[20..24].try_into().ok()?),
+        )),
+        "image/gif" if data.len() >= 10 => Some((
+            u32::from(u16::from_le_bytes(data[6..8].try_into().ok()?)),
+            u32::from(u16::from_le_bytes(data[8..10].try_into().ok()?)),
+        )),
+        "image/jpeg" => jpeg_dimensions(data),
+        _ => None,
+    }
+}
+
+/// jpeg_dimensions walks the segments of a JPEG up to its start of frame,
+/// which holds the dimensions.
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let mut i = 2;
+    while i + 9 <= data.len() {
+        if data[i] != 0xff {
+            return None;
+        }
+        let marker = data[i + 1];
+        // Markers may be padded with any number of fill bytes.
+        if marker == 0xff {
+            i += 1;
+            continue;
+        }
+        // Everything from 0xc0 to 0xcf is a start of frame, except for the
+        // Huffman and arithmetic coding tables and the reserved 0xc8.
+        if (0xc0..=0xcf).contains(&marker) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) {
+            let height = u16::from_be_bytes([data[i + 5], data[i + 6]]);
+            let width = u16::from_be_bytes([data[i + 7], data[i + 8]]);
+            return Some((width.into(), height.into()));
+        }
+        i += 2 + usize::from(u16::from_be_bytes([data[i + 2], data[i + 3]]));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html;
+    use crate::test_server::{TestResponse, TestServer};
+
+    /// A PNG header with the given dimensions, the rest of the image does
+    /// not matter for embedding.
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        data.extend(width.to_be_bytes());
+        data.extend(height.to_be_bytes());
+        data.extend(b"\x08\x06\0\0\0");
+        data
+    }
+
+    fn fetcher(config: ImageConfig) -> ImageFetcher {
+        ImageFetcher::new(ureq::AgentBuilder::new().build(), config)
+    }
+
+    #[test]
+    fn image_dimensions_of_jpeg() {
+        // A JFIF segment, then the start of a 640x480 frame.
+        let mut jpeg = b"\xff\xd8\xff\xe0\0\x10JFIF\0\x01\x01\0\0\x01\0\x01\0\0".to_vec();
+        jpeg.extend(b"\xff\xc0\0\x11\x08\x01\xe0\x02\x80\x03");
+        assert_eq!(image_dimensions(&jpeg), Some((640, 480)));
+        assert_eq!(image_dimensions(&jpeg[..20]), None);
+    }
+
+    #[test]
+    fn embed_images_rewrites_and_caches() {
+        let server = TestServer::start(vec![
+            TestResponse::new(200, png(2, 2)).header("Content-Type", "image/png"),
+            TestResponse::new(200, png(1, 1)).header("Content-Type", "image/png"),
+        ]);
+        let base_url = Url::parse(&server.url("/posts/1")).unwrap();
+        let fetcher = fetcher(ImageConfig::default());
+
+        let mut nodes = html::parse_fragment(
+            r#"<p><img src="/cat.png" srcset="/cat-2x.png 2x"><img src="/cat.png"><img src="/pixel.png"><img src="https://pixel.wp.com/g.gif" width="1" height="1"></p>"#,
+        );
+        let embedded = fetcher.embed_images(&mut nodes, Some(&base_url), "images/");
+
+        assert_eq!(embedded.len(), 1);
+        assert_eq!(embedded[0].path, "images/0.png");
+        assert_eq!(embedded[0].image.mime_type, "image/png");
+        assert_eq!(server.next_request().path, "/cat.png");
+        assert_eq!(server.next_request().path, "/pixel.png");
+        assert_eq!(
+            html::to_xhtml(&nodes),
+            r#"<p><img src="images/0.png" /><img src="images/0.png" /></p>"#
+        );
+
+        // The server has nothing left to serve, this only works from cache.
+        let mut nodes = html::parse_fragment(r#"<img src="/cat.png">"#);
+        let embedded = fetcher.embed_images(&mut nodes, Some(&base_url), "images/1-");
+        assert_eq!(embedded.len(), 1);
+        assert_eq!(html::to_xhtml(&nodes), r#"<img src="images/1-0.png" />"#);
+    }
+
+    #[test]
+    fn fetch_retries_temporary_failures() {
+        let server = TestServer::start(vec![
+            TestResponse::new(503, ""),
+            TestResponse::new(200, png(2, 2)).header("Content-Type", "image/png"),
+            TestResponse::new(404, ""),
+        ]);
+        let fetcher = fetcher(ImageConfig::default());
+        let cat = Url::parse(&server.url("/cat.png")).unwrap();
+        let gone = Url::parse(&server.url("/gone.png")).unwrap();
+
+        assert!(fetcher.cover_image(&cat).is_none());
+        assert!(fetcher.cover_image(&cat).is_some());
+        assert!(fetcher.cover_image(&gone).is_none());
+        for _ in 0..3 {
+            server.next_request();
+        }
+
+        // The server has nothing left to serve, the 404 sticks.
+        assert!(fetcher.cover_image(&gone).is_none());
+        assert!(matches!(
+            fetcher.cache.borrow().images.get(gone.as_str()),
+            Some(CachedImage::Failed)
+        ));
+    }
+
+    #[test]
+    fn download_ignores_content_type() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" onload="alert(1)"/>"#;
+        let server = TestServer::start(vec![
+            TestResponse::new(200, svg).header("Content-Type", "image/svg+xml"),
+            TestResponse::new(200, png(2, 2)).header("Content-Type", "text/plain"),
+        ]);
+        let fetcher = fetcher(ImageConfig::default());
+
+        assert!(matches!(
+            fetcher.download(&Url::parse(&server.url("/logo.svg")).unwrap()),
+            Err(FetchImageError::NotAnImage)
+        ));
+        let image = fetcher
+            .download(&Url::parse(&server.url("/cat")).unwrap())
+            .expect("failed to download image");
+        assert_eq!(image.mime_type, "image/png");
+    }
+
+    #[test]
+    fn embed_images_decodes_data_uris() {
+        let fetcher = fetcher(ImageConfig::default());
+        let image = format!(
+            "data:image/png;base64,{}",
+            BASE64_STANDARD.encode(png(2, 2))
+        );
+        let pixel = format!(
+            "data:image/png;base64,{}",
+            BASE64_STANDARD.encode(png(1, 1))
+        );
+
+        let mut nodes = html::parse_fragment(&format!(
+            r#"<img src="{image}"><img src="{pixel}"><img src="{image}"><img src="data:text/plain,hello">"#
+        ));
+        let embedded = fetcher.embed_images(&mut nodes, None, "images/");
+
+        assert_eq!(embedded.len(), 1);
+        assert_eq!(embedded[0].image.data, png(2, 2));
+        assert_eq!(
+            html::to_xhtml(&nodes),
+            r#"<img src="images/0.png" /><img src="images/0.png" /><img src="data:text/plain,hello" />"#
+        );
+    }
+
+    #[test]
+    fn embed_images_respects_limits() {
+        let server = TestServer::start(vec![
+            TestResponse::new(200, vec![0u8; 64]).header("Content-Type", "image/png"),
+            TestResponse::new(200, png(2, 2)).header("Content-Type", "image/png"),
+        ]);
+        let fetcher = fetcher(ImageConfig {
+            embed: true,
+            max_bytes: 32,
+            max_per_entry: 1,
+        });
+
+        let mut nodes = html::parse_fragment(&format!(
+            r#"<img src="{}"><img src="{}"><img src="{}">"#,
+            server.url("/huge.png"),
+            server.url("/small.png"),
+            server.url("/over-the-limit.png"),
+        ));
+        let embedded = fetcher.embed_images(&mut nodes, None, "images/");
+
+        assert_eq!(embedded.len(), 1);
+        assert_eq!(server.next_request().path, "/huge.png");
+        assert_eq!(server.next_request().path, "/small.png");
+        assert_eq!(
+            html::to_xhtml(&nodes),
+            format!(
+                r#"<img src="{}" /><img src="images/0.png" /><img src="{}" />"#,
+                server.url("/huge.png"),
+                server.url("/over-the-limit.png"),
+            )
+        );
+    }
+}
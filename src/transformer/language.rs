@@ -0,0 +1,127 @@
+//! Guesses the language of entries whose feed doesn't say, e-readers pick
+//! their hyphenation and dictionaries by it. Counting the most common words
+//! of each language is crude, but articles are long enough for it.
+
+/// The most common words of the languages we can tell apart, words shared
+/// by several of them count for each.
+const COMMON_WORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "of", "to", "is", "in", "that", "it", "for", "with", "was", "on", "you",
+            "this", "are", "be", "have", "not",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "und", "das", "ist", "nicht", "ein", "eine", "ich", "zu", "mit", "sich",
+            "auf", "für", "den", "dem", "auch", "es",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "la", "les", "et", "est", "des", "une", "un", "que", "pas", "pour", "dans", "du",
+            "qui", "sur", "au", "avec", "ce",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "la", "los", "las", "y", "que", "en", "un", "una", "es", "por", "para", "con",
+            "no", "del", "se", "lo", "como",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "la", "che", "di", "e", "un", "una", "per", "non", "sono", "è", "con", "del",
+            "della", "gli", "le", "si", "anche",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "de", "het", "een", "en", "van", "is", "dat", "niet", "op", "te", "voor", "met",
+            "zijn", "ik", "die", "ook", "maar", "er",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "o", "a", "os", "as", "e", "que", "de", "um", "uma", "não", "para", "com", "do", "da",
+            "é", "em", "no", "na",
+        ],
+    ),
+    (
+        "sv",
+        &[
+            "och", "att", "det", "som", "en", "är", "på", "för", "med", "inte", "av", "den",
+            "till", "jag", "har", "om", "ett", "var",
+        ],
+    ),
+];
+
+/// Below this many common words a text is too short to tell.
+const MIN_COMMON_WORDS: usize = 5;
+
+/// detect returns the language tag of the language `text` is most likely
+/// written in, or None if it can't tell.
+pub fn detect(text: &str) -> Option<&'static str> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+
+    let mut scores: Vec<(&str, usize)> = COMMON_WORDS
+        .iter()
+        .map(|(language, common_words)| {
+            let score = words
+                .iter()
+                .filter(|word| common_words.contains(&word.as_str()))
+                .count();
+            (*language, score)
+        })
+        .collect();
+    scores.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    match scores.as_slice() {
+        [(language, best), (_, second), ..] if *best >= MIN_COMMON_WORDS && best > second => {
+            Some(language)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_languages() {
+        for (text, expected) in [
+            (
+                "The quick brown fox jumps over the lazy dog, and it is not the first time that this happens to the dog.",
+                Some("en"),
+            ),
+            (
+                "Der schnelle braune Fuchs springt über den faulen Hund, und das ist nicht das erste Mal, dass er es mit dem Hund zu tun hat.",
+                Some("de"),
+            ),
+            (
+                "Le renard brun rapide saute par-dessus le chien paresseux, et ce n'est pas la première fois que cela arrive au chien.",
+                Some("fr"),
+            ),
+            (
+                "El rápido zorro marrón salta sobre el perro perezoso, y no es la primera vez que esto le pasa al perro con el zorro.",
+                Some("es"),
+            ),
+            ("Hello world", None),
+            ("", None),
+        ] {
+            assert_eq!(detect(text), expected, "{text}");
+        }
+    }
+}
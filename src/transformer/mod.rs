@@ -0,0 +1,921 @@
+use crate::html;
+use cover::CoverText;
+use epub_builder::{
+    EpubBuilder, EpubContent, EpubVersion, MetadataOpf, MetadataOpfV3, ReferenceType, ZipLibrary,
+};
+use images::{EmbeddedImage, ImageFetcher};
+use jiff::tz::TimeZone;
+use jiff::Timestamp;
+use siphasher::sip128::{Hasher128, SipHasher13};
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use style::Style;
+use thiserror::Error;
+
+pub mod cover;
+pub mod digest;
+pub mod images;
+pub mod language;
+pub mod newspaper;
+pub mod style;
+pub mod template;
+pub mod xhtml;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("could not create file.\nError: {0}")]
+    FileCreationError(#[from] std::io::Error),
+    #[error("could not build epub library builder: {0}")]
+    EpubBuilderError(#[from] epub_builder::Error),
+    #[error("could not extract content from entry: {0}")]
+    ContentExtractionError(#[from] crate::storage::EntryConversionError),
+    #[error("could not add metadata to epub: {0}")]
+    MetadataError(#[from] zip::result::ZipError),
+}
+
+/// Chapter is an entry rendered into XHTML together with the images it
+/// references.
+struct Chapter {
+    xhtml: String,
+    images: Vec<EmbeddedImage>,
+    language: String,
+}
+
+/// DEFAULT_LANGUAGE is the language of entries that don't have one and
+/// are too short to guess theirs.
+const DEFAULT_LANGUAGE: &str = "en";
+
+/// entry_to_chapter renders an entry into the document of its chapter.
+fn entry_to_chapter(
+    entry: &crate::storage::Entry,
+    style: &Style,
+    image_fetcher: Option<&ImageFetcher>,
+    image_prefix: &str,
+) -> Chapter {
+    let mut nodes = xhtml::sanitize(html::parse_fragment(&entry.content));
+
+    let images = match image_fetcher {
+        Some(image_fetcher) => {
+            // Relative image paths are relative to the article, not the feed.
+            let base_url = entry
+                .link
+                .as_deref()
+                .and_then(|link| url::Url::parse(link).ok());
+            image_fetcher.embed_images(&mut nodes, base_url.as_ref(), image_prefix)
+        }
+        None => Vec::new(),
+    };
+
+    let language = entry_language(entry, &nodes);
+    Chapter {
+        xhtml: style.chapter(entry, &html::to_xhtml(&nodes), &language),
+        images,
+        language,
+    }
+}
+
+/// new_epub_builder sets up a book with everything all of our books share.
+fn new_epub_builder(style: &Style) -> Result<EpubBuilder<ZipLibrary>, Error> {
+    let mut epub_builder = EpubBuilder::new(ZipLibrary::new()?)?;
+    epub_builder
+        .epub_version(EpubVersion::V33)
+        .metadata("generator", "feed-to-epub")?
+        .stylesheet(style.stylesheet.as_bytes())?;
+    Ok(epub_builder)
+}
+
+/// add_images adds the images of a chapter to the book, except for the
+/// cover which add_cover takes care of.
+fn add_images(
+    epub_builder: &mut EpubBuilder<ZipLibrary>,
+    images: &[EmbeddedImage],
+    cover: &EmbeddedImage,
+) -> Result<(), Error> {
+    for embedded_image in images {
+        if embedded_image.path == cover.path {
+            continue;
+        }
+        epub_builder.add_resource(
+            &embedded_image.path,
+            embedded_image.image.data.as_slice(),
+            embedded_image.image.mime_type,
+        )?;
+    }
+    Ok(())
+}
+
+fn add_cover(
+    epub_builder: &mut EpubBuilder<ZipLibrary>,
+    cover: &EmbeddedImage,
+) -> Result<(), Error> {
+    epub_builder.add_cover_image(
+        &cover.path,
+        cover.image.data.as_slice(),
+        cover.image.mime_type,
+    )?;
+    Ok(())
+}
+
+/// entry_to_epub builds the EPUB for a stored entry, it only needs what we
+/// keep in the database so books can be rebuilt without the feed at hand.
+/// `logo` is the URL of the feed's logo, which becomes the cover.
+pub fn entry_to_epub(
+    feed_name: &str,
+    download_dir: &str,
+    entry: &crate::storage::Entry,
+    series_index: Option<u64>,
+    logo: Option<&str>,
+    style: &Style,
+    image_fetcher: Option<&ImageFetcher>,
+) -> Result<(), Error> {
+    if entry.title.is_empty() {
+        return Err(Error::ContentExtractionError(
+            crate::storage::EntryConversionError::TitleExtractionError,
+        ));
+    }
+
+    let chapter = entry_to_chapter(entry, style, image_fetcher, "images/");
+    let cover = cover::choose_cover(
+        logo,
+        &chapter.images,
+        image_fetcher,
+        &CoverText {
+            kicker: feed_name,
+            title: &entry.title,
+            date: entry.timestamp(),
+        },
+    );
+
+    let mut epub_builder = new_epub_builder(style)?;
+    add_cover(&mut epub_builder, &cover)?;
+    add_images(&mut epub_builder, &chapter.images, &cover)?;
+
+    epub_builder.set_uuid(entry_uuid(feed_name, entry));
+    epub_builder.metadata("lang", &chapter.language)?;
+    add_series(&mut epub_builder, feed_name, series_index);
+
+    if let Some(published_date) = entry
+        .published
+        .as_deref()
+        .and_then(|published| chrono::DateTime::parse_from_rfc3339(published).ok())
+    {
+        epub_builder.set_publication_date(published_date.with_timezone(&chrono::Utc));
+    }
+
+    // The summary was already cut down to something reasonable when the
+    // entry was stored.
+    if !entry.summary.is_empty() {
+        epub_builder.metadata("description", &entry.summary)?;
+    }
+
+    for author in &entry.authors {
+        epub_builder.metadata("author", author)?;
+    }
+    for category in &entry.categories {
+        epub_builder.metadata("subject", category)?;
+    }
+
+    epub_builder.metadata("title", &entry.title)?.add_content(
+        EpubContent::new("chapter.xhtml", chapter.xhtml.as_bytes()).title(&entry.title),
+    )?;
+
+    let mut epub = Vec::new();
+    epub_builder.generate(&mut epub)?;
+    let dublin_core: Vec<(&str, &str)> = entry
+        .link
+        .iter()
+        .map(|link| ("source", link.as_str()))
+        .collect();
+    write_epub(
+        &entry_title_to_file_name(download_dir, &entry.title.replace('/', "_")),
+        &epub,
+        &dublin_core,
+    )
+}
+
+/// write_epub writes a book epub-builder generated to `path`, adding the
+/// Dublin Core `elements` epub-builder has no way to write to its package
+/// document.
+fn write_epub(path: &Path, epub: &[u8], elements: &[(&str, &str)]) -> Result<(), Error> {
+    if elements.is_empty() {
+        std::fs::write(path, epub)?;
+        return Ok(());
+    }
+
+    let dublin_core: String = elements
+        .iter()
+        .map(|(name, value)| format!("<dc:{name}>{}</dc:{name}>\n  ", html::escape(value)))
+        .collect();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(epub))?;
+    let mut writer = zip::ZipWriter::new(File::create(path)?);
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if file.name() != "OEBPS/content.opf" {
+            // Copying as is keeps the mimetype first and uncompressed.
+            writer.raw_copy_file(file)?;
+            continue;
+        }
+
+        let mut opf = String::new();
+        file.read_to_string(&mut opf)?;
+        let opf = opf.replacen("</metadata>", &format!("{dublin_core}</metadata>"), 1);
+        writer.start_file(
+            file.name(),
+            zip::write::SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated),
+        )?;
+        writer.write_all(opf.as_bytes())?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// digest_to_epub bundles several entries of a feed into a single EPUB with
+/// one chapter per entry and a table of contents up front. It returns the
+/// path of the written file.
+pub fn digest_to_epub(
+    feed_name: &str,
+    download_dir: &str,
+    title: &str,
+    entries: &[crate::storage::Entry],
+    logo: Option<&str>,
+    style: &Style,
+    image_fetcher: Option<&ImageFetcher>,
+) -> Result<PathBuf, Error> {
+    let chapters: Vec<Chapter> = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| entry_to_chapter(entry, style, image_fetcher, &format!("images/{i}-")))
+        .collect();
+    let cover = cover::choose_cover(
+        logo,
+        chapters
+            .first()
+            .map(|chapter| chapter.images.as_slice())
+            .unwrap_or_default(),
+        image_fetcher,
+        &CoverText {
+            kicker: feed_name,
+            title,
+            date: None,
+        },
+    );
+
+    let mut epub_builder = new_epub_builder(style)?;
+    epub_builder.metadata("title", title)?;
+    add_series(&mut epub_builder, feed_name, None);
+    epub_builder.inline_toc();
+    add_cover(&mut epub_builder, &cover)?;
+
+    for (i, (entry, chapter)) in entries.iter().zip(chapters).enumerate() {
+        add_images(&mut epub_builder, &chapter.images, &cover)?;
+
+        epub_builder.add_content(
+            EpubContent::new(format!("entry_{i}.xhtml"), chapter.xhtml.as_bytes())
+                .title(chapter_title(entry)),
+        )?;
+    }
+
+    let file_name = entry_title_to_file_name(download_dir, &title.replace('/', "_"));
+    epub_builder.generate(File::create(&file_name)?)?;
+    Ok(file_name)
+}
+
+/// Section is a part of a newspaper edition, either a single feed or a
+/// group of feeds.
+pub struct Section {
+    pub title: String,
+    pub entries: Vec<crate::storage::Entry>,
+}
+
+/// newspaper_to_epub builds a newspaper edition, opening with a cover page
+/// listing all headlines followed by one chapter per section with the entries
+/// nested below it in the table of contents. It returns the path of the
+/// written file.
+pub fn newspaper_to_epub(
+    output_dir: &str,
+    title: &str,
+    sections: &[Section],
+    style: &Style,
+    image_fetcher: Option<&ImageFetcher>,
+) -> Result<PathBuf, Error> {
+    let mut epub_builder = new_epub_builder(style)?;
+    epub_builder.metadata("title", title)?;
+
+    // A newspaper has no single feed whose logo would do as cover.
+    let section_titles: Vec<&str> = sections
+        .iter()
+        .map(|section| section.title.as_str())
+        .collect();
+    let cover = cover::choose_cover(
+        None,
+        &[],
+        image_fetcher,
+        &CoverText {
+            kicker: &section_titles.join(" · "),
+            title,
+            date: None,
+        },
+    );
+    add_cover(&mut epub_builder, &cover)?;
+
+    let headlines = headlines_page(title, sections, style);
+    epub_builder.add_content(
+        EpubContent::new("headlines.xhtml", headlines.as_bytes())
+            .title("Headlines")
+            .reftype(ReferenceType::TitlePage),
+    )?;
+
+    for (s, section) in sections.iter().enumerate() {
+        let heading = html::to_xhtml(&[html::Node::Element(text_element("h1", &section.title))]);
+        epub_builder.add_content(
+            EpubContent::new(
+                format!("section_{s}.xhtml"),
+                style
+                    .document(&section.title, &section_language(section), &heading)
+                    .as_bytes(),
+            )
+            .title(&section.title)
+            .level(1),
+        )?;
+
+        for (e, entry) in section.entries.iter().enumerate() {
+            let chapter =
+                entry_to_chapter(entry, style, image_fetcher, &format!("images/{s}-{e}-"));
+            add_images(&mut epub_builder, &chapter.images, &cover)?;
+            epub_builder.add_content(
+                EpubContent::new(section_entry_href(s, e), chapter.xhtml.as_bytes())
+                    .title(chapter_title(entry))
+                    .level(2),
+            )?;
+        }
+    }
+
+    let file_name = entry_title_to_file_name(output_dir, &title.replace('/', "_"));
+    epub_builder.generate(File::create(&file_name)?)?;
+    Ok(file_name)
+}
+
+fn section_entry_href(section: usize, entry: usize) -> String {
+    format!("section_{section}_entry_{entry}.xhtml")
+}
+
+fn chapter_title(entry: &crate::storage::Entry) -> &str {
+    if entry.title.is_empty() {
+        "Untitled"
+    } else {
+        &entry.title
+    }
+}
+
+/// entry_language is the language tag of an entry, guessed from `nodes`,
+/// its content, if neither the entry nor its feed say.
+fn entry_language(entry: &crate::storage::Entry, nodes: &[html::Node]) -> String {
+    match entry.language.as_deref().map(str::trim) {
+        Some(language) if !language.is_empty() => language.into(),
+        _ => language::detect(&html::text_content(nodes))
+            .unwrap_or(DEFAULT_LANGUAGE)
+            .into(),
+    }
+}
+
+/// entry_uuid identifies the book of an entry. It only depends on the feed
+/// and the id of the entry, so e-readers recognize a rebuilt book as the
+/// one they already have.
+fn entry_uuid(feed_name: &str, entry: &crate::storage::Entry) -> uuid::Uuid {
+    let id = entry
+        .feed_entry_id
+        .as_deref()
+        .or(entry.link.as_deref())
+        .unwrap_or(&entry.title);
+    let mut hasher = SipHasher13::new();
+    hasher.write(feed_name.as_bytes());
+    hasher.write_u8(0);
+    hasher.write(id.as_bytes());
+    uuid::Builder::from_custom_bytes(hasher.finish128().as_u128().to_be_bytes()).into_uuid()
+}
+
+/// add_series makes the book part of the series of its feed, both the way
+/// EPUB 3 and calibre describe series. `series_index` is the position of
+/// the book in the series, if known.
+fn add_series(
+    epub_builder: &mut EpubBuilder<ZipLibrary>,
+    feed_name: &str,
+    series_index: Option<u64>,
+) {
+    let mut collection =
+        MetadataOpfV3::new("belongs-to-collection".into(), html::escape(feed_name));
+    collection.add_id("collection".into());
+    let mut collection_type = MetadataOpfV3::new("collection-type".into(), "series".into());
+    // add_refines sets the id instead, at least in epub-builder 0.8.
+    collection_type.refines = Some("#collection".into());
+    epub_builder
+        .add_metadata_opf(Box::new(collection))
+        .add_metadata_opf(Box::new(collection_type))
+        .add_metadata_opf(Box::new(MetadataOpf {
+            name: "calibre:series".into(),
+            content: feed_name.into(),
+        }));
+
+    if let Some(series_index) = series_index {
+        let mut group_position =
+            MetadataOpfV3::new("group-position".into(), series_index.to_string());
+        group_position.refines = Some("#collection".into());
+        epub_builder
+            .add_metadata_opf(Box::new(group_position))
+            .add_metadata_opf(Box::new(MetadataOpf {
+                name: "calibre:series_index".into(),
+                content: series_index.to_string(),
+            }));
+    }
+}
+
+/// format_date is how dates are shown in books, in the local time zone.
+fn format_date(timestamp: Timestamp) -> String {
+    timestamp
+        .to_zoned(TimeZone::system())
+        .date()
+        .strftime("%-d %B %Y")
+        .to_string()
+}
+
+fn text_element(name: &str, text: &str) -> html::Element {
+    let mut element = html::Element::new(name);
+    element.children.push(html::Node::Text(text.into()));
+    element
+}
+
+/// headlines_page renders the cover page of a newspaper edition, every
+/// headline links to its article.
+fn headlines_page(title: &str, sections: &[Section], style: &Style) -> String {
+    let mut nodes = vec![html::Node::Element(text_element("h1", title))];
+    for (s, section) in sections.iter().enumerate() {
+        nodes.push(html::Node::Element(text_element("h2", &section.title)));
+
+        let mut list = html::Element::new("ul");
+        for (e, entry) in section.entries.iter().enumerate() {
+            let mut link = text_element("a", chapter_title(entry));
+            link.set_attr("href", &section_entry_href(s, e));
+            let mut item = html::Element::new("li");
+            item.children.push(html::Node::Element(link));
+            list.children.push(html::Node::Element(item));
+        }
+        nodes.push(html::Node::Element(list));
+    }
+
+    let language = sections
+        .first()
+        .map(section_language)
+        .unwrap_or_else(|| DEFAULT_LANGUAGE.into());
+    style.document(title, &language, &html::to_xhtml(&nodes))
+}
+
+/// section_language is the language of a newspaper section, that of its
+/// first entry.
+fn section_language(section: &Section) -> String {
+    match section.entries.first() {
+        Some(entry) => entry_language(entry, &html::parse_fragment(&entry.content)),
+        None => DEFAULT_LANGUAGE.into(),
+    }
+}
+
+pub fn entry_title_to_file_name(destination_dir: &str, title: &str) -> PathBuf {
+    PathBuf::from(format!("{destination_dir}/{title}.epub"))
+}
+
+/// store_test_entry stores an entry with nothing but a title and a paragraph
+/// for the feed at `feed_url`, setting up the feed if it is new.
+#[cfg(test)]
+fn store_test_entry(storage: &crate::storage::Storage, feed_url: &str, title: &str) {
+    let feed_id = match storage.feed_stats_from_db(feed_url).unwrap() {
+        Some(feed_stats) => feed_stats.id,
+        None => storage.new_feed_stats_to_db(feed_url).unwrap().id,
+    };
+    storage
+        .entry_to_db(&crate::storage::Entry {
+            feed_id,
+            feed_entry_id: Some(title.into()),
+            title: title.into(),
+            updated: None,
+            published: None,
+            authors: Vec::new(),
+            summary: "".into(),
+            content: format!("<p>{title}</p>"),
+            link: None,
+            language: None,
+            categories: Vec::new(),
+        })
+        .expect("failed to store entry");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every fixture in testdata/feeds is a feed in one of the formats
+    /// feed-rs supports, next to a `.expected` file listing the title of
+    /// each entry and a snippet its chapter must contain, tab separated.
+    #[test]
+    fn feeds_in_every_format_to_epub() {
+        let mut fixtures: Vec<_> = std::fs::read_dir("src/transformer/testdata/feeds")
+            .expect("failed to read feed fixtures")
+            .map(|dir_entry| dir_entry.unwrap().path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension != "expected")
+            })
+            .collect();
+        fixtures.sort();
+        assert!(!fixtures.is_empty());
+
+        for fixture in fixtures {
+            let name = fixture.display();
+            let feed = feed_rs::parser::parse(File::open(&fixture).unwrap())
+                .unwrap_or_else(|err| panic!("{name}: failed to parse: {err}"));
+            let expected = std::fs::read_to_string(fixture.with_extension("expected")).unwrap();
+            let expected: Vec<(&str, &str)> = expected
+                .lines()
+                .map(|line| line.split_once('\t').expect("title and snippet"))
+                .collect();
+            assert_eq!(feed.entries.len(), expected.len(), "{name}: entries");
+
+            let download_dir = tempfile::tempdir().unwrap();
+            let download_dir = download_dir.path().to_str().unwrap();
+            for (feed_entry, (title, snippet)) in feed.entries.iter().zip(expected) {
+                let entry = crate::storage::entry_from_feed_entry(1, feed_entry)
+                    .unwrap_or_else(|err| panic!("{name}: failed to convert {title}: {err}"));
+                assert_eq!(entry.title, title, "{name}: title");
+
+                entry_to_epub(
+                    "fixture",
+                    download_dir,
+                    &entry,
+                    None,
+                    None,
+                    &Style::default(),
+                    None,
+                )
+                .unwrap_or_else(|err| panic!("{name}: failed to build {title}: {err}"));
+                let chapter = read_file(download_dir, &entry.title, "OEBPS/chapter.xhtml");
+
+                assert_well_formed(&chapter);
+                assert!(
+                    chapter.contains(snippet),
+                    "{name}: {title} should contain {snippet:?}\n{chapter}"
+                );
+
+                let opf = read_file(download_dir, &entry.title, "OEBPS/content.opf");
+                assert_well_formed(&opf);
+                for category in &entry.categories {
+                    let subject = format!("<dc:subject>{category}</dc:subject>");
+                    assert!(opf.contains(&subject), "{name}: missing {subject}\n{opf}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn entry_to_epub_adds_cover() {
+        use base64::prelude::{Engine, BASE64_STANDARD};
+
+        let download_dir = tempfile::tempdir().unwrap();
+        let download_dir = download_dir.path().to_str().unwrap();
+        let image_fetcher =
+            ImageFetcher::new(ureq::AgentBuilder::new().build(), Default::default());
+
+        // Just the header of a PNG, which is all we look at.
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend(600u32.to_be_bytes());
+        png.extend(400u32.to_be_bytes());
+        let mut entry = crate::storage::Entry {
+            feed_id: 1,
+            feed_entry_id: Some("1".into()),
+            title: "With a photo".into(),
+            updated: None,
+            published: Some("2025-01-13T12:00:00Z".into()),
+            authors: Vec::new(),
+            summary: "".into(),
+            content: format!(
+                r#"<p>Look:</p><img src="data:image/png;base64,{}" />"#,
+                BASE64_STANDARD.encode(&png)
+            ),
+            link: None,
+            language: None,
+            categories: Vec::new(),
+        };
+        entry_to_epub(
+            "photos",
+            download_dir,
+            &entry,
+            None,
+            None,
+            &Style::default(),
+            Some(&image_fetcher),
+        )
+        .unwrap();
+        let opf = read_file(download_dir, &entry.title, "OEBPS/content.opf");
+        assert!(
+            opf.contains(r#"properties="cover-image" id="cover-image" href="images/0.png""#),
+            "the photo should be the cover:\n{opf}"
+        );
+        assert_eq!(opf.matches("images/0.png").count(), 1, "{opf}");
+
+        entry.title = "Without a photo".into();
+        entry.content = "<p>Nothing to see.</p>".into();
+        entry_to_epub(
+            "photos",
+            download_dir,
+            &entry,
+            None,
+            None,
+            &Style::default(),
+            Some(&image_fetcher),
+        )
+        .unwrap();
+        let opf = read_file(download_dir, &entry.title, "OEBPS/content.opf");
+        assert!(
+            opf.contains(r#"properties="cover-image" id="cover-image" href="cover.svg""#),
+            "a cover should have been generated:\n{opf}"
+        );
+        assert!(read_file(download_dir, &entry.title, "OEBPS/cover.svg").contains("PHOTOS"));
+    }
+
+    #[test]
+    fn entry_to_epub_styles_chapters() {
+        let download_dir = tempfile::tempdir().unwrap();
+        let download_dir = download_dir.path().to_str().unwrap();
+        let mut stylesheet = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut stylesheet, b"body { color: red; }").unwrap();
+
+        let entry = crate::storage::Entry {
+            feed_id: 1,
+            feed_entry_id: Some("1".into()),
+            title: "Styled & headed".into(),
+            updated: None,
+            published: Some("2025-01-13T12:00:00Z".into()),
+            authors: vec!["Jane Doe".into(), "John Roe".into()],
+            summary: "".into(),
+            content: "<p>Body.</p>".into(),
+            link: Some("https://www.example.com/posts/1".into()),
+            language: None,
+            categories: Vec::new(),
+        };
+        let style = Style::load(stylesheet.path().to_str(), None);
+        entry_to_epub("styled", download_dir, &entry, None, None, &style, None).unwrap();
+
+        assert_eq!(
+            read_file(download_dir, &entry.title, "OEBPS/stylesheet.css"),
+            "body { color: red; }"
+        );
+        let chapter = read_file(download_dir, &entry.title, "OEBPS/chapter.xhtml");
+        assert_well_formed(&chapter);
+        assert!(chapter.contains("<!DOCTYPE html>\n"), "{chapter}");
+        for expected in [
+            r#"<html xmlns="http://www.w3.org/1999/xhtml" xml:lang="en">"#,
+            "<title>Styled &amp; headed</title>",
+            r#"<link rel="stylesheet" href="stylesheet.css" type="text/css" />"#,
+            r#"<h1 class="chapter-title">Styled &amp; headed</h1>"#,
+            r#"<p class="chapter-authors">Jane Doe, John Roe</p>"#,
+            r#"<a href="https://www.example.com/posts/1">example.com</a>"#,
+            "<p>Body.</p>",
+        ] {
+            assert!(chapter.contains(expected), "missing {expected}:\n{chapter}");
+        }
+
+        // Only links a reader can safely follow make it into the header.
+        let entry = crate::storage::Entry {
+            link: Some("java\tscript:alert(1)".into()),
+            ..entry
+        };
+        let chapter = style.chapter(&entry, "<p>Body.</p>", "en");
+        assert!(!chapter.contains("chapter-source"), "{chapter}");
+
+        // Books don't go without a stylesheet over a wrong path.
+        let style = Style::load(Some("/does/not/exist.css"), Some("/does/not/exist.xhtml"));
+        assert!(style.stylesheet.contains(".chapter-header"));
+    }
+
+    #[test]
+    fn entry_to_epub_writes_metadata() {
+        let download_dir = tempfile::tempdir().unwrap();
+        let download_dir = download_dir.path().to_str().unwrap();
+
+        let entry = crate::storage::Entry {
+            feed_id: 1,
+            feed_entry_id: Some("urn:entry:7".into()),
+            title: "Fully described".into(),
+            updated: None,
+            published: Some("2025-01-13T12:00:00Z".into()),
+            authors: vec!["Doe, Jane".into(), "John Roe".into()],
+            summary: "".into(),
+            content: "<p>Body.</p>".into(),
+            link: Some("https://example.com/posts/7?a=1&b=2".into()),
+            language: Some("en-GB".into()),
+            categories: vec!["rust".into(), "books, reading".into()],
+        };
+        let opf = |series_index| {
+            entry_to_epub(
+                "Jane & John",
+                download_dir,
+                &entry,
+                series_index,
+                None,
+                &Style::default(),
+                None,
+            )
+            .unwrap();
+            read_file(download_dir, &entry.title, "OEBPS/content.opf")
+        };
+
+        let first = opf(Some(7));
+        assert_well_formed(&first);
+        // E-readers expect the mimetype first and stored, even after the
+        // package document was rewritten.
+        let epub = entry_title_to_file_name(download_dir, &entry.title);
+        let mut archive = zip::ZipArchive::new(File::open(epub).unwrap()).unwrap();
+        let mimetype = archive.by_index(0).unwrap();
+        assert_eq!(mimetype.name(), "mimetype");
+        assert_eq!(mimetype.compression(), zip::CompressionMethod::Stored);
+        for expected in [
+            ">Doe, Jane</dc:creator>",
+            ">John Roe</dc:creator>",
+            "<dc:language>en-GB</dc:language>",
+            "<dc:subject>rust</dc:subject>",
+            "<dc:subject>books, reading</dc:subject>",
+            "<dc:source>https://example.com/posts/7?a=1&amp;b=2</dc:source>",
+            r#"<meta id="collection" property="belongs-to-collection">Jane &amp; John</meta>"#,
+            r##"<meta refines="#collection" property="collection-type">series</meta>"##,
+            r##"<meta refines="#collection" property="group-position">7</meta>"##,
+            r#"<meta name="calibre:series" content="Jane &amp; John"/>"#,
+            r#"<meta name="calibre:series_index" content="7"/>"#,
+        ] {
+            assert!(first.contains(expected), "missing {expected}:\n{first}");
+        }
+
+        // Rebuilding the book keeps its identifier.
+        let identifier = |opf: &str| {
+            let start = opf.find("<dc:identifier").unwrap();
+            let end = opf[start..].find("</dc:identifier>").unwrap();
+            opf[start..start + end].to_string()
+        };
+        let second = opf(None);
+        assert_eq!(identifier(&first), identifier(&second));
+        assert!(!second.contains("series_index"), "{second}");
+    }
+
+    #[test]
+    fn entry_to_epub_names_chapter_independent_of_title() {
+        let download_dir = tempfile::tempdir().unwrap();
+        let download_dir = download_dir.path().to_str().unwrap();
+
+        let entry = crate::storage::Entry {
+            feed_id: 1,
+            feed_entry_id: Some("1".into()),
+            title: "Q&A: what's <new>? 50% #1".into(),
+            updated: None,
+            published: None,
+            authors: Vec::new(),
+            summary: "".into(),
+            content: "<p>Answers.</p>".into(),
+            link: None,
+            language: None,
+            categories: Vec::new(),
+        };
+        entry_to_epub(
+            "questions",
+            download_dir,
+            &entry,
+            None,
+            None,
+            &Style::default(),
+            None,
+        )
+        .unwrap();
+
+        let nav = read_file(download_dir, &entry.title, "OEBPS/nav.xhtml");
+        assert_well_formed(&nav);
+        assert!(
+            nav.contains(r#"<a href="chapter.xhtml">Q&amp;A: what's &lt;new&gt;? 50% #1</a>"#),
+            "{nav}"
+        );
+        assert_well_formed(&read_file(
+            download_dir,
+            &entry.title,
+            "OEBPS/chapter.xhtml",
+        ));
+    }
+
+    #[test]
+    fn digest_to_epub_escapes_feed_name() {
+        let download_dir = tempfile::tempdir().unwrap();
+        let download_dir = download_dir.path().to_str().unwrap();
+
+        let entry = crate::storage::Entry {
+            feed_id: 1,
+            feed_entry_id: Some("1".into()),
+            title: "First".into(),
+            updated: None,
+            published: None,
+            authors: Vec::new(),
+            summary: "".into(),
+            content: "<p>First.</p>".into(),
+            link: None,
+            language: None,
+            categories: Vec::new(),
+        };
+        let title = "Jane & John — 2026-10-16";
+        digest_to_epub(
+            "Jane & John",
+            download_dir,
+            title,
+            &[entry],
+            None,
+            &Style::default(),
+            None,
+        )
+        .unwrap();
+
+        let opf = read_file(download_dir, title, "OEBPS/content.opf");
+        assert_well_formed(&opf);
+        for expected in [
+            r#"<meta id="collection" property="belongs-to-collection">Jane &amp; John</meta>"#,
+            r#"<meta name="calibre:series" content="Jane &amp; John"/>"#,
+        ] {
+            assert!(opf.contains(expected), "missing {expected}:\n{opf}");
+        }
+    }
+
+    #[test]
+    fn entry_to_epub_renders_chapter_template() {
+        let download_dir = tempfile::tempdir().unwrap();
+        let download_dir = download_dir.path().to_str().unwrap();
+        let mut template = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(
+            &mut template,
+            br#"<?xml version="1.0" encoding="UTF-8" ?>
+<html xmlns="http://www.w3.org/1999/xhtml" xml:lang="{{language}}">
+<head><title>{{title}}</title></head>
+<body><p class="dates">{{published}}{{#updated}} / {{updated}}{{/updated}}</p>{{{content}}}</body>
+</html>"#,
+        )
+        .unwrap();
+
+        let mut entry = crate::storage::Entry {
+            feed_id: 1,
+            feed_entry_id: Some("1".into()),
+            title: "Der Fuchs".into(),
+            updated: Some("2025-02-01T12:00:00Z".into()),
+            published: Some("2025-01-13T12:00:00Z".into()),
+            authors: Vec::new(),
+            summary: "".into(),
+            content: "<p>Der schnelle braune Fuchs springt über den faulen Hund, und das ist nicht das erste Mal, dass er es mit dem Hund zu tun hat.</p>".into(),
+            link: None,
+            language: None,
+            categories: Vec::new(),
+        };
+        let style = Style::load(None, template.path().to_str());
+        entry_to_epub("fuchs", download_dir, &entry, None, None, &style, None).unwrap();
+
+        let chapter = read_file(download_dir, &entry.title, "OEBPS/chapter.xhtml");
+        assert_well_formed(&chapter);
+        for expected in [
+            r#"xml:lang="de""#,
+            "<title>Der Fuchs</title>",
+            r#"<p class="dates">13 January 2025 / 1 February 2025</p>"#,
+        ] {
+            assert!(chapter.contains(expected), "missing {expected}:\n{chapter}");
+        }
+
+        // The language of the feed wins over guessing.
+        entry.language = Some("de-AT".into());
+        entry_to_epub("fuchs", download_dir, &entry, None, None, &style, None).unwrap();
+        let chapter = read_file(download_dir, &entry.title, "OEBPS/chapter.xhtml");
+        assert!(chapter.contains(r#"xml:lang="de-AT""#), "{chapter}");
+    }
+
+    fn read_file(download_dir: &str, title: &str, name: &str) -> String {
+        let epub = entry_title_to_file_name(download_dir, title);
+        let mut archive = zip::ZipArchive::new(File::open(epub).unwrap()).unwrap();
+        let mut contents = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    fn assert_well_formed(xhtml: &str) {
+        let mut reader = quick_xml::Reader::from_str(xhtml);
+        reader.config_mut().check_end_names = true;
+        loop {
+            match reader.read_event() {
+                Ok(quick_xml::events::Event::Eof) => break,
+                Ok(_) => (),
+                Err(err) => panic!("not well-formed XML: {err}\n{xhtml}"),
+            }
+        }
+    }
+}
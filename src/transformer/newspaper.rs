@@ -0,0 +1,285 @@
+//! The newspaper combines the undelivered entries of every feed with
+//! output_mode = "newspaper" into a single edition with a section per feed
+//! or group.
+
+use super::images::ImageFetcher;
+use super::style::Style;
+use super::Section;
+use crate::feed_reader::config::{Config, NewspaperConfig, OutputMode};
+use crate::feed_reader::schedule;
+use crate::storage::{Edition, ErrorDBOperation, Storage};
+use jiff::tz::TimeZone;
+use jiff::Timestamp;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum NewspaperError {
+    #[error("storage error: {0}")]
+    StorageError(#[from] ErrorDBOperation),
+    #[error("could not create output directory: {0}")]
+    OutputDirError(#[from] std::io::Error),
+    #[error("failed to build newspaper EPUB: {0}")]
+    EpubError(#[from] super::Error),
+}
+
+/// next_edition_due returns when the next edition should be built, None if
+/// no newspaper is configured.
+pub fn next_edition_due(
+    storage: &Storage,
+    config: &Config,
+) -> Result<Option<Timestamp>, ErrorDBOperation> {
+    let Some(newspaper) = &config.newspaper else {
+        return Ok(None);
+    };
+
+    let last_edition = storage.last_edition_from_db()?;
+    Ok(Some(schedule::edition_due(
+        last_edition.map(|edition| edition.created),
+        newspaper.schedule,
+        newspaper.hour,
+        &TimeZone::system(),
+    )))
+}
+
+/// build_due_edition builds the next newspaper edition if one is due and any
+/// of its feeds has entries that were not in an edition before. It returns
+/// the path of the new EPUB, or None if there was nothing to do.
+pub fn build_due_edition(
+    storage: &Storage,
+    config: &Config,
+    now: Timestamp,
+    image_fetcher: Option<&ImageFetcher>,
+) -> Result<Option<PathBuf>, NewspaperError> {
+    let Some(newspaper) = &config.newspaper else {
+        return Ok(None);
+    };
+    match next_edition_due(storage, config)? {
+        Some(due) if due <= now => (),
+        _ => return Ok(None),
+    }
+
+    // Sections are sorted by name, entries within a section by when we first
+    // stored them.
+    let mut sections: BTreeMap<&str, Vec<(u64, crate::storage::Entry)>> = BTreeMap::new();
+    for (feed_name, feed) in config
+        .feeds
+        .iter()
+        .filter(|(_, feed)| feed.output_mode == OutputMode::Newspaper)
+    {
+        let Some(feed_stats) = storage.feed_stats_from_db(&feed.url)? else {
+            continue;
+        };
+        let entries = storage.undelivered_entries_from_db(feed_stats.id)?;
+        if entries.is_empty() {
+            continue;
+        }
+
+        let section = feed.group.as_deref().unwrap_or(feed_name);
+        sections.entry(section).or_default().extend(entries);
+    }
+
+    if sections.is_empty() {
+        return Ok(None);
+    }
+
+    let mut entry_ids = Vec::new();
+    let sections: Vec<Section> = sections
+        .into_iter()
+        .map(|(title, mut entries)| {
+            entries.sort_by_key(|(id, _)| *id);
+            let (ids, entries): (Vec<u64>, Vec<_>) = entries.into_iter().unzip();
+            entry_ids.extend(ids);
+            Section {
+                title: title.into(),
+                entries,
+            }
+        })
+        .collect();
+
+    let today = now.to_zoned(TimeZone::system()).date();
+    let title = format!("{} — {today}", newspaper.title);
+    let file_name = write_edition(config, newspaper, &title, &sections, image_fetcher)?;
+    storage.edition_to_db(&title, now, &entry_ids)?;
+
+    Ok(Some(file_name))
+}
+
+/// rebuild_edition writes the EPUB of an edition built before again, with
+/// the entries that went into it and its original title. Entries of feeds
+/// that are no longer configured are left out. It returns None if no
+/// newspaper is configured or none of the entries are left.
+pub fn rebuild_edition(
+    storage: &Storage,
+    config: &Config,
+    edition: &Edition,
+    image_fetcher: Option<&ImageFetcher>,
+) -> Result<Option<PathBuf>, NewspaperError> {
+    let Some(newspaper) = &config.newspaper else {
+        return Ok(None);
+    };
+
+    // The feed may have been moved out of the newspaper since, so look at
+    // every feed to find the section its entries were in.
+    let mut section_titles = HashMap::new();
+    for (feed_name, feed) in config.feeds.iter() {
+        if let Some(feed_stats) = storage.feed_stats_from_db(&feed.url)? {
+            section_titles.insert(feed_stats.id, feed.group.as_deref().unwrap_or(feed_name));
+        }
+    }
+
+    let mut sections: BTreeMap<&str, Vec<crate::storage::Entry>> = BTreeMap::new();
+    for entry in storage.edition_entries_from_db(edition.id)? {
+        if let Some(section) = section_titles.get(&entry.feed_id) {
+            sections.entry(section).or_default().push(entry);
+        }
+    }
+    if sections.is_empty() {
+        return Ok(None);
+    }
+
+    let sections: Vec<Section> = sections
+        .into_iter()
+        .map(|(title, entries)| Section {
+            title: title.into(),
+            entries,
+        })
+        .collect();
+    let file_name = write_edition(config, newspaper, &edition.title, &sections, image_fetcher)?;
+
+    Ok(Some(file_name))
+}
+
+fn write_edition(
+    config: &Config,
+    newspaper: &NewspaperConfig,
+    title: &str,
+    sections: &[Section],
+    image_fetcher: Option<&ImageFetcher>,
+) -> Result<PathBuf, NewspaperError> {
+    std::fs::create_dir_all(&newspaper.output_dir)?;
+    Ok(super::newspaper_to_epub(
+        &newspaper.output_dir,
+        title,
+        sections,
+        &Style::load(
+            config.stylesheet.as_deref(),
+            config.chapter_template.as_deref(),
+        ),
+        image_fetcher,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transformer::store_test_entry;
+    use std::io::Read;
+
+    fn read_file(epub: &PathBuf, name: &str) -> String {
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(epub).unwrap()).unwrap();
+        let mut contents = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    #[test]
+    fn build_due_edition_sections() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = Config::from_reader(
+            format!(
+                "
+[newspaper]
+title = \"Morning Edition\"
+output_dir = \"{}\"
+
+[feeds.lwn]
+url = \"https://lwn.example/rss\"
+download_dir = \"/tmp/lwn\"
+output_mode = \"newspaper\"
+group = \"Tech\"
+
+[feeds.hn]
+url = \"https://hn.example/rss\"
+download_dir = \"/tmp/hn\"
+output_mode = \"newspaper\"
+group = \"Tech\"
+
+[feeds.weather]
+url = \"https://weather.example/rss\"
+download_dir = \"/tmp/weather\"
+output_mode = \"newspaper\"
+
+[feeds.blog]
+url = \"https://blog.example/rss\"
+download_dir = \"/tmp/blog\"
+                ",
+                output_dir.path().display()
+            )
+            .as_bytes(),
+        )
+        .expect("failed to parse configuration");
+
+        let storage = Storage::new_in_memory().expect("failed to open in memory db");
+        storage.init_database().expect("failed to set up test DB");
+        store_test_entry(&storage, "https://lwn.example/rss", "Kernel released");
+        store_test_entry(&storage, "https://weather.example/rss", "Sunny & warm");
+        store_test_entry(&storage, "https://hn.example/rss", "Show HN");
+        store_test_entry(&storage, "https://blog.example/rss", "Not in the paper");
+
+        let now: Timestamp = "2026-10-16T06:00:00Z".parse().unwrap();
+        let epub = build_due_edition(&storage, &config, now, None)
+            .expect("failed to build edition")
+            .expect("expected an edition to be built");
+
+        let headlines = read_file(&epub, "OEBPS/headlines.xhtml");
+        assert!(headlines.contains("<h2>Tech</h2><ul><li><a href=\"section_0_entry_0.xhtml\">Kernel released</a></li><li><a href=\"section_0_entry_1.xhtml\">Show HN</a></li></ul>"));
+        assert!(headlines.contains("<h2>weather</h2><ul><li><a href=\"section_1_entry_0.xhtml\">Sunny &amp; warm</a></li></ul>"));
+        assert!(!headlines.contains("Not in the paper"));
+
+        // The articles are nested below their section in the navigation.
+        let nav = read_file(&epub, "OEBPS/nav.xhtml");
+        let tech = nav.find("section_0.xhtml").unwrap();
+        let article = nav.find("section_0_entry_1.xhtml").unwrap();
+        let weather = nav.find("section_1.xhtml").unwrap();
+        assert!(tech < article && article < weather);
+        assert!(nav[tech..weather].contains("<ol>"));
+
+        let edition = storage.last_edition_from_db().unwrap().unwrap();
+        assert_eq!(
+            storage.edition_entries_from_db(edition.id).unwrap().len(),
+            3
+        );
+
+        // Rebuilding the edition puts the entries back in their sections.
+        std::fs::remove_file(&epub).unwrap();
+        assert_eq!(
+            rebuild_edition(&storage, &config, &edition, None).expect("failed to rebuild edition"),
+            Some(epub.clone())
+        );
+        assert_eq!(read_file(&epub, "OEBPS/headlines.xhtml"), headlines);
+
+        // Everything was delivered and the next edition is only due tomorrow.
+        store_test_entry(&storage, "https://hn.example/rss", "Ask HN");
+        assert!(build_due_edition(&storage, &config, now, None)
+            .unwrap()
+            .is_none());
+        // Two days on to be past the next morning in whatever the local
+        // timezone of the test machine is.
+        let later = now
+            .saturating_add(jiff::SignedDuration::from_hours(48))
+            .unwrap();
+        build_due_edition(&storage, &config, later, None)
+            .unwrap()
+            .expect("expected the next edition to be built");
+        let edition = storage.last_edition_from_db().unwrap().unwrap();
+        let entries = storage.edition_entries_from_db(edition.id).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].title, "Ask HN");
+    }
+}
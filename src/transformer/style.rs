@@ -0,0 +1,116 @@
+//! How books look: the stylesheet every book embeds and the template every
+//! entry's chapter is rendered from.
+
+use super::template::{Template, TemplateError};
+use super::xhtml;
+use crate::storage::Entry;
+use std::collections::HashMap;
+use thiserror::Error;
+
+const BUNDLED_STYLESHEET: &str = include_str!("stylesheet.css");
+const CHAPTER_TEMPLATE: &str = include_str!("templates/chapter.xhtml");
+const DOCUMENT_TEMPLATE: &str = include_str!("templates/document.xhtml");
+
+#[derive(Error, Debug)]
+pub enum ChapterTemplateError {
+    #[error("could not read the template: {0}")]
+    ReadError(#[from] std::io::Error),
+    #[error("could not parse the template: {0}")]
+    ParseError(#[from] TemplateError),
+}
+
+pub struct Style {
+    pub stylesheet: String,
+    chapter: Template,
+    document: Template,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            stylesheet: BUNDLED_STYLESHEET.into(),
+            chapter: Template::parse(CHAPTER_TEMPLATE)
+                .expect("the bundled chapter template is valid"),
+            document: Template::parse(DOCUMENT_TEMPLATE)
+                .expect("the bundled document template is valid"),
+        }
+    }
+}
+
+impl Style {
+    /// load reads the stylesheet at `stylesheet` and the chapter template at
+    /// `chapter_template`, books get the bundled ones if they can't be read
+    /// so a typo in the config doesn't cost any books.
+    pub fn load(stylesheet: Option<&str>, chapter_template: Option<&str>) -> Style {
+        let mut style = Style::default();
+        if let Some(path) = stylesheet {
+            match std::fs::read_to_string(path) {
+                Ok(stylesheet) => style.stylesheet = stylesheet,
+                Err(err) => {
+                    eprintln!("failed to read stylesheet {path}, using the bundled one: {err}")
+                }
+            }
+        }
+        if let Some(path) = chapter_template {
+            match read_template(path) {
+                Ok(template) => style.chapter = template,
+                Err(err) => {
+                    eprintln!(
+                        "failed to load chapter template {path}, using the bundled one: {err}"
+                    )
+                }
+            }
+        }
+        style
+    }
+
+    /// chapter renders the document of an entry's chapter around its
+    /// `content`, with its title, language, authors, dates and a link to the
+    /// original.
+    pub fn chapter(&self, entry: &Entry, content: &str, language: &str) -> String {
+        let title = super::chapter_title(entry);
+        let mut values = HashMap::from([
+            ("title", title.to_string()),
+            ("language", language.to_string()),
+            ("authors", entry.authors.join(", ")),
+            ("content", content.to_string()),
+        ]);
+        let published = parse_date(entry.published.as_deref());
+        let updated = parse_date(entry.updated.as_deref());
+        // Most feeds set updated to published, that's not worth showing twice.
+        if updated.is_some() && updated != published {
+            values.insert("updated", updated.unwrap_or_default());
+        }
+        if let Some(published) = published {
+            values.insert("published", published);
+        }
+        if let Some(link) = entry.link.as_ref().filter(|link| xhtml::is_safe_url(link)) {
+            let host = url::Url::parse(link).ok().and_then(|url| {
+                url.host_str()
+                    .map(|host| host.trim_start_matches("www.").to_string())
+            });
+            values.insert("link_host", host.unwrap_or_else(|| link.clone()));
+            values.insert("link", link.clone());
+        }
+        self.document(title, language, &self.chapter.render(&values))
+    }
+
+    /// document wraps `body` into an XHTML document of its own, every page of
+    /// a book is one.
+    pub fn document(&self, title: &str, language: &str, body: &str) -> String {
+        self.document.render(&HashMap::from([
+            ("title", title.to_string()),
+            ("language", language.to_string()),
+            ("body", body.to_string()),
+        ]))
+    }
+}
+
+fn read_template(path: &str) -> Result<Template, ChapterTemplateError> {
+    Ok(Template::parse(&std::fs::read_to_string(path)?)?)
+}
+
+fn parse_date(date: Option<&str>) -> Option<String> {
+    date.and_then(|date| date.parse().ok())
+        .map(super::format_date)
+}
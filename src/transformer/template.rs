@@ -0,0 +1,189 @@
+//! Templates for the documents of a book, with just enough of mustache to
+//! leave out what an entry doesn't have: `{{name}}` is replaced by the
+//! escaped value, `{{{name}}}` by the value as it is, `{{#name}}…{{/name}}`
+//! is only rendered if the value is not empty and `{{^name}}…{{/name}}` only
+//! if it is. Names without a value are empty.
+
+use crate::html;
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TemplateError {
+    #[error("tag opened at byte {0} is never closed")]
+    UnclosedTagError(usize),
+    #[error("section {0} is never closed")]
+    UnclosedSectionError(String),
+    #[error("found end of section {found} where {expected} should end")]
+    UnexpectedSectionEndError { found: String, expected: String },
+    #[error("found end of section {0} which was never opened")]
+    UnopenedSectionError(String),
+}
+
+#[derive(Debug)]
+enum Part {
+    Text(String),
+    Value {
+        name: String,
+        escape: bool,
+    },
+    Section {
+        name: String,
+        inverted: bool,
+        parts: Vec<Part>,
+    },
+}
+
+#[derive(Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, TemplateError> {
+        // Sections being parsed, with the parts that came before them.
+        let mut sections: Vec<(String, bool, Vec<Part>)> = Vec::new();
+        let mut parts = Vec::new();
+
+        let mut offset = 0;
+        while let Some(start) = source[offset..].find("{{").map(|start| offset + start) {
+            if start > offset {
+                parts.push(Part::Text(source[offset..start].into()));
+            }
+
+            let (open, close) = match source[start..].starts_with("{{{") {
+                true => ("{{{", "}}}"),
+                false => ("{{", "}}"),
+            };
+            let tag_start = start + open.len();
+            let tag_end = source[tag_start..]
+                .find(close)
+                .map(|end| tag_start + end)
+                .ok_or(TemplateError::UnclosedTagError(start))?;
+            let tag = source[tag_start..tag_end].trim();
+            offset = tag_end + close.len();
+
+            if open == "{{{" {
+                parts.push(Part::Value {
+                    name: tag.into(),
+                    escape: false,
+                });
+                continue;
+            }
+            match tag.split_at_checked(1) {
+                Some(("#", name)) => {
+                    sections.push((name.trim().into(), false, std::mem::take(&mut parts)))
+                }
+                Some(("^", name)) => {
+                    sections.push((name.trim().into(), true, std::mem::take(&mut parts)))
+                }
+                Some(("/", name)) => {
+                    let name = name.trim();
+                    let Some((expected, inverted, outer)) = sections.pop() else {
+                        return Err(TemplateError::UnopenedSectionError(name.into()));
+                    };
+                    if name != expected {
+                        return Err(TemplateError::UnexpectedSectionEndError {
+                            found: name.into(),
+                            expected,
+                        });
+                    }
+                    let section = std::mem::replace(&mut parts, outer);
+                    parts.push(Part::Section {
+                        name: expected,
+                        inverted,
+                        parts: section,
+                    });
+                }
+                _ => parts.push(Part::Value {
+                    name: tag.into(),
+                    escape: true,
+                }),
+            }
+        }
+        if offset < source.len() {
+            parts.push(Part::Text(source[offset..].into()));
+        }
+
+        match sections.pop() {
+            Some((name, _, _)) => Err(TemplateError::UnclosedSectionError(name)),
+            None => Ok(Template { parts }),
+        }
+    }
+
+    pub fn render(&self, values: &HashMap<&str, String>) -> String {
+        let mut rendered = String::new();
+        render_parts(&self.parts, values, &mut rendered);
+        rendered
+    }
+}
+
+fn render_parts(parts: &[Part], values: &HashMap<&str, String>, out: &mut String) {
+    for part in parts {
+        match part {
+            Part::Text(text) => out.push_str(text),
+            Part::Value { name, escape } => {
+                let value = values.get(name.as_str()).map(String::as_str);
+                match escape {
+                    true => out.push_str(&html::escape(value.unwrap_or_default())),
+                    false => out.push_str(value.unwrap_or_default()),
+                }
+            }
+            Part::Section {
+                name,
+                inverted,
+                parts,
+            } => {
+                let set = values
+                    .get(name.as_str())
+                    .is_some_and(|value| !value.is_empty());
+                if set != *inverted {
+                    render_parts(parts, values, out);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_template() {
+        let template = Template::parse(
+            "<h1>{{ title }}</h1>{{#author}}<p>by {{author}}</p>{{/author}}{{^author}}<p>anonymous</p>{{/author}}{{{content}}}",
+        )
+        .unwrap();
+
+        let values = HashMap::from([
+            ("title", "Fish & <Chips>".to_string()),
+            ("content", "<p>Yes.</p>".to_string()),
+        ]);
+        assert_eq!(
+            template.render(&values),
+            "<h1>Fish &amp; &lt;Chips&gt;</h1><p>anonymous</p><p>Yes.</p>"
+        );
+
+        let values = HashMap::from([("author", "Jane".to_string())]);
+        assert_eq!(template.render(&values), "<h1></h1><p>by Jane</p>");
+    }
+
+    #[test]
+    fn parse_errors() {
+        for (source, expected) in [
+            ("<h1>{{title</h1>", TemplateError::UnclosedTagError(4)),
+            (
+                "{{#a}}{{#b}}{{/a}}{{/b}}",
+                TemplateError::UnexpectedSectionEndError {
+                    found: "a".into(),
+                    expected: "b".into(),
+                },
+            ),
+            ("{{#a}}", TemplateError::UnclosedSectionError("a".into())),
+            ("{{/a}}", TemplateError::UnopenedSectionError("a".into())),
+        ] {
+            assert_eq!(Template::parse(source).unwrap_err(), expected, "{source}");
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Turns whatever HTML a feed hands us into XHTML that e-readers accept.
+
+use crate::html::{self, Element, Node};
+
+/// Elements that are removed together with everything inside them, either
+/// because they are active content or because they make no sense in a book.
+const DROPPED_ELEMENTS: &[&str] = &[
+    "applet", "base", "button", "canvas", "embed", "frame", "frameset", "head", "iframe", "input",
+    "link", "math", "meta", "noembed", "noframes", "object", "param", "script", "select", "source",
+    "style", "svg", "template", "textarea", "title", "track",
+];
+
+/// Elements we keep as they are. Anything not in here and not dropped is
+/// replaced by its children, which takes care of wrappers like `<html>`,
+/// `<body>`, `<font>` and whatever custom elements a CMS invents.
+const ALLOWED_ELEMENTS: &[&str] = &[
+    "a",
+    "abbr",
+    "address",
+    "article",
+    "aside",
+    "b",
+    "bdi",
+    "bdo",
+    "blockquote",
+    "br",
+    "caption",
+    "cite",
+    "code",
+    "col",
+    "colgroup",
+    "dd",
+    "del",
+    "details",
+    "dfn",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "i",
+    "img",
+    "ins",
+    "kbd",
+    "li",
+    "main",
+    "mark",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "q",
+    "rp",
+    "rt",
+    "ruby",
+    "s",
+    "samp",
+    "section",
+    "small",
+    "span",
+    "strong",
+    "sub",
+    "summary",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "time",
+    "tr",
+    "u",
+    "ul",
+    "var",
+    "wbr",
+];
+
+/// normalize parses feed HTML tolerantly and returns the sanitized content as
+/// well-formed XHTML, ready to be put into the body of a chapter.
+pub fn normalize(html: &str) -> String {
+    html::to_xhtml(&sanitize(html::parse_fragment(html)))
+}
+
+/// sanitize drops active content and event handlers and unwraps elements we
+/// don't want in a book.
+pub fn sanitize(nodes: Vec<Node>) -> Vec<Node> {
+    let mut sanitized = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        match node {
+            Node::Text(text) => sanitized.push(Node::Text(text)),
+            Node::Element(element) => sanitize_element(element, &mut sanitized),
+        }
+    }
+    sanitized
+}
+
+fn sanitize_element(mut element: Element, out: &mut Vec<Node>) {
+    let name = element.name.as_str();
+    if DROPPED_ELEMENTS.contains(&name) {
+        return;
+    }
+
+    let children = sanitize(std::mem::take(&mut element.children));
+    if !ALLOWED_ELEMENTS.contains(&name) {
+        out.extend(children);
+        return;
+    }
+
+    element
+        .attrs
+        .retain(|(name, value)| is_safe_attribute(name, value));
+    if element.name == "img" {
+        if element.attr("src").is_none() {
+            return;
+        }
+        if element.attr("alt").is_none() {
+            element.set_attr("alt", "");
+        }
+    }
+
+    element.children = children;
+    out.push(Node::Element(element));
+}
+
+fn is_safe_attribute(name: &str, value: &str) -> bool {
+    if name.starts_with("on") || name.starts_with("xmlns") {
+        return false;
+    }
+
+    match name {
+        // Images may still be inline, they are embedded like any other.
+        "src" => is_safe_url(value) || is_image_data_url(value),
+        "href" | "action" | "formaction" | "poster" | "cite" => is_safe_url(value),
+        _ => true,
+    }
+}
+
+/// is_safe_url tells whether `url` may be linked to from a book: relative
+/// URLs and http, https and mailto ones. Browsers ignore tabs, newlines and
+/// surrounding spaces in URLs, so `java&#x09;script:` is still a script, and
+/// they are removed before looking at the scheme.
+pub fn is_safe_url(url: &str) -> bool {
+    match url_scheme(url) {
+        Some(scheme) => matches!(scheme.as_str(), "http" | "https" | "mailto"),
+        None => true,
+    }
+}
+
+fn is_image_data_url(url: &str) -> bool {
+    let url = strip_ignored(url).to_ascii_lowercase();
+    // SVG is a document of its own that may carry scripts.
+    url.starts_with("data:image/") && !url.starts_with("data:image/svg")
+}
+
+/// url_scheme returns the lowercased scheme of `url`, None if it is
+/// relative.
+fn url_scheme(url: &str) -> Option<String> {
+    let url = strip_ignored(url);
+    let end = url.find([':', '/', '?', '#'])?;
+    (url[end..].starts_with(':')).then(|| url[..end].to_ascii_lowercase())
+}
+
+fn strip_ignored(url: &str) -> String {
+    url.chars()
+        .filter(|c| !c.is_ascii_control())
+        .collect::<String>()
+        .trim_matches(' ')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    /// Every `<name>.html` snippet in testdata/xhtml must normalize into the
+    /// matching `<name>.xhtml` and parse as XML.
+    #[test]
+    fn normalize_corpus() {
+        let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/transformer/testdata/xhtml");
+        let mut checked = 0;
+
+        for dir_entry in fs::read_dir(&corpus).expect("failed to read test corpus") {
+            let path = dir_entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "html") {
+                continue;
+            }
+
+            let html = fs::read_to_string(&path).unwrap();
+            let expected = fs::read_to_string(path.with_extension("xhtml"))
+                .unwrap_or_else(|_| panic!("missing expected output for {}", path.display()));
+            let xhtml = normalize(&html);
+            assert_eq!(xhtml.trim_end(), expected.trim_end(), "{}", path.display());
+            assert_well_formed(&xhtml);
+            checked += 1;
+        }
+
+        assert!(
+            checked > 0,
+            "no test snippets found in {}",
+            corpus.display()
+        );
+    }
+
+    fn assert_well_formed(xhtml: &str) {
+        let document = format!("<body>{xhtml}</body>");
+        let mut reader = quick_xml::Reader::from_str(&document);
+        reader.config_mut().check_end_names = true;
+        loop {
+            match reader.read_event() {
+                Ok(quick_xml::events::Event::Eof) => break,
+                // Unescaping fails on entities XML does not know about.
+                Ok(quick_xml::events::Event::Text(text)) => {
+                    if let Err(err) = text.unescape() {
+                        panic!("undefined entity: {err}\n{xhtml}");
+                    }
+                }
+                Ok(quick_xml::events::Event::Start(tag) | quick_xml::events::Event::Empty(tag)) => {
+                    for attr in tag.attributes() {
+                        let attr =
+                            attr.unwrap_or_else(|err| panic!("bad attribute: {err}\n{xhtml}"));
+                        if let Err(err) = attr.decode_and_unescape_value(reader.decoder()) {
+                            panic!("undefined entity: {err}\n{xhtml}");
+                        }
+                    }
+                }
+                Ok(_) => (),
+                Err(err) => panic!("not well-formed XML: {err}\n{xhtml}"),
+            }
+        }
+    }
+}